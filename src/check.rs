@@ -0,0 +1,418 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Consistency checks of the memory map tables
+//!
+//! The regions should cover the 32-bit physical address space without gaps
+//! or overlaps, each subregion should lie inside a single region, and each
+//! register block inside the subregion it names. Short names should be unique
+//! within a table, as they make up the annotations.
+//!
+//! Issues are either errors, which make lookups ambiguous or wrong, or
+//! warnings, such as gaps left for the lookup to not name.
+//!
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::map::{REGIONS, SEGMENTS, SEGMENTS_64, SUBREGIONS};
+use crate::mapfile::{built_in, MapRegion, MemoryMap};
+use crate::registers::REGISTER_BLOCKS;
+
+/// How bad an issue is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// What an issue is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The range ends before it starts
+    InvalidRange,
+    /// A built-in table is not sorted by address
+    Unsorted,
+    /// Addresses no entry covers
+    Gap,
+    /// Two entries overlap without one holding the other
+    Overlap,
+    /// Short names shared by entries, or differing only in case
+    DuplicateName,
+    /// A subregion outside of any single region, or a register block outside
+    /// of its subregion
+    Orphan,
+}
+
+impl fmt::Display for IssueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IssueKind::InvalidRange => write!(f, "invalid range"),
+            IssueKind::Unsorted => write!(f, "unsorted"),
+            IssueKind::Gap => write!(f, "gap"),
+            IssueKind::Overlap => write!(f, "overlap"),
+            IssueKind::DuplicateName => write!(f, "duplicate name"),
+            IssueKind::Orphan => write!(f, "orphan"),
+        }
+    }
+}
+
+/// An inconsistency found in a table.
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+    /// `segments`, `regions`, `subregions` or `registers`
+    pub table: &'static str,
+    /// First address concerned
+    pub start: u64,
+    /// Last address concerned
+    pub end: u64,
+    pub message: String,
+}
+
+impl Issue {
+    fn new(severity: Severity, kind: IssueKind, table: &'static str, start: u64, end: u64, message: String) -> Issue {
+        Issue { severity, kind, table, start, end, message }
+    }
+}
+
+/// Names an entry in messages, e.g. `PIFM (PIF RAM) 0x1FC007C0-0x1FC007FF`.
+fn describe(entry: &MapRegion) -> String {
+    format!("{} ({}) 0x{:08X}-0x{:08X}", entry.2, entry.3, entry.0, entry.1)
+}
+
+/// Entries ending before they start.
+fn check_ranges(table: &'static str, entries: &[MapRegion], issues: &mut Vec<Issue>) {
+    for entry in entries.iter().filter(|entry| entry.1 < entry.0) {
+        issues.push(Issue::new(
+            Severity::Error,
+            IssueKind::InvalidRange,
+            table,
+            entry.0 as u64,
+            entry.1 as u64,
+            format!("{} ends before it starts", describe(entry)),
+        ));
+    }
+}
+
+/// A built-in table whose entries don't follow each other by start address.
+fn check_sorted(table: &'static str, starts: &[(u64, &str)], issues: &mut Vec<Issue>) {
+    for pair in starts.windows(2) {
+        if pair[1].0 < pair[0].0 {
+            issues.push(Issue::new(
+                Severity::Warning,
+                IssueKind::Unsorted,
+                table,
+                pair[1].0,
+                pair[1].0,
+                format!("{} is listed after {}, which starts later", pair[1].1, pair[0].1),
+            ));
+        }
+    }
+}
+
+/// Partial overlaps between entries of a table. Entries nested in one
+/// another, as map files add them, are fine.
+fn check_overlaps(table: &'static str, entries: &[MapRegion], issues: &mut Vec<Issue>) {
+    for (position, a) in entries.iter().enumerate() {
+        for b in entries[position + 1..].iter() {
+            let overlaps: bool = a.0 <= b.1 && b.0 <= a.1;
+            let nested: bool = (a.0 <= b.0 && b.1 <= a.1) || (b.0 <= a.0 && a.1 <= b.1);
+            if overlaps && !nested {
+                issues.push(Issue::new(
+                    Severity::Error,
+                    IssueKind::Overlap,
+                    table,
+                    a.0.max(b.0) as u64,
+                    a.1.min(b.1) as u64,
+                    format!("{} overlaps {}", describe(a), describe(b)),
+                ));
+            }
+        }
+    }
+}
+
+/// Ranges from `start` to `end` that none of the entries cover.
+fn uncovered(entries: &[&MapRegion], start: u32, end: u32) -> Vec<(u32, u32)> {
+    let mut sorted: Vec<&MapRegion> = entries.to_vec();
+    sorted.sort_by_key(|entry| entry.0);
+
+    let mut gaps: Vec<(u32, u32)> = Vec::new();
+    // Next address to be covered, None once past `end`
+    let mut cursor: Option<u32> = Some(start);
+    for entry in sorted {
+        let Some(next) = cursor else {
+            break;
+        };
+        if entry.0 > next {
+            gaps.push((next, entry.0 - 1));
+        }
+        if entry.1 >= next {
+            cursor = entry.1.checked_add(1).filter(|next| *next <= end);
+        }
+    }
+    if let Some(next) = cursor {
+        gaps.push((next, end));
+    }
+    gaps
+}
+
+/// Short names used more than once in a table, or differing only in case.
+fn check_names(table: &'static str, entries: &[MapRegion], issues: &mut Vec<Issue>) {
+    let mut by_name: BTreeMap<String, Vec<&MapRegion>> = BTreeMap::new();
+    for entry in entries.iter() {
+        by_name.entry(entry.2.to_uppercase()).or_default().push(entry);
+    }
+
+    for same in by_name.values().filter(|same| same.len() > 1) {
+        let exact: bool = same.iter().all(|entry| entry.2 == same[0].2);
+        let names: Vec<String> = same.iter().map(|entry| describe(entry)).collect();
+        issues.push(Issue::new(
+            Severity::Warning,
+            IssueKind::DuplicateName,
+            table,
+            same.iter().map(|entry| entry.0).min().unwrap_or(0) as u64,
+            same.iter().map(|entry| entry.1).max().unwrap_or(0) as u64,
+            if exact {
+                format!("short name {} is shared by {}", same[0].2, names.join(", "))
+            } else {
+                format!("short names differ only in case: {}", names.join(", "))
+            },
+        ));
+    }
+}
+
+/// Check the regions and subregions of a memory map, the register blocks
+/// against its subregions, and the order of the built-in tables.
+pub fn check_map(map: &MemoryMap) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+
+    // The built-in tables are kept sorted so that dumps list them in order
+    let segments: Vec<(u64, &str)> = SEGMENTS.iter().map(|segment| (segment.0 as u64, segment.3))
+        .chain(SEGMENTS_64.iter().map(|segment| (segment.0, segment.3)))
+        .collect();
+    check_sorted("segments", &segments[..SEGMENTS.len()], &mut issues);
+    check_sorted("segments", &segments[SEGMENTS.len()..], &mut issues);
+    let regions: Vec<(u64, &str)> = REGIONS.iter().map(|region| (region.0 as u64, region.2)).collect();
+    check_sorted("regions", &regions, &mut issues);
+    let subregions: Vec<(u64, &str)> = SUBREGIONS.iter().map(|subregion| (subregion.0 as u64, subregion.2)).collect();
+    check_sorted("subregions", &subregions, &mut issues);
+    let blocks: Vec<(u64, &str)> = REGISTER_BLOCKS.iter()
+        .map(|block| (block.start as u64, block.registers.first().map_or(block.subregion, |register| register.name)))
+        .collect();
+    check_sorted("registers", &blocks, &mut issues);
+
+    for segment in SEGMENTS_64.iter().filter(|segment| segment.1 < segment.0) {
+        issues.push(Issue::new(
+            Severity::Error,
+            IssueKind::InvalidRange,
+            "segments",
+            segment.0,
+            segment.1,
+            format!("{} ends before it starts", segment.3),
+        ));
+    }
+    let segments: Vec<MapRegion> = SEGMENTS.iter().map(built_in).collect();
+    check_ranges("segments", &segments, &mut issues);
+    check_overlaps("segments", &segments, &mut issues);
+    let segment_refs: Vec<&MapRegion> = segments.iter().collect();
+    for (start, end) in uncovered(&segment_refs, 0, u32::MAX) {
+        issues.push(Issue::new(
+            Severity::Warning,
+            IssueKind::Gap,
+            "segments",
+            start as u64,
+            end as u64,
+            format!("no segment covers 0x{:08X}-0x{:08X}", start, end),
+        ));
+    }
+
+    check_ranges("regions", &map.regions, &mut issues);
+    check_ranges("subregions", &map.subregions, &mut issues);
+    check_overlaps("regions", &map.regions, &mut issues);
+    check_overlaps("subregions", &map.subregions, &mut issues);
+    check_names("regions", &map.regions, &mut issues);
+    check_names("subregions", &map.subregions, &mut issues);
+
+    let regions: Vec<&MapRegion> = map.regions.iter().collect();
+    for (start, end) in uncovered(&regions, 0, u32::MAX) {
+        issues.push(Issue::new(
+            Severity::Warning,
+            IssueKind::Gap,
+            "regions",
+            start as u64,
+            end as u64,
+            format!("no region covers 0x{:08X}-0x{:08X}", start, end),
+        ));
+    }
+
+    for subregion in map.subregions.iter() {
+        let inside: bool = map.regions.iter().any(|region| region.0 <= subregion.0 && subregion.1 <= region.1);
+        if !inside {
+            issues.push(Issue::new(
+                Severity::Error,
+                IssueKind::Orphan,
+                "subregions",
+                subregion.0 as u64,
+                subregion.1 as u64,
+                format!("{} doesn't lie inside a single region", describe(subregion)),
+            ));
+        }
+    }
+
+    // Gaps between the subregions of each region, leaving out regions
+    // without subregions at all
+    for region in map.regions.iter() {
+        let held: Vec<&MapRegion> = map.subregions.iter()
+            .filter(|subregion| subregion.0 <= region.1 && region.0 <= subregion.1)
+            .collect();
+        if held.is_empty() {
+            continue;
+        }
+        for (start, end) in uncovered(&held, region.0, region.1) {
+            issues.push(Issue::new(
+                Severity::Warning,
+                IssueKind::Gap,
+                "subregions",
+                start as u64,
+                end as u64,
+                format!("no subregion of {} covers 0x{:08X}-0x{:08X}", describe(region), start, end),
+            ));
+        }
+    }
+
+    for block in REGISTER_BLOCKS.iter() {
+        let name: &str = block.registers.first().map_or(block.subregion, |register| register.name);
+        let subregion = map.subregions.iter()
+            .find(|subregion| subregion.2 == block.subregion && subregion.0 <= block.start && block.end <= subregion.1);
+        if subregion.is_none() {
+            issues.push(Issue::new(
+                Severity::Error,
+                IssueKind::Orphan,
+                "registers",
+                block.start as u64,
+                block.end as u64,
+                format!(
+                    "the {} block 0x{:08X}-0x{:08X} doesn't lie inside a {} subregion",
+                    name, block.start, block.end, block.subregion,
+                ),
+            ));
+        }
+        for register in block.registers.iter().filter(|register| register.offset + register.width / 8 > block.stride) {
+            issues.push(Issue::new(
+                Severity::Error,
+                IssueKind::Overlap,
+                "registers",
+                (block.start + register.offset) as u64,
+                (block.start + register.offset + register.width / 8 - 1) as u64,
+                format!("{} lies past the 0x{:X} byte stride of its block", register.name, block.stride),
+            ));
+        }
+    }
+
+    issues.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.start.cmp(&b.start)));
+    issues
+}
+
+/// An issue as a JSON object with the fields `severity`, `kind`, `table`,
+/// `start`, `end` and `message`.
+pub fn issue_to_json(issue: &Issue) -> Value {
+    json!({
+        "severity": issue.severity.to_string(),
+        "kind": issue.kind.to_string(),
+        "table": issue.table,
+        "start": format_address(issue.start),
+        "end": format_address(issue.end),
+        "message": issue.message,
+    })
+}
+
+/// Column names of [`issue_to_csv`].
+pub const ISSUE_CSV_HEADER: &[&str] = &["severity", "kind", "table", "start", "end", "message"];
+
+/// An issue as CSV fields, see [`ISSUE_CSV_HEADER`].
+pub fn issue_to_csv(issue: &Issue) -> Vec<String> {
+    vec![
+        issue.severity.to_string(),
+        issue.kind.to_string(),
+        issue.table.to_string(),
+        format_address(issue.start),
+        format_address(issue.end),
+        issue.message.clone(),
+    ]
+}
+
+/// Addresses with 8 digits if they fit in 32 bits, and 16 digits otherwise.
+fn format_address(address: u64) -> String {
+    if address <= u32::MAX as u64 {
+        format!("0x{:08X}", address)
+    } else {
+        format!("0x{:016X}", address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_map_checks_clean() {
+        let issues: Vec<Issue> = check_map(&MemoryMap::default());
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+
+    fn kinds(issues: &[Issue]) -> Vec<(Severity, IssueKind, &'static str)> {
+        issues.iter().map(|issue| (issue.severity, issue.kind, issue.table)).collect()
+    }
+
+    #[test]
+    fn reports_names_differing_only_in_case() {
+        let mut map: MemoryMap = MemoryMap::default();
+        map.subregions.insert(0, (0x00100000, 0x001FFFFF, "rdrm".into(), "Heap".into()));
+        let issues: Vec<Issue> = check_map(&map);
+        assert_eq!(kinds(&issues), vec![(Severity::Warning, IssueKind::DuplicateName, "subregions")]);
+        assert_eq!(
+            issues[0].message,
+            "short names differ only in case: rdrm (Heap) 0x00100000-0x001FFFFF, \
+             RDRM (RDRAM memory-space) 0x00000000-0x03EFFFFF",
+        );
+    }
+
+    #[test]
+    fn reports_overlaps_orphans_and_gaps() {
+        let mut map: MemoryMap = MemoryMap::default();
+        map.regions.retain(|region| region.2 != "G");
+        map.regions.insert(0, (0x03F00000, 0x040FFFFF, "X".into(), "Straddling".into()));
+        let issues: Vec<Issue> = check_map(&map);
+
+        let errors: Vec<&Issue> = issues.iter().filter(|issue| issue.severity == Severity::Error).collect();
+        assert!(errors.iter().any(|issue| issue.kind == IssueKind::Overlap && issue.table == "regions"));
+        assert!(errors.iter().any(|issue| {
+            issue.kind == IssueKind::Orphan && issue.message.starts_with("InVI (Video Interface)")
+        }));
+        assert!(issues.iter().any(|issue| {
+            issue.kind == IssueKind::Gap && issue.table == "regions" && (issue.start, issue.end) == (0x04100000, 0x04FFFFFF)
+        }));
+        assert!(issues.iter().position(|issue| issue.severity == Severity::Warning) >= Some(errors.len()));
+    }
+
+    #[test]
+    fn finds_the_uncovered_ranges() {
+        let entries: [MapRegion; 2] = [(0x10, 0x1F, "B".into(), "B".into()), (0x00, 0x07, "A".into(), "A".into())];
+        let refs: Vec<&MapRegion> = entries.iter().collect();
+        assert_eq!(uncovered(&refs, 0, 0xFF), vec![(0x08, 0x0F), (0x20, 0xFF)]);
+        assert_eq!(uncovered(&refs, 0x10, 0x1F), vec![]);
+        assert_eq!(uncovered(&[], 0, u32::MAX), vec![(0, u32::MAX)]);
+    }
+}
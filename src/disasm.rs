@@ -0,0 +1,656 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Disassembly of VR4300 code from ROM files and memory dumps
+//!
+//! Covers the MIPS III instruction set of the VR4300 with its COP0 and FPU
+//! instructions. Registers are written with their ABI names as in Ares
+//! traces, and immediates in hexadecimal.
+//!
+//! Branch and jump targets are annotated with the memory map and symbols, as
+//! are the addresses built by a `lui` followed by an `addiu`, `ori`, load or
+//! store using the same register, e.g. `lui at,0xa440` and `sw t0,0x4(at)`.
+//!
+
+use std::rc::Rc;
+
+use serde_json::{json, Value};
+
+use crate::map::{
+    address_location_to_string,
+    format_virtual_address,
+    sign_extend,
+    AddressLocation,
+};
+use crate::mapfile::MemoryMap;
+use crate::range::offset_address;
+use crate::rsp::{format_rsp_address, rsp_to_virtual};
+use crate::symbols::SymbolTable;
+use crate::tlb::Tlb;
+
+/// ABI names of the general purpose registers.
+pub static GPR_NAMES: [&str; 32] = [
+    "zero", "at", "v0", "v1", "a0", "a1", "a2", "a3",
+    "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7",
+    "t8", "t9", "k0", "k1", "gp", "sp", "fp", "ra",
+];
+
+/// Names of the COP0 registers, by number.
+pub static COP0_NAMES: [&str; 32] = [
+    "Index", "Random", "EntryLo0", "EntryLo1", "Context", "PageMask", "Wired", "$7",
+    "BadVAddr", "Count", "EntryHi", "Compare", "Status", "Cause", "EPC", "PRId",
+    "Config", "LLAddr", "WatchLo", "WatchHi", "XContext", "$21", "$22", "$23",
+    "$24", "$25", "PErr", "CacheErr", "TagLo", "TagHi", "ErrorEPC", "$31",
+];
+
+/// Conditions of the FPU compare instructions, by the low 4 bits of the
+/// function field.
+static FPU_CONDITIONS: [&str; 16] = [
+    "f", "un", "eq", "ueq", "olt", "ult", "ole", "ule",
+    "sf", "ngle", "seq", "ngl", "lt", "nge", "le", "ngt",
+];
+
+/// Processor an instruction runs on, which sets the address space of its
+/// address, target and reference.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Processor {
+    /// The VR4300, addressing virtual memory
+    #[default]
+    Cpu,
+    /// The RSP, addressing its DMEM and IMEM, see [`crate::rsp`]
+    Rsp,
+}
+
+/// A disassembled instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub processor: Processor,
+    /// Address of the instruction, virtual for the CPU
+    pub address: u64,
+    pub word: u32,
+    pub mnemonic: String,
+    /// Operands separated by commas, empty if there are none
+    pub operands: String,
+    /// Target of a branch or of a jump to an immediate address
+    pub target: Option<u64>,
+    /// Address built by a `lui` and this instruction
+    pub reference: Option<u64>,
+}
+
+impl Instruction {
+    /// The instruction as written in assembly, e.g. `addiu   sp,sp,-0x18`.
+    pub fn text(&self) -> String {
+        if self.operands.is_empty() {
+            self.mnemonic.clone()
+        } else {
+            format!("{:<7} {}", self.mnemonic, self.operands)
+        }
+    }
+
+    /// Formats an address of the address space of the processor.
+    pub fn format_address(&self, address: u64) -> String {
+        match self.processor {
+            Processor::Cpu => format_virtual_address(address),
+            Processor::Rsp => format_rsp_address(address as u32),
+        }
+    }
+
+    /// What the target or the address built by the instruction is, see
+    /// [`describe_reference`]. The address built is shown first as it isn't
+    /// among the operands. RSP addresses are described at the CPU address
+    /// reaching them.
+    pub fn comment(&self, map: &MemoryMap, symbols: &SymbolTable, tlb: &Tlb) -> Option<String> {
+        let describe = |address: u64| match self.processor {
+            Processor::Cpu => describe_reference(address, map, symbols, tlb),
+            Processor::Rsp => describe_reference(rsp_to_virtual(address as u32), map, symbols, tlb),
+        };
+        if let Some(target) = self.target {
+            return Some(describe(target));
+        }
+        self.reference.map(|reference| format!("{} {}", self.format_address(reference), describe(reference)))
+    }
+}
+
+/// Short description of an address an instruction refers to: the register
+/// or symbol at it followed by its annotation in parentheses, e.g.
+/// `VI_ORIGIN (1G.InVI)`, or only the annotation.
+pub fn describe_reference(address: u64, map: &MemoryMap, symbols: &SymbolTable, tlb: &Tlb) -> String {
+    let location: AddressLocation = map.lookup(address, tlb);
+    let annotation: String = address_location_to_string(&location);
+    let name: Option<String> = location.register
+        .map(|register| register.name.to_string())
+        .or_else(|| symbols.describe(address));
+    match name {
+        Some(name) => format!("{} ({})", name, annotation),
+        None => annotation,
+    }
+}
+
+/// Fields of an instruction word.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fields {
+    pub word: u32,
+    pub op: u32,
+    pub rs: usize,
+    pub rt: usize,
+    pub rd: usize,
+    pub sa: u32,
+    pub funct: u32,
+    pub imm: u16,
+}
+
+impl Fields {
+    pub fn new(word: u32) -> Fields {
+        Fields {
+            word,
+            op: word >> 26,
+            rs: (word >> 21 & 0x1F) as usize,
+            rt: (word >> 16 & 0x1F) as usize,
+            rd: (word >> 11 & 0x1F) as usize,
+            sa: word >> 6 & 0x1F,
+            funct: word & 0x3F,
+            imm: word as u16,
+        }
+    }
+
+    pub fn simm(&self) -> i16 {
+        self.imm as i16
+    }
+
+    /// Target of a branch at `address`, relative to its delay slot.
+    pub fn branch_target(&self, address: u64) -> u64 {
+        offset_address(address, 4u64.wrapping_add((self.simm() as i64 as u64) << 2))
+    }
+
+    /// Target of a `j` or `jal` at `address`, within the 256 MiB area of its
+    /// delay slot.
+    pub fn jump_target(&self, address: u64) -> u64 {
+        (offset_address(address, 4) & !0x0FFF_FFFF) | ((self.word & 0x03FF_FFFF) as u64) << 2
+    }
+}
+
+/// Formats a signed immediate as hexadecimal, e.g. `-0x18`.
+pub(crate) fn signed_hex(value: i64) -> String {
+    if value < 0 {
+        format!("-{:#x}", value.unsigned_abs())
+    } else {
+        format!("{:#x}", value)
+    }
+}
+
+/// Formats the memory operand of a load or store, e.g. `0x10(sp)`.
+pub(crate) fn memory_operand(offset: i64, base: usize) -> String {
+    format!("{}({})", signed_hex(offset), GPR_NAMES[base])
+}
+
+/// Mnemonic, operands and target of an instruction.
+pub(crate) struct Decoded {
+    pub mnemonic: String,
+    pub operands: String,
+    pub target: Option<u64>,
+}
+
+impl Decoded {
+    pub fn new<S: Into<String>>(mnemonic: S, operands: String) -> Decoded {
+        Decoded { mnemonic: mnemonic.into(), operands, target: None }
+    }
+
+    pub fn branch<S: Into<String>>(mnemonic: S, operands: String, target: u64) -> Decoded {
+        let operands: String = if operands.is_empty() {
+            format_virtual_address(target)
+        } else {
+            format!("{},{}", operands, format_virtual_address(target))
+        };
+        Decoded { mnemonic: mnemonic.into(), operands, target: Some(target) }
+    }
+
+    /// A word that isn't a valid instruction.
+    pub fn word(word: u32) -> Decoded {
+        Decoded::new(".word", format!("0x{:08X}", word))
+    }
+}
+
+fn decode_special(f: &Fields) -> Decoded {
+    let (rs, rt, rd) = (GPR_NAMES[f.rs], GPR_NAMES[f.rt], GPR_NAMES[f.rd]);
+    let shift = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{},{}", rd, rt, f.sa));
+    let shift_variable = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{},{}", rd, rt, rs));
+    let three = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{},{}", rd, rs, rt));
+    let two = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{}", rs, rt));
+
+    match f.funct {
+        0x00 if f.word == 0 => Decoded::new("nop", String::new()),
+        0x00 => shift("sll"),
+        0x02 => shift("srl"),
+        0x03 => shift("sra"),
+        0x04 => shift_variable("sllv"),
+        0x06 => shift_variable("srlv"),
+        0x07 => shift_variable("srav"),
+        0x08 => Decoded::new("jr", rs.to_string()),
+        0x09 if f.rd == 31 => Decoded::new("jalr", rs.to_string()),
+        0x09 => Decoded::new("jalr", format!("{},{}", rd, rs)),
+        0x0C => Decoded::new("syscall", String::new()),
+        0x0D => Decoded::new("break", String::new()),
+        0x0F => Decoded::new("sync", String::new()),
+        0x10 => Decoded::new("mfhi", rd.to_string()),
+        0x11 => Decoded::new("mthi", rs.to_string()),
+        0x12 => Decoded::new("mflo", rd.to_string()),
+        0x13 => Decoded::new("mtlo", rs.to_string()),
+        0x14 => shift_variable("dsllv"),
+        0x16 => shift_variable("dsrlv"),
+        0x17 => shift_variable("dsrav"),
+        0x18 => two("mult"),
+        0x19 => two("multu"),
+        0x1A => two("div"),
+        0x1B => two("divu"),
+        0x1C => two("dmult"),
+        0x1D => two("dmultu"),
+        0x1E => two("ddiv"),
+        0x1F => two("ddivu"),
+        0x20 => three("add"),
+        0x21 | 0x25 | 0x2D if f.rt == 0 => Decoded::new("move", format!("{},{}", rd, rs)),
+        0x21 => three("addu"),
+        0x22 => three("sub"),
+        0x23 => three("subu"),
+        0x24 => three("and"),
+        0x25 => three("or"),
+        0x26 => three("xor"),
+        0x27 => three("nor"),
+        0x2A => three("slt"),
+        0x2B => three("sltu"),
+        0x2C => three("dadd"),
+        0x2D => three("daddu"),
+        0x2E => three("dsub"),
+        0x2F => three("dsubu"),
+        0x30 => two("tge"),
+        0x31 => two("tgeu"),
+        0x32 => two("tlt"),
+        0x33 => two("tltu"),
+        0x34 => two("teq"),
+        0x36 => two("tne"),
+        0x38 => shift("dsll"),
+        0x3A => shift("dsrl"),
+        0x3B => shift("dsra"),
+        0x3C => shift("dsll32"),
+        0x3E => shift("dsrl32"),
+        0x3F => shift("dsra32"),
+        _ => Decoded::word(f.word),
+    }
+}
+
+fn decode_regimm(f: &Fields, address: u64) -> Decoded {
+    let rs: &str = GPR_NAMES[f.rs];
+    let branch = |mnemonic: &str| Decoded::branch(mnemonic, rs.to_string(), f.branch_target(address));
+    let trap = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{}", rs, signed_hex(f.simm() as i64)));
+
+    match f.rt {
+        0x00 => branch("bltz"),
+        0x01 => branch("bgez"),
+        0x02 => branch("bltzl"),
+        0x03 => branch("bgezl"),
+        0x08 => trap("tgei"),
+        0x09 => trap("tgeiu"),
+        0x0A => trap("tlti"),
+        0x0B => trap("tltiu"),
+        0x0C => trap("teqi"),
+        0x0E => trap("tnei"),
+        0x10 => branch("bltzal"),
+        0x11 if f.rs == 0 => Decoded::branch("bal", String::new(), f.branch_target(address)),
+        0x11 => branch("bgezal"),
+        0x12 => branch("bltzall"),
+        0x13 => branch("bgezall"),
+        _ => Decoded::word(f.word),
+    }
+}
+
+fn decode_cop0(f: &Fields) -> Decoded {
+    let move_cop0 = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{}", GPR_NAMES[f.rt], COP0_NAMES[f.rd]));
+
+    match f.rs {
+        0x00 => move_cop0("mfc0"),
+        0x01 => move_cop0("dmfc0"),
+        0x04 => move_cop0("mtc0"),
+        0x05 => move_cop0("dmtc0"),
+        0x10..=0x1F => match f.funct {
+            0x01 => Decoded::new("tlbr", String::new()),
+            0x02 => Decoded::new("tlbwi", String::new()),
+            0x06 => Decoded::new("tlbwr", String::new()),
+            0x08 => Decoded::new("tlbp", String::new()),
+            0x18 => Decoded::new("eret", String::new()),
+            _ => Decoded::word(f.word),
+        },
+        _ => Decoded::word(f.word),
+    }
+}
+
+fn decode_cop1(f: &Fields, address: u64) -> Decoded {
+    let (fs, ft, fd) = (f.rd, f.rt, f.sa);
+    let move_cop1 = |mnemonic: &str| Decoded::new(mnemonic, format!("{},f{}", GPR_NAMES[f.rt], fs));
+    let move_control = |mnemonic: &str| Decoded::new(mnemonic, format!("{},fcr{}", GPR_NAMES[f.rt], fs));
+
+    let format: &str = match f.rs {
+        0x00 => return move_cop1("mfc1"),
+        0x01 => return move_cop1("dmfc1"),
+        0x02 => return move_control("cfc1"),
+        0x04 => return move_cop1("mtc1"),
+        0x05 => return move_cop1("dmtc1"),
+        0x06 => return move_control("ctc1"),
+        0x08 => {
+            let mnemonic: &str = ["bc1f", "bc1t", "bc1fl", "bc1tl"][f.rt & 3];
+            return Decoded::branch(mnemonic, String::new(), f.branch_target(address));
+        }
+        0x10 => "s",
+        0x11 => "d",
+        0x14 => "w",
+        0x15 => "l",
+        _ => return Decoded::word(f.word),
+    };
+
+    let binary = |operation: &str| Decoded::new(format!("{}.{}", operation, format), format!("f{},f{},f{}", fd, fs, ft));
+    let unary = |operation: &str| Decoded::new(format!("{}.{}", operation, format), format!("f{},f{}", fd, fs));
+
+    match f.funct {
+        0x00 => binary("add"),
+        0x01 => binary("sub"),
+        0x02 => binary("mul"),
+        0x03 => binary("div"),
+        0x04 => unary("sqrt"),
+        0x05 => unary("abs"),
+        0x06 => unary("mov"),
+        0x07 => unary("neg"),
+        0x08 => unary("round.l"),
+        0x09 => unary("trunc.l"),
+        0x0A => unary("ceil.l"),
+        0x0B => unary("floor.l"),
+        0x0C => unary("round.w"),
+        0x0D => unary("trunc.w"),
+        0x0E => unary("ceil.w"),
+        0x0F => unary("floor.w"),
+        0x20 => unary("cvt.s"),
+        0x21 => unary("cvt.d"),
+        0x24 => unary("cvt.w"),
+        0x25 => unary("cvt.l"),
+        0x30..=0x3F => Decoded::new(
+            format!("c.{}.{}", FPU_CONDITIONS[(f.funct & 0xF) as usize], format),
+            format!("f{},f{}", fs, ft),
+        ),
+        _ => Decoded::word(f.word),
+    }
+}
+
+/// Decodes an instruction at a virtual address.
+pub(crate) fn decode(word: u32, address: u64) -> Decoded {
+    let f: Fields = Fields::new(word);
+    let (rs, rt) = (GPR_NAMES[f.rs], GPR_NAMES[f.rt]);
+    let branch_two = |mnemonic: &str| Decoded::branch(mnemonic, format!("{},{}", rs, rt), f.branch_target(address));
+    let branch_one = |mnemonic: &str| Decoded::branch(mnemonic, rs.to_string(), f.branch_target(address));
+    let signed = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{},{}", rt, rs, signed_hex(f.simm() as i64)));
+    let unsigned = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{},{:#x}", rt, rs, f.imm));
+    let memory = |mnemonic: &str| Decoded::new(mnemonic, format!("{},{}", rt, memory_operand(f.simm() as i64, f.rs)));
+    let memory_fpu = |mnemonic: &str| Decoded::new(mnemonic, format!("f{},{}", f.rt, memory_operand(f.simm() as i64, f.rs)));
+
+    match f.op {
+        0x00 => decode_special(&f),
+        0x01 => decode_regimm(&f, address),
+        0x02 => Decoded::branch("j", String::new(), f.jump_target(address)),
+        0x03 => Decoded::branch("jal", String::new(), f.jump_target(address)),
+        0x04 if f.rs == 0 && f.rt == 0 => Decoded::branch("b", String::new(), f.branch_target(address)),
+        0x04 if f.rt == 0 => branch_one("beqz"),
+        0x04 => branch_two("beq"),
+        0x05 if f.rt == 0 => branch_one("bnez"),
+        0x05 => branch_two("bne"),
+        0x06 => branch_one("blez"),
+        0x07 => branch_one("bgtz"),
+        0x08 => signed("addi"),
+        0x09 => signed("addiu"),
+        0x0A => signed("slti"),
+        0x0B => signed("sltiu"),
+        0x0C => unsigned("andi"),
+        0x0D => unsigned("ori"),
+        0x0E => unsigned("xori"),
+        0x0F => Decoded::new("lui", format!("{},{:#x}", rt, f.imm)),
+        0x10 => decode_cop0(&f),
+        0x11 => decode_cop1(&f, address),
+        0x14 => branch_two("beql"),
+        0x15 => branch_two("bnel"),
+        0x16 => branch_one("blezl"),
+        0x17 => branch_one("bgtzl"),
+        0x18 => signed("daddi"),
+        0x19 => signed("daddiu"),
+        0x1A => memory("ldl"),
+        0x1B => memory("ldr"),
+        0x20 => memory("lb"),
+        0x21 => memory("lh"),
+        0x22 => memory("lwl"),
+        0x23 => memory("lw"),
+        0x24 => memory("lbu"),
+        0x25 => memory("lhu"),
+        0x26 => memory("lwr"),
+        0x27 => memory("lwu"),
+        0x28 => memory("sb"),
+        0x29 => memory("sh"),
+        0x2A => memory("swl"),
+        0x2B => memory("sw"),
+        0x2C => memory("sdl"),
+        0x2D => memory("sdr"),
+        0x2E => memory("swr"),
+        0x2F => Decoded::new("cache", format!("{:#x},{}", f.rt, memory_operand(f.simm() as i64, f.rs))),
+        0x30 => memory("ll"),
+        0x31 => memory_fpu("lwc1"),
+        0x34 => memory("lld"),
+        0x35 => memory_fpu("ldc1"),
+        0x37 => memory("ld"),
+        0x38 => memory("sc"),
+        0x39 => memory_fpu("swc1"),
+        0x3C => memory("scd"),
+        0x3D => memory_fpu("sdc1"),
+        0x3F => memory("sd"),
+        _ => Decoded::word(word),
+    }
+}
+
+/// The general purpose register an instruction writes, if any. Writes to
+/// `zero` are reported too, they are harmless to the `lui` tracking.
+fn written_register(f: &Fields) -> Option<usize> {
+    match f.op {
+        0x00 => match f.funct {
+            0x08 | 0x0C..=0x0F | 0x11 | 0x13 | 0x18..=0x1F | 0x30..=0x36 => None,
+            _ => Some(f.rd),
+        },
+        0x01 if f.rt & 0x10 != 0 => Some(31),
+        0x03 => Some(31),
+        0x08..=0x0F | 0x18..=0x1B | 0x20..=0x27 | 0x30 | 0x34 | 0x37 | 0x38 | 0x3C => Some(f.rt),
+        0x10 | 0x11 if f.rs <= 0x02 => Some(f.rt),
+        _ => None,
+    }
+}
+
+/// Address built from the upper half loaded into a register by a `lui` and
+/// the immediate of an `addiu`, `ori`, load or store using that register.
+fn reference(f: &Fields, upper: &[Option<u64>; 32]) -> Option<u64> {
+    let base: u64 = upper[f.rs]?;
+    match f.op {
+        0x08 | 0x09 | 0x18 | 0x19 | 0x1A | 0x1B | 0x20..=0x3F => {
+            Some(sign_extend((base as u32).wrapping_add(f.simm() as i32 as u32)))
+        }
+        0x0D => Some(base | f.imm as u64),
+        _ => None,
+    }
+}
+
+/// Disassembles consecutive instruction words, the first one being at the
+/// virtual address given.
+pub fn disassemble(words: &[u32], address: u64) -> Vec<Instruction> {
+    let mut upper: [Option<u64>; 32] = [None; 32];
+    let mut instructions: Vec<Instruction> = Vec::with_capacity(words.len());
+
+    for (index, &word) in words.iter().enumerate() {
+        let address: u64 = offset_address(address, index as u64 * 4);
+        let f: Fields = Fields::new(word);
+        let decoded: Decoded = decode(word, address);
+        let reference: Option<u64> = reference(&f, &upper);
+
+        if let Some(register) = written_register(&f) {
+            upper[register] = None;
+        }
+        if f.op == 0x0F && f.rt != 0 {
+            upper[f.rt] = Some(sign_extend((f.imm as u32) << 16));
+        }
+
+        instructions.push(Instruction {
+            processor: Processor::Cpu,
+            address,
+            word,
+            mnemonic: decoded.mnemonic,
+            operands: decoded.operands,
+            target: decoded.target,
+            reference,
+        });
+    }
+
+    instructions
+}
+
+/// Memory contents known from dumps and ROM files, by physical address.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryImage {
+    pieces: Vec<(u32, Rc<[u8]>)>,
+}
+
+impl MemoryImage {
+    /// Adds the contents of memory starting at a physical address. Later
+    /// pieces take precedence where they overlap.
+    pub fn add(&mut self, physical_address: u32, data: Rc<[u8]>) {
+        self.pieces.push((physical_address, data));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    /// The big-endian word at a physical address, if a piece holds it.
+    pub fn read_word(&self, physical_address: u32) -> Option<u32> {
+        self.pieces.iter().rev().find_map(|(start, data)| {
+            let offset: usize = physical_address.checked_sub(*start)? as usize;
+            let bytes: &[u8] = data.get(offset..offset.checked_add(4)?)?;
+            Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+        })
+    }
+
+    /// Reads up to `count` instruction words from a virtual address, stopping
+    /// at the first one that doesn't translate or isn't held by any piece.
+    pub fn read_words(&self, address: u64, count: usize, map: &MemoryMap, tlb: &Tlb) -> Vec<u32> {
+        (0..count)
+            .map_while(|index| {
+                let location: AddressLocation = map.lookup(offset_address(address, index as u64 * 4), tlb);
+                self.read_word(location.physical_address?)
+            })
+            .collect()
+    }
+}
+
+/// An instruction as a JSON object with the fields `address`, `word`,
+/// `instruction`, `mnemonic`, `operands`, `target` and `reference`, the
+/// address built with a `lui`, and `comment`, the description of either.
+pub fn instruction_to_json(instruction: &Instruction, map: &MemoryMap, symbols: &SymbolTable, tlb: &Tlb) -> Value {
+    json!({
+        "address": instruction.format_address(instruction.address),
+        "word": format!("0x{:08X}", instruction.word),
+        "instruction": instruction.text(),
+        "mnemonic": instruction.mnemonic,
+        "operands": instruction.operands,
+        "target": instruction.target.map(|target| instruction.format_address(target)),
+        "reference": instruction.reference.map(|reference| instruction.format_address(reference)),
+        "comment": instruction.comment(map, symbols, tlb),
+    })
+}
+
+/// Column names of [`instruction_to_csv`].
+pub const INSTRUCTION_CSV_HEADER: &[&str] = &[
+    "address",
+    "word",
+    "mnemonic",
+    "operands",
+    "target",
+    "reference",
+    "comment",
+];
+
+/// An instruction as CSV fields, see [`INSTRUCTION_CSV_HEADER`].
+pub fn instruction_to_csv(instruction: &Instruction, map: &MemoryMap, symbols: &SymbolTable, tlb: &Tlb) -> Vec<String> {
+    vec![
+        instruction.format_address(instruction.address),
+        format!("0x{:08X}", instruction.word),
+        instruction.mnemonic.clone(),
+        instruction.operands.clone(),
+        instruction.target.map_or(String::new(), |target| instruction.format_address(target)),
+        instruction.reference.map_or(String::new(), |reference| instruction.format_address(reference)),
+        instruction.comment(map, symbols, tlb).unwrap_or_default(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(instructions: &[Instruction]) -> Vec<String> {
+        instructions.iter().map(Instruction::text).collect()
+    }
+
+    #[test]
+    fn decodes_operands_and_targets() {
+        let words: [u32; 7] = [0x27BDFFE8, 0x0C000100, 0x00000000, 0x1000FFFF, 0x40886000, 0x46020800, 0x7C000000];
+        let instructions: Vec<Instruction> = disassemble(&words, sign_extend(0x80000400));
+        assert_eq!(texts(&instructions), [
+            "addiu   sp,sp,-0x18",
+            "jal     0x80000400",
+            "nop",
+            "b       0x8000040C",
+            "mtc0    t0,Status",
+            "add.s   f0,f1,f2",
+            ".word   0x7C000000",
+        ]);
+        assert_eq!(instructions[1].target, Some(sign_extend(0x80000400)));
+        assert_eq!(instructions[3].address, sign_extend(0x8000040C));
+        assert_eq!(instructions[3].target, Some(sign_extend(0x8000040C)));
+    }
+
+    #[test]
+    fn tracks_addresses_built_with_lui() {
+        let words: [u32; 6] = [
+            0x3C01A440, // lui   at,0xa440
+            0xAC280004, // sw    t0,0x4(at)
+            0x3C088030, // lui   t0,0x8030
+            0x35080010, // ori   t0,t0,0x10
+            0x24210008, // addiu at,at,0x8
+            0xAC280010, // sw    t0,0x10(at)
+        ];
+        let instructions: Vec<Instruction> = disassemble(&words, sign_extend(0x80000400));
+        let references: Vec<Option<u64>> = instructions.iter().map(|instruction| instruction.reference).collect();
+        assert_eq!(references, [
+            None,
+            Some(sign_extend(0xA4400004)),
+            None,
+            Some(sign_extend(0x80300010)),
+            Some(sign_extend(0xA4400008)),
+            None,
+        ]);
+
+        let symbols: SymbolTable = SymbolTable::default();
+        let tlb: Tlb = Tlb::default();
+        assert_eq!(instructions[1].comment(&MemoryMap::default(), &symbols, &tlb).as_deref(), Some("0xA4400004 VI_ORIGIN (1G.InVI)"));
+        assert_eq!(instructions[0].comment(&MemoryMap::default(), &symbols, &tlb), None);
+    }
+
+    #[test]
+    fn reads_words_from_memory_images() {
+        let mut image: MemoryImage = MemoryImage::default();
+        assert!(image.is_empty());
+        image.add(0x00000400, Rc::from(&[0x27, 0xBD, 0xFF, 0xE8, 0x00, 0x00, 0x00, 0x00, 0x03][..]));
+        image.add(0x00000404, Rc::from(&[0x03, 0xE0, 0x00, 0x08][..]));
+
+        assert_eq!(image.read_word(0x00000404), Some(0x03E00008));
+        assert_eq!(image.read_word(0x00000406), None);
+        assert_eq!(image.read_words(sign_extend(0x80000400), 4, &MemoryMap::default(), &Tlb::default()), [0x27BDFFE8, 0x03E00008]);
+        assert!(image.read_words(0x00000400, 1, &MemoryMap::default(), &Tlb::default()).is_empty());
+    }
+}
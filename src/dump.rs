@@ -0,0 +1,447 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Rendering of the whole memory map
+//!
+//! The segment, region, subregion and register tables are flattened into a
+//! list of entries in tree order, i.e. each region followed by its
+//! subregions, each followed by its registers, which is rendered as a
+//! terminal tree, Markdown tables, or a C header of base addresses.
+//!
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use serde_json::{json, Value};
+
+use crate::map::{SEGMENTS, SEGMENTS_64};
+use crate::mapfile::{MapRegion, MemoryMap};
+use crate::registers::{Register, REGISTER_BLOCKS};
+
+/// Which table an entry of the memory map comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapEntryKind {
+    Segment,
+    Region,
+    Subregion,
+    Register,
+}
+
+impl MapEntryKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MapEntryKind::Segment => "segment",
+            MapEntryKind::Region => "region",
+            MapEntryKind::Subregion => "subregion",
+            MapEntryKind::Register => "register",
+        }
+    }
+}
+
+/// An entry of the memory map. Segments are virtual address ranges, the
+/// other entries physical ones.
+#[derive(Debug, Clone)]
+pub struct MapEntry {
+    pub kind: MapEntryKind,
+    pub short_name: Cow<'static, str>,
+    pub long_name: Cow<'static, str>,
+    /// First address
+    pub start: u64,
+    /// Last address
+    pub end: u64,
+    /// Short name of the region holding a subregion, or of the subregion
+    /// holding a register
+    pub parent: Option<Cow<'static, str>>,
+    /// Nesting level in the tree, 0 for segments and regions
+    pub depth: usize,
+    pub register: Option<&'static Register>,
+}
+
+impl MapEntry {
+    /// Formats the start or end address with 8 digits if it fits in 32 bits,
+    /// and 16 digits otherwise.
+    fn format_address(&self, address: u64) -> String {
+        if self.end <= u32::MAX as u64 {
+            format!("0x{:08X}", address)
+        } else {
+            format!("0x{:016X}", address)
+        }
+    }
+
+    pub fn start_string(&self) -> String {
+        self.format_address(self.start)
+    }
+
+    pub fn end_string(&self) -> String {
+        self.format_address(self.end)
+    }
+}
+
+/// The entries of the memory map in tree order: the 32-bit and 64-bit
+/// segments, then each region followed by its subregions, each followed by
+/// the registers of the register blocks it holds. Registers are listed at
+/// their first address, not at their mirrors. Regions and subregions are
+/// those of the built-in tables, see [`MemoryMap::entries`] for another map.
+pub fn map_entries() -> Vec<MapEntry> {
+    MemoryMap::default().entries()
+}
+
+impl MemoryMap {
+    /// Same as [`map_entries`], listing the regions and subregions of this map.
+    pub fn entries(&self) -> Vec<MapEntry> {
+        let mut entries: Vec<MapEntry> = Vec::new();
+
+        for segment in SEGMENTS.iter() {
+            entries.push(MapEntry {
+                kind: MapEntryKind::Segment,
+                short_name: Cow::Borrowed(segment.2),
+                long_name: Cow::Borrowed(segment.3),
+                start: segment.0 as u64,
+                end: segment.1 as u64,
+                parent: None,
+                depth: 0,
+                register: None,
+            });
+        }
+        for segment in SEGMENTS_64.iter() {
+            entries.push(MapEntry {
+                kind: MapEntryKind::Segment,
+                short_name: Cow::Borrowed(segment.2),
+                long_name: Cow::Borrowed(segment.3),
+                start: segment.0,
+                end: segment.1,
+                parent: None,
+                depth: 0,
+                register: None,
+            });
+        }
+
+        // Regions in address order, outermost first, each holding the subregions
+        // the lookup finds it for
+        let mut regions: Vec<&MapRegion> = self.regions.iter().collect();
+        regions.sort_by_key(|region| (region.0, Reverse(region.1)));
+        let mut subregions: Vec<&MapRegion> = self.subregions.iter().collect();
+        subregions.sort_by_key(|subregion| (subregion.0, Reverse(subregion.1)));
+
+        for region in regions {
+            entries.push(MapEntry {
+                kind: MapEntryKind::Region,
+                short_name: region.2.clone(),
+                long_name: region.3.clone(),
+                start: region.0 as u64,
+                end: region.1 as u64,
+                parent: None,
+                depth: 0,
+                register: None,
+            });
+
+            // Subregions belong to the region holding their start
+            let held = subregions.iter().filter(|sub| self.region(sub.0) == Some(region));
+            for subregion in held {
+                entries.push(MapEntry {
+                    kind: MapEntryKind::Subregion,
+                    short_name: subregion.2.clone(),
+                    long_name: subregion.3.clone(),
+                    start: subregion.0 as u64,
+                    end: subregion.1 as u64,
+                    parent: Some(region.2.clone()),
+                    depth: 1,
+                    register: None,
+                });
+
+                let blocks = REGISTER_BLOCKS.iter()
+                    .filter(|block| block.subregion == subregion.2 && subregion.0 <= block.start && block.start <= subregion.1);
+                for block in blocks {
+                    for register in block.registers.iter() {
+                        let start: u32 = block.start + register.offset;
+                        entries.push(MapEntry {
+                            kind: MapEntryKind::Register,
+                            short_name: Cow::Borrowed(register.name),
+                            long_name: Cow::Borrowed(register.description),
+                            start: start as u64,
+                            end: (start + register.width / 8 - 1) as u64,
+                            parent: Some(subregion.2.clone()),
+                            depth: 2,
+                            register: Some(register),
+                        });
+                    }
+                }
+            }
+        }
+
+        entries
+    }
+}
+
+/// Renders the entries as a tree, under a `Segments` and a `Physical` root.
+pub fn render_tree(entries: &[MapEntry]) -> String {
+    let mut text: String = String::new();
+
+    let roots: [(&str, Vec<&MapEntry>); 2] = [
+        ("Segments", entries.iter().filter(|entry| entry.kind == MapEntryKind::Segment).collect()),
+        ("Physical", entries.iter().filter(|entry| entry.kind != MapEntryKind::Segment).collect()),
+    ];
+    for (root, entries) in roots.iter() {
+        let _ = writeln!(text, "{}", root);
+        let short_width: usize = entries.iter().map(|entry| entry.short_name.len()).max().unwrap_or(0);
+        let range_width: usize = entries.iter()
+            .map(|entry| entry.start_string().len() + entry.end_string().len() + 1)
+            .max()
+            .unwrap_or(0);
+
+        // Whether the ancestor at each depth is the last of its siblings
+        let mut last_at_depth: Vec<bool> = Vec::new();
+        for (position, entry) in entries.iter().enumerate() {
+            let is_last: bool = entries[position + 1..].iter()
+                .take_while(|next| next.depth >= entry.depth)
+                .all(|next| next.depth > entry.depth);
+            last_at_depth.truncate(entry.depth);
+            last_at_depth.push(is_last);
+
+            let mut prefix: String = String::new();
+            for last in last_at_depth[..entry.depth].iter() {
+                prefix.push_str(if *last { "    " } else { "│   " });
+            }
+            prefix.push_str(if is_last { "└── " } else { "├── " });
+
+            let range: String = format!("{}-{}", entry.start_string(), entry.end_string());
+            let _ = writeln!(
+                text,
+                "{}{:<range_width$} {:<short_width$} {}",
+                prefix,
+                range,
+                entry.short_name,
+                entry.long_name,
+            );
+        }
+    }
+
+    text
+}
+
+/// Escapes the pipes of a Markdown table cell.
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+/// Renders the entries as one Markdown table per kind.
+pub fn render_markdown(entries: &[MapEntry]) -> String {
+    let mut text: String = String::from("# N64 memory map\n");
+
+    let sections: [(MapEntryKind, &str, &str); 4] = [
+        (MapEntryKind::Segment, "Segments", "| Start | End | Name | Description |\n|---|---|---|---|"),
+        (MapEntryKind::Region, "Regions", "| Start | End | Name | Description |\n|---|---|---|---|"),
+        (MapEntryKind::Subregion, "Subregions", "| Start | End | Name | Description | Region |\n|---|---|---|---|---|"),
+        (
+            MapEntryKind::Register,
+            "Registers",
+            "| Start | End | Name | Description | Subregion | Access | Width |\n|---|---|---|---|---|---|---|",
+        ),
+    ];
+    for (kind, title, header) in sections {
+        let _ = write!(text, "\n## {}\n\n{}\n", title, header);
+        for entry in entries.iter().filter(|entry| entry.kind == kind) {
+            let _ = write!(
+                text,
+                "| `{}` | `{}` | {} | {} |",
+                entry.start_string(),
+                entry.end_string(),
+                markdown_cell(&entry.short_name),
+                markdown_cell(&entry.long_name),
+            );
+            if let Some(parent) = &entry.parent {
+                let _ = write!(text, " {} |", parent);
+            }
+            if let Some(register) = entry.register {
+                let _ = write!(text, " {} | {} |", register.access, register.width);
+            }
+            text.push('\n');
+        }
+    }
+
+    text
+}
+
+/// Turns a name into an uppercase C identifier.
+fn c_identifier(name: &str) -> String {
+    let identifier: String = name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    identifier.split('_').filter(|part| !part.is_empty()).collect::<Vec<&str>>().join("_")
+}
+
+/// Renders the entries as a C header of `#define`s, e.g. `N64_KSEG0_START`,
+/// `N64_RDRAM_START`, `N64_RDRM_END` and `N64_RDRM_SIZE` for segments,
+/// regions and subregions, and `N64_VI_CTRL` for registers, given as
+/// physical addresses. Subregions sharing a short name are told apart by
+/// their long name, e.g. `N64_PIFR_PIF_RAM_START`.
+pub fn render_c_header(entries: &[MapEntry]) -> String {
+    let mut text: String = String::from(
+        "/* N64 memory map, generated by n64-memory-map */\n\n\
+         #ifndef N64_MEMORY_MAP_H\n\
+         #define N64_MEMORY_MAP_H\n\n\
+         /* Uncached and cached virtual addresses of a physical address */\n\
+         #define N64_KSEG0(physical) ((physical) | 0x80000000)\n\
+         #define N64_KSEG1(physical) ((physical) | 0xA0000000)\n",
+    );
+
+    let mut used: HashSet<String> = HashSet::new();
+    let mut section: Option<MapEntryKind> = None;
+    for entry in entries.iter() {
+        // Subregions and registers are listed under the region holding them
+        let top_level: bool = matches!(entry.kind, MapEntryKind::Segment | MapEntryKind::Region);
+        if top_level && section != Some(entry.kind) {
+            let _ = write!(text, "\n/* {}s */\n", match entry.kind {
+                MapEntryKind::Segment => "Segment",
+                _ => "Region",
+            });
+            section = Some(entry.kind);
+        }
+
+        // The short names of segments and regions are single letters, their
+        // long names the familiar KSEG0, RDRAM and such
+        let mut name: String = if top_level {
+            c_identifier(&entry.long_name)
+        } else {
+            c_identifier(&entry.short_name)
+        };
+        if !top_level && used.contains(&name) {
+            name = format!("{}_{}", name, c_identifier(&entry.long_name));
+        }
+        let mut unique: String = name.clone();
+        let mut count: usize = 2;
+        while used.contains(&unique) {
+            unique = format!("{}_{}", name, count);
+            count += 1;
+        }
+        used.insert(unique.clone());
+
+        let suffix: &str = if entry.end > u32::MAX as u64 { "ULL" } else { "" };
+        if entry.kind == MapEntryKind::Register {
+            let _ = writeln!(
+                text,
+                "#define N64_{:<28} {}{} /* {} */",
+                unique,
+                entry.start_string(),
+                suffix,
+                entry.long_name.replace("*/", "* /"),
+            );
+            continue;
+        }
+
+        let _ = writeln!(text, "#define N64_{:<28} {}{} /* {} */", format!("{}_START", unique), entry.start_string(), suffix, entry.long_name.replace("*/", "* /"));
+        let _ = writeln!(text, "#define N64_{:<28} {}{}", format!("{}_END", unique), entry.end_string(), suffix);
+        let _ = writeln!(text, "#define N64_{:<28} 0x{:X}{}", format!("{}_SIZE", unique), entry.end - entry.start + 1, suffix);
+    }
+
+    text.push_str("\n#endif /* N64_MEMORY_MAP_H */\n");
+    text
+}
+
+/// An entry as a JSON object with the fields `kind`, `short`, `name`,
+/// `start`, `end` and `parent`.
+pub fn map_entry_to_json(entry: &MapEntry) -> Value {
+    json!({
+        "kind": entry.kind.name(),
+        "short": entry.short_name,
+        "name": entry.long_name,
+        "start": entry.start_string(),
+        "end": entry.end_string(),
+        "parent": entry.parent,
+    })
+}
+
+/// Column names of [`map_entry_to_csv`].
+pub const MAP_CSV_HEADER: &[&str] = &["kind", "short", "name", "start", "end", "parent"];
+
+/// An entry as CSV fields, see [`MAP_CSV_HEADER`].
+pub fn map_entry_to_csv(entry: &MapEntry) -> Vec<String> {
+    vec![
+        entry.kind.name().to_string(),
+        entry.short_name.to_string(),
+        entry.long_name.to_string(),
+        entry.start_string(),
+        entry.end_string(),
+        entry.parent.as_deref().unwrap_or_default().to_string(),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(kind: MapEntryKind, short_name: &'static str, long_name: &'static str, start: u64, end: u64) -> MapEntry {
+        MapEntry { kind, short_name: short_name.into(), long_name: long_name.into(), start, end, parent: None, depth: 0, register: None }
+    }
+
+    #[test]
+    fn lists_registers_under_their_subregion() {
+        let entries: Vec<MapEntry> = map_entries();
+        let position: usize = entries.iter().position(|entry| entry.short_name == "InVI").unwrap();
+        assert_eq!(entries[position].kind, MapEntryKind::Subregion);
+        assert_eq!(entries[position].parent.as_deref(), Some("G"));
+        assert_eq!(entries[position + 1].short_name, "VI_CTRL");
+        assert_eq!(entries[position + 1].parent.as_deref(), Some("InVI"));
+        assert_eq!((entries[position + 1].start, entries[position + 1].end), (0x04400000, 0x04400003));
+        assert_eq!(entries[0].kind, MapEntryKind::Segment);
+    }
+
+    #[test]
+    fn renders_the_tree_markdown_and_c_header() {
+        let entries: Vec<MapEntry> = map_entries();
+
+        let tree: String = render_tree(&entries);
+        assert!(tree.starts_with("Segments\n├── 0x00000000-0x7FFFFFFF"));
+        assert!(tree.contains("│   │   ├── 0x04400000-0x04400003 VI_CTRL"));
+
+        let markdown: String = render_markdown(&entries);
+        assert!(markdown.contains("| `0x04400000` | `0x04400003` | VI_CTRL | Video mode and output control | InVI | RW | 32 |\n"));
+
+        let header: String = render_c_header(&entries);
+        assert!(header.contains("#define N64_KSEG0_START                  0x80000000 /* KSEG0 */\n"));
+        assert!(header.contains("#define N64_RDRM_SIZE                    0x3F00000\n"));
+        assert!(header.ends_with("#endif /* N64_MEMORY_MAP_H */\n"));
+    }
+
+    #[test]
+    fn c_header_lists_each_section_once_with_long_region_names() {
+        let header: String = render_c_header(&map_entries());
+        assert_eq!(header.matches("/* Segments */").count(), 1);
+        assert_eq!(header.matches("/* Regions */").count(), 1);
+        assert!(header.contains("\n/* Regions */\n#define N64_RDRAM_START                  0x00000000 /* RDRAM */\n"));
+        assert!(header.contains("#define N64_RCP_START                    0x04000000 /* RCP */\n"));
+        assert!(header.contains("#define N64_PI_1_2_START                 0x05000000 /* PI 1/2 */\n"));
+        assert!(!header.contains("#define N64_R_START"));
+    }
+
+    #[test]
+    fn c_header_names_are_unique_identifiers() {
+        assert_eq!(c_identifier("RDRAM memory-space"), "RDRAM_MEMORY_SPACE");
+        assert_eq!(c_identifier("InVI"), "INVI");
+
+        let entries: Vec<MapEntry> = vec![
+            entry(MapEntryKind::Subregion, "CART", "Cartridge ROM", 0x10000000, 0x1FBFFFFF),
+            entry(MapEntryKind::Subregion, "CART", "Cartridge SRAM", 0x08000000, 0x0FFFFFFF),
+            entry(MapEntryKind::Subregion, "CART", "Cartridge SRAM", 0x08000000, 0x0FFFFFFF),
+        ];
+        let header: String = render_c_header(&entries);
+        assert!(header.contains("#define N64_CART_START "));
+        assert!(header.contains("#define N64_CART_CARTRIDGE_SRAM_START "));
+        assert!(header.contains("#define N64_CART_CARTRIDGE_SRAM_2_START "));
+    }
+
+    #[test]
+    fn writes_entries_as_json_and_csv() {
+        let entry: MapEntry = entry(MapEntryKind::Segment, "XP", "XKPHYS", 0x8000000000000000, 0xBFFFFFFFFFFFFFFF);
+        assert_eq!(map_entry_to_json(&entry), json!({
+            "kind": "segment",
+            "short": "XP",
+            "name": "XKPHYS",
+            "start": "0x8000000000000000",
+            "end": "0xBFFFFFFFFFFFFFFF",
+            "parent": null,
+        }));
+        assert_eq!(map_entry_to_csv(&entry), ["segment", "XP", "XKPHYS", "0x8000000000000000", "0xBFFFFFFFFFFFFFFF", ""]);
+    }
+}
@@ -0,0 +1,255 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Arithmetic over addresses, register names and symbols
+//!
+//! Expressions such as `VI_BASE+0x14`, `0x80000400 + 0x1C*3` or
+//! `sym:osViSetMode+8` evaluate to a virtual address. The operators are those
+//! of C, with the same precedence: unary `-` and `~`, then `*`, `/` and `%`,
+//! then `+` and `-`, then `<<` and `>>`, then `&`, `^` and `|`, along with
+//! parentheses.
+//!
+//! Values are 64-bit and wrap around. Numbers that fit in 32 bits are
+//! sign-extended like addresses, so that `VI_ORIGIN - 0xA4400000` is 4, and so
+//! is the result, see [`normalize_address`].
+//!
+
+use crate::map::{normalize_address, parse_number};
+use crate::mapfile::MemoryMap;
+use crate::registers::{find_register, REGISTER_BLOCKS};
+use crate::symbols::SymbolTable;
+
+/// Base of the KSEG1 segment, through which registers are usually accessed.
+const KSEG1: u32 = 0xA000_0000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u64),
+    Name(String),
+    Symbol(String),
+    Operator(&'static str),
+}
+
+static OPERATORS: &[&str] = &["<<", ">>", "+", "-", "*", "/", "%", "&", "|", "^", "~", "(", ")"];
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut rest: &str = text.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else if c.is_ascii_digit() || c == '$' {
+            let end: usize = rest[1..].find(|c: char| !is_name_char(c)).map_or(rest.len(), |end| end + 1);
+            let number: u64 = parse_number(&rest[..end])
+                .ok_or_else(|| format!("invalid number `{}`", &rest[..end]))?;
+            tokens.push(Token::Number(normalize_address(number)));
+            rest = &rest[end..];
+        } else if is_name_char(c) {
+            let end: usize = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            let name: &str = &rest[..end];
+            rest = &rest[end..];
+            if name == "sym" {
+                if let Some(symbol) = rest.strip_prefix(':') {
+                    let end: usize = symbol.find(|c: char| !is_name_char(c) && c != '$').unwrap_or(symbol.len());
+                    if end == 0 {
+                        return Err("expected a symbol name after `sym:`".to_string());
+                    }
+                    tokens.push(Token::Symbol(symbol[..end].to_string()));
+                    rest = &symbol[end..];
+                    rest = rest.trim_start();
+                    continue;
+                }
+            }
+            tokens.push(Token::Name(name.to_string()));
+        } else {
+            return Err(format!("unexpected `{}`", c));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+/// Value of a name: a register (the KSEG1 address of its first copy), the
+/// base of a register block such as `VI_BASE` or `VI_BASE_REG` (in KSEG1), a
+/// subregion short name of the map such as `RSPD` (its start in KSEG1), a
+/// symbol, or else a bare hexadecimal number.
+fn resolve_name(name: &str, map: &MemoryMap, symbols: &SymbolTable) -> Option<u64> {
+    if let Some((block, register)) = find_register(name) {
+        return Some(kseg1(block.start + register.offset));
+    }
+
+    let upper: String = name.to_uppercase();
+    if let Some(prefix) = upper.strip_suffix("_BASE_REG").or_else(|| upper.strip_suffix("_BASE")) {
+        let block = REGISTER_BLOCKS.iter().find(|block| {
+            block.registers.first()
+                .and_then(|register| register.name.split_once('_'))
+                .is_some_and(|(block_prefix, _)| block_prefix == prefix)
+        });
+        if let Some(block) = block {
+            return Some(kseg1(block.start));
+        }
+    }
+
+    if let Some(subregion) = map.subregions.iter().find(|subregion| subregion.2 == name) {
+        return Some(kseg1(subregion.0));
+    }
+
+    symbols.find(name)
+        .map(|symbol| symbol.address)
+        .or_else(|| parse_number(name).map(normalize_address))
+}
+
+/// Physical addresses reachable through KSEG1 are given as such.
+fn kseg1(physical_address: u32) -> u64 {
+    if physical_address <= 0x1FFF_FFFF {
+        normalize_address((KSEG1 | physical_address) as u64)
+    } else {
+        physical_address as u64
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    map: &'a MemoryMap,
+    symbols: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token: Option<Token> = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Parses operands separated by operators of one precedence level, the
+    /// operands being the next level up.
+    fn binary(&mut self, level: usize) -> Result<u64, String> {
+        // Lowest precedence first
+        static LEVELS: &[&[&str]] = &[&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+
+        let mut value: u64 = self.binary(level + 1)?;
+        while let Some(Token::Operator(operator)) = self.peek() {
+            let operator: &str = operator;
+            if !operators.contains(&operator) {
+                break;
+            }
+            self.position += 1;
+            let rhs: u64 = self.binary(level + 1)?;
+            value = match operator {
+                "|" => value | rhs,
+                "^" => value ^ rhs,
+                "&" => value & rhs,
+                "<<" => value.wrapping_shl(rhs as u32),
+                ">>" => value.wrapping_shr(rhs as u32),
+                "+" => value.wrapping_add(rhs),
+                "-" => value.wrapping_sub(rhs),
+                "*" => value.wrapping_mul(rhs),
+                "/" => value.checked_div(rhs).ok_or("division by zero")?,
+                _ => value.checked_rem(rhs).ok_or("division by zero")?,
+            };
+        }
+        Ok(value)
+    }
+
+    fn unary(&mut self) -> Result<u64, String> {
+        match self.next() {
+            Some(Token::Operator("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Operator("~")) => Ok(!self.unary()?),
+            Some(Token::Operator("(")) => {
+                let value: u64 = self.binary(0)?;
+                match self.next() {
+                    Some(Token::Operator(")")) => Ok(value),
+                    _ => Err("expected `)`".to_string()),
+                }
+            }
+            Some(Token::Number(value)) => Ok(value),
+            Some(Token::Name(name)) => resolve_name(&name, self.map, self.symbols)
+                .ok_or_else(|| format!("unknown name `{}`", name)),
+            Some(Token::Symbol(name)) => self.symbols.find(&name)
+                .map(|symbol| symbol.address)
+                .ok_or_else(|| format!("unknown symbol `{}`", name)),
+            Some(Token::Operator(operator)) => Err(format!("unexpected `{}`", operator)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Evaluate an expression to a virtual address, normalized with
+/// [`normalize_address`]. Names are registers, register block bases,
+/// subregions of `map` and symbols from `symbols`; `sym:` only looks up
+/// symbols.
+pub fn evaluate(text: &str, map: &MemoryMap, symbols: &SymbolTable) -> Result<u64, String> {
+    let mut parser: Parser = Parser { tokens: tokenize(text)?, position: 0, map, symbols };
+    let value: u64 = parser.binary(0)?;
+    if let Some(token) = parser.peek() {
+        return Err(match token {
+            Token::Number(value) => format!("unexpected number {:#x}", value),
+            Token::Name(name) | Token::Symbol(name) => format!("unexpected `{}`", name),
+            Token::Operator(operator) => format!("unexpected `{}`", operator),
+        });
+    }
+    Ok(normalize_address(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbols::Symbol;
+
+    fn eval(text: &str) -> Result<u64, String> {
+        evaluate(text, &MemoryMap::default(), &SymbolTable::default())
+    }
+
+    #[test]
+    fn resolves_registers_block_bases_and_subregions() {
+        assert_eq!(eval("VI_BASE+0x14"), Ok(0xFFFFFFFFA4400014));
+        assert_eq!(eval("vi_origin"), Ok(0xFFFFFFFFA4400004));
+        assert_eq!(eval("SP_BASE_REG"), Ok(0xFFFFFFFFA4040000));
+        assert_eq!(eval("RSPI + 0x10"), Ok(0xFFFFFFFFA4001010));
+        assert_eq!(eval("VI_ORIGIN - 0xA4400000"), Ok(4));
+    }
+
+    #[test]
+    fn follows_the_precedence_of_c() {
+        assert_eq!(eval("0x80000400 + 0x1C*3"), Ok(0xFFFFFFFF80000454));
+        assert_eq!(eval("1 << 4 + 1"), Ok(32));
+        assert_eq!(eval("(1 << 4) + 1"), Ok(17));
+        assert_eq!(eval("0xF0 | 0x0F & 0x3"), Ok(0xF3));
+        assert_eq!(eval("~0"), Ok(0xFFFFFFFFFFFFFFFF));
+        assert_eq!(eval("-4 % 3"), Ok(0));
+    }
+
+    #[test]
+    fn looks_up_symbols() {
+        let mut symbols: SymbolTable = SymbolTable::default();
+        symbols.extend([Symbol { address: 0xFFFFFFFF80001234, size: None, name: "osViSetMode".to_string() }]);
+        assert_eq!(evaluate("sym:osViSetMode+8", &MemoryMap::default(), &symbols), Ok(0xFFFFFFFF8000123C));
+        assert_eq!(evaluate("osViSetMode", &MemoryMap::default(), &symbols), Ok(0xFFFFFFFF80001234));
+        assert_eq!(eval("sym:osViSetMode"), Err("unknown symbol `osViSetMode`".to_string()));
+    }
+
+    #[test]
+    fn reports_malformed_expressions() {
+        assert_eq!(eval("1/0"), Err("division by zero".to_string()));
+        assert_eq!(eval("(1"), Err("expected `)`".to_string()));
+        assert_eq!(eval("1 2"), Err("unexpected number 0x2".to_string()));
+        assert_eq!(eval("1 +"), Err("unexpected end of expression".to_string()));
+        assert_eq!(eval("VI_NOPE"), Err("unknown name `VI_NOPE`".to_string()));
+        assert_eq!(eval("1 @ 2"), Err("unexpected `@`".to_string()));
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Library to describe an N64 virtual address
+//!
+//! Based on information the memory map documentation here:
+//! https://n64brew.dev/wiki/Memory_map
+//!
+//! The [`map`] module holds the memory map tables and the address lookup,
+//! [`registers`] the register tables of the RCP interfaces, [`search`] the
+//! reverse lookup from names to addresses, [`tlb`] the translation of mapped
+//! segments, [`symbols`] the resolution of addresses to function+offset, and
+//! [`source`] the resolution of addresses to source lines, while [`trace`]
+//! annotates the virtual address column of Ares instruction traces using the
+//! lookup, and [`parsers`] recognizes the traces of other emulators and
+//! debuggers. [`output`] writes lookups as JSON or CSV for scripts, [`text`] as
+//! the tables of the command line tool, and [`expr`] evaluates address
+//! expressions over registers and symbols. [`range`] splits address ranges
+//! along the areas they cross, and [`dump`] renders the whole memory map as a
+//! tree, Markdown tables or a C header. [`mapfile`] merges regions and
+//! subregions loaded from TOML or JSON files into the tables, and [`check`]
+//! reports gaps, overlaps and duplicate names in them. [`rom`] reads cartridge
+//! ROM files in any byte order and parses their header, and [`disasm`]
+//! disassembles the code of ROM files and memory dumps. [`rsp`] maps the
+//! addresses of the RSP view of its memories and disassembles RSP microcode,
+//! and [`scan`] annotates the addresses found anywhere in any text.
+//!
+
+pub mod check;
+pub mod disasm;
+pub mod dump;
+pub mod expr;
+pub mod map;
+pub mod mapfile;
+pub mod output;
+pub mod parsers;
+pub mod range;
+pub mod registers;
+pub mod rom;
+pub mod rsp;
+pub mod scan;
+pub mod search;
+pub mod source;
+pub mod symbols;
+pub mod text;
+pub mod tlb;
+pub mod trace;
+
+pub use check::{check_map, issue_to_csv, issue_to_json, Issue, IssueKind, Severity, ISSUE_CSV_HEADER};
+pub use disasm::{
+    describe_reference,
+    disassemble,
+    instruction_to_csv,
+    instruction_to_json,
+    Instruction,
+    MemoryImage,
+    Processor,
+    INSTRUCTION_CSV_HEADER,
+};
+pub use dump::{
+    map_entries,
+    map_entry_to_csv,
+    map_entry_to_json,
+    render_c_header,
+    render_markdown,
+    render_tree,
+    MapEntry,
+    MapEntryKind,
+    MAP_CSV_HEADER,
+};
+pub use expr::evaluate;
+pub use map::{
+    address_location_to_string,
+    cache_attribute_name,
+    format_virtual_address,
+    get_segment_region_subregion,
+    get_segment_region_subregion_64,
+    get_segment_region_subregion_tlb,
+    is_32bit_compatible,
+    normalize_address,
+    parse_address,
+    parse_number,
+    resolve_mirror,
+    sign_extend,
+    AddressLocation,
+    AreaNames,
+    CanonicalAddress,
+    Mirror,
+    Region,
+    Segment64,
+    MIRRORS,
+    REGIONS,
+    SEGMENTS,
+    SEGMENTS_64,
+    SUBREGIONS,
+};
+pub use mapfile::{
+    parse_size,
+    MapRegion,
+    MemoryMap,
+    RDRAM_EXPANSION_SIZE,
+    RDRAM_MAX_SIZE,
+    RDRAM_STOCK_SIZE,
+    RDRAM_UNPOPULATED,
+};
+pub use output::{
+    csv_row,
+    location_to_csv,
+    location_to_json,
+    range_piece_to_csv,
+    range_to_json,
+    OutputFormat,
+    LOCATION_CSV_HEADER,
+    RANGE_CSV_HEADER,
+    ROM_BYTES,
+};
+pub use parsers::{load_trace_patterns, trace_pattern, TraceFormat, CAPTURE_NAMES, DETECT_LINES};
+pub use range::{offset_address, range_size, split_range, RangePiece};
+pub use registers::{
+    decode_register,
+    decoded_register_to_string,
+    find_register,
+    get_register,
+    Access,
+    Bitfield,
+    Register,
+    RegisterBlock,
+    REGISTER_BLOCKS,
+};
+pub use rom::{
+    format_rom_bytes,
+    rom_header_fields,
+    rom_header_to_json,
+    ByteOrder,
+    Rom,
+    RomHeader,
+    ROM_BASE,
+    ROM_END,
+};
+pub use rsp::{
+    disassemble_rsp,
+    format_rsp_address,
+    physical_to_rsp,
+    rsp_memory_name,
+    rsp_to_physical,
+    rsp_to_virtual,
+    RSP_BASE,
+    RSP_IMEM,
+    RSP_VIEW_SIZE,
+};
+pub use scan::{annotate_addresses, in_areas, is_area_name, SCAN_CSV_HEADER};
+pub use search::{find_by_name, NameKind, NameMatch};
+pub use source::{SourceFrame, SourceLines};
+pub use symbols::{symbol_to_string, Symbol, SymbolTable};
+pub use text::{format_range, render_decoded_register, render_location, render_matches, render_range};
+pub use tlb::{Tlb, TlbEntry, TlbTranslation};
+pub use trace::{rewrite_lines, rewrite_lines_of_file, trace_stats, TraceOptions, TraceStats, TRACE_CSV_HEADER};
@@ -2,273 +2,1113 @@
 
 //! CLI to describe an N64 virtual address
 //!
-//! Based on information the memory map documentation here:
-//! https://n64brew.dev/wiki/Memory_map
+//! Thin wrapper around the `n64_memory_map` library crate.
 //!
-//! The CLI accepts one argument with the following behaviors respectively:
+//! The CLI takes one of the following subcommands:
 //!
-//! 1. If given a 32-bit hexadecimal number prefixed with "0x", details about
-//!    the address are printed to stdout.
+//! 1. `lookup <address>...` prints details about each address. Addresses are
+//!    read from stdin, one per line, when none are given or given as `-`.
+//!    They may be written as `0x` or `$` prefixed hexadecimal, bare
+//!    hexadecimal or decimal, and combined with register names and symbols in
+//!    expressions, e.g. `VI_BASE+0x14` or `sym:osViSetMode+8`. Addresses of up
+//!    to 32 bits are sign-extended as in 32-bit mode. Ranges, written
+//!    `start..end`, `start..=end` or `start..+length`, are split into the
+//!    areas they cross. With `--physical`, addresses are physical, and with
+//!    `--rsp`, addresses of the RSP view of DMEM (0x0000) and IMEM (0x1000).
+//!    Without them, addresses below 0x80000000 are KUSEG ones, mapped through
+//!    the TLB, so physical addresses such as `0x00FFFFFF` and ranges such as
+//!    `0x03FF0000..0x04002000` need `--physical`, which is hinted at when
+//!    they miss the TLB.
 //!
-//! 2. If given a filename of an Ares instruction trace, the virtual address
-//!    column is annotated with a short string describing the address.
+//! 2. `annotate [file]` annotates the virtual address column of an
+//!    instruction trace with a short string describing the address. The trace
+//!    is read from stdin when no file is given or given as `-`.
+//!
+//! 3. `decode <register|address> <value> [write]` splits the value into the
+//!    bitfields of the register. The value is read like an address, so the
+//!    8-digit values of Ares `VI I/O:` lines are hexadecimal. A trailing
+//!    `write` selects the layout of values written to the register rather
+//!    than read from it.
+//!
+//! 4. `find <name>` prints the regions, subregions and registers named like
+//!    it with their physical and KSEG0/KSEG1 address ranges.
+//!
+//! 5. `table [tree|markdown|header]` prints the segments, regions,
+//!    subregions and registers of the memory map as a tree, Markdown tables
+//!    or a C header of `#define`s. With `--format`, they are listed as flat
+//!    JSON or CSV entries instead.
+//!
+//! 6. `stats [file]` counts the instructions of a trace per area they ran
+//!    from, and the loads and stores per area and register they accessed.
+//!
+//! 7. `check` reports gaps, overlaps, duplicate short names and subregions
+//!    outside of their region in the memory map, including map files.
+//!
+//! 8. `rom [file]` prints the title, game code, entry point and CIC of the
+//!    header of a cartridge ROM, the one given with `--rom` by default.
+//!
+//! 9. `disasm [--rsp] [--at <address>] <address> [count]` disassembles the
+//!    instructions at an address from the ROM or memory dumps, annotating
+//!    branch targets and the addresses built with `lui`. `--at` gives the
+//!    address the code runs at when it's copied elsewhere, e.g. from ROM.
+//!    With `--rsp`, the instructions are RSP microcode, at an RSP address or
+//!    read from a CPU address to run from IMEM.
+//!
+//! 10. `scan [--only <area>]... [file]` annotates the addresses found anywhere
+//!     in any text, e.g. crash logs or disassembly listings, with the same
+//!     short strings. `--only` restricts them to the segments, regions or
+//!     subregions named, to leave out data that looks like addresses.
+//!
+//! For compatibility, an address prefixed with "0x" or the name of an existing
+//! trace file may also be given without a subcommand. Anything else is an
+//! unknown command.
+//!
+//! Addresses of the TLB-mapped segments only translate to physical addresses
+//! when a TLB dump is given with `--tlb <file>`. Traces also update the TLB
+//! from the `mtc0`, `tlbwi` and `tlbwr` instructions they contain.
+//!
+//! Symbols from ELF files, linker map files or `symbol_addrs.txt` files given
+//! with `--symbols <file>`, which may be repeated, are shown as function+offset
+//! for addresses and trace lines.
+//!
+//! Microcode symbols given with `--rsp-symbols <file>`, which may be repeated,
+//! are shown for the RSP lines of traces and RSP disassembly instead. Their
+//! addresses are RSP addresses, IMEM starting at 0x1000.
+//!
+//! An ELF file with DWARF debug information given with `--source <file>`
+//! resolves addresses to source lines and inlined functions, and adds a source
+//! location column to trace lines.
+//!
+//! A cartridge ROM given with `--rom <file>`, in any of the z64, v64 and n64
+//! byte orders, shows its bytes at the cartridge ROM addresses looked up.
+//!
+//! Memory dumps given with `--dump <file>[@address]`, which may be repeated,
+//! hold the memory at a physical address, RDRAM at 0 by default, for
+//! `disasm`.
+//!
+//! Regions and subregions from TOML or JSON files given with `--map <file>`,
+//! which may be repeated, are merged into the built-in memory map, later files
+//! taking precedence.
+//!
+//! `--rdram <size>`, e.g. `4M` or `8M` with the Expansion Pak, splits RDRAM
+//! into base, Expansion Pak and unpopulated memory, and flags trace lines
+//! accessing RDRAM beyond the installed size.
+//!
+//! `--trace-format <name>` gives the emulator or debugger a trace comes from,
+//! e.g. `mupen64plus` or `gdb`, detected from the first lines of the trace by
+//! default or with `auto`. Traces of other tools are parsed with regular
+//! expressions given with `--trace-pattern <regex>`, which may be repeated, or
+//! read one per line from `--trace-patterns <file>`. They capture the
+//! instruction address as `pc`, and optionally the processor as `prefix`, the
+//! instruction as `rest` and the effective address of a load or store as
+//! `addr`.
+//!
+//! `--format json|jsonl|csv|table` selects how addresses, trace lines,
+//! statistics and the memory map are written, `table` being the default
+//! human-readable output.
+//!
+//! The exit status is 1 when a name or register is not found or `check` finds
+//! errors, 2 when an argument or input doesn't parse, and 3 when a file can't
+//! be read or the output can't be written.
 //!
 
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead};
+use std::io::{self, BufRead, BufReader};
 use std::path::Path;
 use std::process::exit;
+use std::rc::Rc;
 
 use regex::Regex;
+use serde_json::{json, Value};
 use tabular::{Table, Row};
 
-type Region = (
-    u32,            // start
-    u32,            // end
-    &'static str,   // short name
-    &'static str,   // long name
-);
-
-static SEGMENTS: &[Region] = &[
-    (0x00000000, 0x7FFFFFFF, "U", "KUSEG"),
-    (0x80000000, 0x9FFFFFFF, "0", "KSEG0"),
-    (0xA0000000, 0xBFFFFFFF, "1", "KSEG1"),
-    (0xC0000000, 0xDFFFFFFF, "S", "KSSEG"),
-    (0xE0000000, 0xFFFFFFFF, "3", "KSEG3"),
-];
-
-static REGIONS: &[Region] = &[
-    (0x00000000, 0x03FFFFFF, "R", "RDRAM"),
-    (0x04000000, 0x049FFFFF, "G", "RCP"),
-    (0x05000000, 0x1FBFFFFF, "P", "PI 1/2"),
-    (0x1FC00000, 0x1FCFFFFF, "S", "SI"),
-    (0x1FD00000, 0x7FFFFFFF, "B", "PI 2/2"),
-    (0x80000000, 0xFFFFFFFF, "U", "Unmapped"),
-];
-
-static SUBREGIONS: &[Region] = &[
-
-    // RDRAM (RDR)
-    (0x00000000, 0x03EFFFFF, "RDRM", "RDRAM memory-space"),
-    (0x03F00000, 0x03F7FFFF, "RDRR", "RDRAM registers"),
-    (0x03F80000, 0x03FFFFFF, "RDRB", "RDRAM broadcast registers"),
-
-    // RCP (RSP or RCP)
-    (0x04000000, 0x04000FFF, "RSPD", "RSP Data Memory"),
-    (0x04001000, 0x04001FFF, "RSPI", "RSP Instruction Memory"),
-    (0x04002000, 0x0403FFFF, "RSPM", "RSP DMEM/IMEM Mirrors"),
-    (0x04040000, 0x040BFFFF, "RSPR", "RSP Registers"),
-    (0x040C0000, 0x040FFFFF, "RCPU", "Unmapped/fatal"),
-    (0x04100000, 0x041FFFFF, "RDPC", "RDP Command Registers"),
-    (0x04200000, 0x042FFFFF, "RDPS", "RDP Span Registers"),
-    (0x04300000, 0x043FFFFF, "InMI", "MIPS Interface"),
-    (0x04400000, 0x044FFFFF, "InVI", "Video Interface"),
-    (0x04500000, 0x045FFFFF, "InAI", "Audio Interface"),
-    (0x04600000, 0x046FFFFF, "InPI", "Peripheral Interface"),
-    (0x04700000, 0x047FFFFF, "InRI", "RDRAM Interface"),
-    (0x04800000, 0x048FFFFF, "InSI", "Serial Interface"),
-    (0x04900000, 0x04FFFFFF, "RCPu", "Unmapped/fatal"),
-
-    // PI
-    (0x05000000, 0x05FFFFFF, "NDDR", "N64DD Registers"),
-    (0x06000000, 0x07FFFFFF, "NDDI", "N64DD IPL ROM"),
-    (0x08000000, 0x0FFFFFFF, "CSRM", "Cartridge SRAM"),
-    (0x10000000, 0x1FBFFFFF, "CROM", "Cartridge ROM"),
-
-    // SI
-    (0x1FC00000, 0x1FC007BF, "PIFR", "PIF ROM"),
-    (0x1FC007C0, 0x1FC007FF, "PIFR", "PIF RAM"),
-    (0x1FC00800, 0x1FCFFFFF, "RSVD", "Reserved"),
-
-    // PI, pt.2
-    (0x1FD00000, 0x1FFFFFFF, "UPB1", "Unused / PI BUS Domain 1"),
-    (0x20000000, 0x7FFFFFFF, "UCPA", "Unused / PI BUS Domain 1 [CPU Accessible]"),
-
-    // No device
-    (0x80000000, 0xFFFFFFFF, "UNMP", "Unmapped/fatal"),
-
-];
-
-/// Describes the location of the address by naming its segment, region, and
-/// subregion as documented in the mappings above.
-#[allow(dead_code)]
-struct AddressLocation {
-    virtual_address: u32,
-    physical_address: u32,
-    segment: Option<(&'static str, &'static str)>,
-    region: Option<(&'static str, &'static str)>,
-    subregions: Vec<(&'static str, &'static str)>,
+use n64_memory_map::{
+    annotate_addresses,
+    check_map,
+    csv_row,
+    disassemble,
+    disassemble_rsp,
+    evaluate,
+    find_register,
+    format_virtual_address,
+    get_register,
+    instruction_to_csv,
+    instruction_to_json,
+    issue_to_csv,
+    issue_to_json,
+    is_32bit_compatible,
+    is_area_name,
+    load_trace_patterns,
+    location_to_csv,
+    location_to_json,
+    map_entry_to_csv,
+    map_entry_to_json,
+    offset_address,
+    parse_address,
+    parse_number,
+    parse_size,
+    range_piece_to_csv,
+    range_to_json,
+    render_c_header,
+    render_decoded_register,
+    render_location,
+    render_markdown,
+    render_matches,
+    render_range,
+    render_tree,
+    rewrite_lines,
+    rom_header_fields,
+    rom_header_to_json,
+    rsp_to_virtual,
+    sign_extend,
+    trace_pattern,
+    trace_stats,
+    AddressLocation,
+    Instruction,
+    Issue,
+    MapEntry,
+    MemoryImage,
+    MemoryMap,
+    NameMatch,
+    OutputFormat,
+    RangePiece,
+    Register,
+    Severity,
+    Rom,
+    SourceLines,
+    SymbolTable,
+    Tlb,
+    TlbTranslation,
+    TraceFormat,
+    TraceOptions,
+    TraceStats,
+    INSTRUCTION_CSV_HEADER,
+    ISSUE_CSV_HEADER,
+    LOCATION_CSV_HEADER,
+    MAP_CSV_HEADER,
+    RANGE_CSV_HEADER,
+    ROM_BASE,
+    RSP_IMEM,
+    RSP_VIEW_SIZE,
+};
+
+/// Exit status when a name or register is not found.
+const EXIT_NOT_FOUND: i32 = 1;
+/// Exit status when `check` finds errors in the memory map.
+const EXIT_CHECK_FAILED: i32 = 1;
+/// Exit status when an argument or input doesn't parse.
+const EXIT_PARSE: i32 = 2;
+/// Exit status when a file can't be read or the output can't be written.
+const EXIT_IO: i32 = 3;
+
+const USAGE: &str = "\
+Usage: n64-memory-map [options] <command> [arguments]
+
+Commands:
+  lookup [--physical|--rsp] [address...]
+                          Describe addresses or ranges, read from stdin if none are given;
+                          --physical for physical ones, e.g. 0x03FF0000..0x04002000
+  annotate [file]         Annotate a trace, read from stdin if no file is given
+  decode <register> <value> [write]
+                          Split a register value into its bitfields
+  find <name>             Find regions, subregions and registers by name
+  table [tree|markdown|header]
+                          Print the memory map as a tree, Markdown or a C header
+  stats [file]            Count what a trace executed and accessed
+  check                   Report gaps, overlaps and duplicate names in the memory map
+  rom [file]              Print the header of a cartridge ROM
+  disasm [--rsp] [--at <address>] <address> [count]
+                          Disassemble CPU or RSP instructions from the ROM or memory dumps
+  scan [--only <area>]... [file]
+                          Annotate the addresses found in any text, e.g. a crash log
+
+Options:
+  --tlb <file>            TLB dump used to translate mapped addresses
+  --symbols <file>        ELF, linker map or symbol_addrs.txt file, may be repeated
+  --rsp-symbols <file>    Microcode symbols of RSP addresses, may be repeated
+  --source <file>         ELF file with DWARF debug information
+  --rom <file>            Cartridge ROM shown at its addresses, z64, v64 or n64
+  --dump <file>[@address] Memory dump at a physical address, RDRAM by default
+  --map <file>            TOML or JSON file of extra regions, may be repeated
+  --rdram <size>          Installed RDRAM, e.g. 4M or 8M with the Expansion Pak
+  --trace-format <name>   ares, cen64, mupen64plus, project64, simple64, gdb or auto (default)
+  --trace-pattern <regex> Trace line pattern capturing pc, prefix, rest and addr, may be repeated
+  --trace-patterns <file> File of trace line patterns, one per line
+  --format <format>       json, jsonl, csv or table (default)";
+
+/// Exit status of a failed read or write: input that doesn't parse is told
+/// apart from files that can't be read.
+fn io_exit_status(e: &io::Error) -> i32 {
+    if e.kind() == io::ErrorKind::InvalidData {
+        EXIT_PARSE
+    } else {
+        EXIT_IO
+    }
 }
 
-/// Given an address, return the name of the segment, region, and subregion
-/// where the address is located.
-fn get_segment_region_subregion(address: u32) -> AddressLocation {
+/// Opens the named file, or stdin if there is no name or it is `-`.
+fn open_input(filename: Option<&String>) -> io::Result<Box<dyn BufRead>> {
+    match filename.map(String::as_str) {
+        None | Some("-") => Ok(Box::new(BufReader::new(io::stdin()))),
+        Some(filename) => Ok(Box::new(BufReader::new(File::open(filename)?))),
+    }
+}
 
-    // Remove bits about cached/uncached access
-    let address_raw: u32 = address & 0x1FFF_FFFF;
+/// An address or an address range given to `lookup`.
+enum Query {
+    Address(u64),
+    /// First and last address
+    Range(u64, u64),
+}
 
-    let segment: Option<(&str, &str)> = SEGMENTS.iter()
-        .find(|seg| seg.0 <= address && address <= seg.1)
-        .map(|seg| (seg.2, seg.3));
+/// The address space of the addresses given to `lookup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressSpace {
+    Virtual,
+    Physical,
+    /// The RSP view of DMEM and IMEM
+    Rsp,
+}
 
-    let region: Option<(&str, &str)> = REGIONS.iter()
-        .find(|reg| reg.0 <= address_raw && address_raw <= reg.1)
-        .map(|reg| (reg.2, reg.3));
+/// Turns a physical address into the KSEG1 address reaching it.
+fn physical_to_virtual(address: u64) -> Result<u64, String> {
+    if address <= 0x1FFF_FFFF {
+        Ok(sign_extend(0xA000_0000 | address as u32))
+    } else {
+        Err(format!("physical address {:#x} is not reachable through KSEG1", address))
+    }
+}
 
-    let subregions: Vec<(&str, &str)> = SUBREGIONS.iter()
-        .filter(|reg| reg.0 <= address_raw && address_raw <= reg.1)
-        .map(|reg| (reg.2, reg.3))
-        .collect();
+/// Hints at `--physical` when every location given to `lookup` misses the
+/// TLB at a KUSEG address that could be a physical one, e.g. 0x00FFFFFF.
+fn hint_physical<'a>(input: &str, space: AddressSpace, mut locations: impl Iterator<Item = &'a AddressLocation>) {
+    let physical: bool = locations.all(|location| {
+        location.virtual_address <= 0x1FFF_FFFF && location.tlb == Some(TlbTranslation::Miss)
+    });
+    if space == AddressSpace::Virtual && physical {
+        eprintln!("Hint: {} is in KUSEG, mapped through the TLB; give --physical for physical addresses", input);
+    }
+}
 
-    AddressLocation {
-        virtual_address: address,
-        physical_address: address_raw,
-        segment,
-        region,
-        subregions,
+/// Turns an RSP address into the KSEG1 address reaching it.
+fn rsp_address_to_virtual(address: u64) -> Result<u64, String> {
+    if address < RSP_VIEW_SIZE as u64 {
+        Ok(rsp_to_virtual(address as u32))
+    } else {
+        Err(format!("RSP address {:#x} is beyond IMEM", address))
     }
 }
 
-/// Produces the short-form description of an address. The short form is meant
-/// to fit into a tight column width.
-fn address_location_to_string(address_location: &AddressLocation) -> String {
-    let subregion_short_names: Vec<&'static str> = address_location.subregions.iter().map(|s| s.0).collect();
-    return format!(
-        "{}{}.{}",
-        address_location.segment.unwrap_or(("?", "?")).0,
-        address_location.region.unwrap_or(("?", "?")).0,
-        subregion_short_names.join("."),
-    );
+/// Parses an address expression or a range, written `start..end` (end
+/// excluded), `start..=end` (end included) or `start..+length`.
+fn parse_query(input: &str, map: &MemoryMap, symbols: &SymbolTable, space: AddressSpace) -> Result<Query, String> {
+    let address = |text: &str| -> Result<u64, String> {
+        let address: u64 = evaluate(text, map, symbols)?;
+        match space {
+            AddressSpace::Virtual => Ok(address),
+            AddressSpace::Physical => physical_to_virtual(address),
+            AddressSpace::Rsp => rsp_address_to_virtual(address),
+        }
+    };
+
+    let Some((start, end)) = input.split_once("..") else {
+        return address(input).map(Query::Address);
+    };
+
+    let start: u64 = address(start)?;
+    let last: u64 = if let Some(end) = end.strip_prefix('=') {
+        address(end)?
+    } else if let Some(length) = end.strip_prefix('+') {
+        let length: u64 = evaluate(length, map, symbols)?;
+        if length == 0 {
+            return Err("empty range".to_string());
+        }
+        offset_address(start, length - 1)
+    } else {
+        let end: u64 = address(end)?;
+        if end == start {
+            return Err("empty range".to_string());
+        }
+        offset_address(end, u64::MAX)
+    };
+
+    let ordered: bool = if is_32bit_compatible(start) && is_32bit_compatible(last) {
+        last as u32 >= start as u32
+    } else {
+        last >= start
+    };
+    if !ordered {
+        return Err("the range ends before it starts".to_string());
+    }
+    Ok(Query::Range(start, last))
 }
 
-/// Read a file line by line and apply a regex to each line looking for lines
-/// that start with three characters, followed by a space, then 16 hexadecimal
-/// characters, and then the rest of the line. Lines that don't match the
-/// pattern are printed to stdout as they are. Matching lines are modified so
-/// that the hexadecimal part is converted to an integer (u64), and the lower
-/// 32 bits are also extracted (u32), and then the modified line is printed
-/// to stdout.
-fn rewrite_lines_of_file(filename: String) -> io::Result<()> {
-    let path: &Path = Path::new(&filename);
-    let file: File = File::open(&path)?;
-    let reader: io::BufReader<File> = io::BufReader::new(file);
-    let re: Regex = Regex::new(r"^([A-Z]{3})\s*([a-f0-9]{16})\s*(.*)$").unwrap();
-
-    for line in reader.lines() {
-        let line: String = line?;
-        match re.captures(&line) {
-            Some(caps) => {
-
-                let prefix: &str = caps.get(1).map_or("", |m| m.as_str());
-                let hex: &str = caps.get(2).map_or("", |m| m.as_str());
-                let suffix: &str = caps.get(3).map_or("", |m| m.as_str());
-                let int_val: u64 = u64::from_str_radix(hex, 16).unwrap();
-                let lower_32_bits_val: u32 = (int_val & 0x0000_ffff_ffff) as u32;
-
-                let location: AddressLocation = get_segment_region_subregion(lower_32_bits_val);
-
-                println!(
-                    "{} {:<12} {:#08x} {}",
-                    prefix,
-                    address_location_to_string(&location).to_uppercase(),
-                    lower_32_bits_val,
-                    suffix
+/// Handles `lookup [--physical|--rsp] [address...]`, reading the addresses
+/// from stdin when none are given. Addresses that don't parse are reported
+/// and skipped. With `--physical`, addresses are physical, and with `--rsp`
+/// addresses of the RSP view of DMEM and IMEM, and both are looked up
+/// through KSEG1.
+fn lookup(args: &[String], options: &TraceOptions) -> i32 {
+    let mut status: i32 = 0;
+    let symbols: &SymbolTable = &options.symbols;
+    let source: Option<&SourceLines> = options.source.as_deref();
+    let rom: Option<&Rom> = options.rom.as_deref();
+
+    let space: AddressSpace = if args.iter().any(|arg| arg == "--physical") {
+        AddressSpace::Physical
+    } else if args.iter().any(|arg| arg == "--rsp") {
+        AddressSpace::Rsp
+    } else {
+        AddressSpace::Virtual
+    };
+    let args: Vec<&String> = args.iter().filter(|arg| *arg != "--physical" && *arg != "--rsp").collect();
+
+    let mut inputs: Vec<String> = args.iter().filter(|arg| **arg != "-").map(|arg| arg.to_string()).collect();
+    if inputs.len() < args.len() || args.is_empty() {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    let line: &str = line.split('#').next().unwrap_or("").trim();
+                    if !line.is_empty() {
+                        inputs.push(line.to_string());
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error reading addresses: {}", e);
+                    return io_exit_status(&e);
+                }
+            }
+        }
+    }
+
+    let mut json: Vec<Value> = Vec::new();
+    if options.format == OutputFormat::Csv {
+        let header: Vec<&str> = LOCATION_CSV_HEADER.iter().chain(RANGE_CSV_HEADER.iter()).copied().collect();
+        println!("{}", csv_row(&header));
+    }
+
+    let mut printed: bool = false;
+    for input in inputs.iter() {
+        let query: Query = match parse_query(input, &options.map, symbols, space) {
+            Ok(query) => query,
+            Err(e) => {
+                eprintln!("Invalid address {}: {}", input, e);
+                status = EXIT_PARSE;
+                continue;
+            }
+        };
+        if options.format == OutputFormat::Table && printed {
+            println!();
+        }
+        printed = true;
+
+        match query {
+            Query::Address(address) => {
+                let location: AddressLocation = options.map.lookup(address, &options.tlb);
+                hint_physical(input, space, std::iter::once(&location));
+                match options.format {
+                    OutputFormat::Table => print!("{}", render_location(&location, symbols, source, rom)),
+                    OutputFormat::Json => json.push(location_to_json(&location, symbols, source, rom)),
+                    OutputFormat::JsonLines => println!("{}", location_to_json(&location, symbols, source, rom)),
+                    OutputFormat::Csv => {
+                        let mut fields: Vec<String> = location_to_csv(&location, symbols, source);
+                        fields.resize(LOCATION_CSV_HEADER.len() + RANGE_CSV_HEADER.len(), String::new());
+                        println!("{}", csv_row(&fields));
+                    }
+                }
+            }
+            Query::Range(start, end) => {
+                let pieces: Vec<RangePiece> = options.map.split_range(start, end, &options.tlb);
+                hint_physical(input, space, pieces.iter().map(|piece| &piece.location));
+                match options.format {
+                    OutputFormat::Table => print!("{}", render_range(&pieces)),
+                    OutputFormat::Json => json.push(range_to_json(&pieces, symbols, source, rom)),
+                    OutputFormat::JsonLines => println!("{}", range_to_json(&pieces, symbols, source, rom)),
+                    OutputFormat::Csv => {
+                        for piece in pieces.iter() {
+                            println!("{}", csv_row(&range_piece_to_csv(piece, symbols, source)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if options.format == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&json).unwrap());
+    }
+
+    status
+}
+
+/// Handles `annotate [file]`.
+fn annotate(args: &[String], options: &TraceOptions) -> i32 {
+    let result: io::Result<()> = open_input(args.first())
+        .and_then(|reader| rewrite_lines(reader, io::stdout().lock(), options));
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error rewriting lines of {}: {}", args.first().map_or("stdin", String::as_str), e);
+            io_exit_status(&e)
+        }
+    }
+}
+
+/// Handles `scan [--only <area>]... [file]`.
+fn scan(args: &[String], options: &TraceOptions) -> i32 {
+    let mut args: Vec<String> = args.to_vec();
+    let mut areas: Vec<String> = Vec::new();
+    while let Some(position) = args.iter().position(|arg| arg == "--only") {
+        let Some(area) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a segment, region or subregion name after --only");
+            return EXIT_PARSE;
+        };
+        if !is_area_name(&area, &options.map) {
+            eprintln!("No segment, region or subregion named {}", area);
+            return EXIT_NOT_FOUND;
+        }
+        areas.push(area);
+        args.drain(position..position + 2);
+    }
+
+    let result: io::Result<()> = open_input(args.first())
+        .and_then(|reader| annotate_addresses(reader, io::stdout().lock(), options, &areas));
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error scanning lines of {}: {}", args.first().map_or("stdin", String::as_str), e);
+            io_exit_status(&e)
+        }
+    }
+}
+
+/// Handles `stats [file]`.
+fn stats(args: &[String], options: &TraceOptions) -> i32 {
+    let stats: TraceStats = match open_input(args.first()).and_then(|reader| trace_stats(reader, options)) {
+        Ok(stats) => stats,
+        Err(e) => {
+            eprintln!("Error reading trace {}: {}", args.first().map_or("stdin", String::as_str), e);
+            return io_exit_status(&e);
+        }
+    };
+
+    // The same kind, name and count rows make up the table and CSV outputs
+    let mut rows: Vec<(&str, String, usize)> = vec![
+        ("Lines", String::new(), stats.lines),
+        ("Instructions", String::new(), stats.instructions),
+    ];
+    let counts: [(&str, &std::collections::BTreeMap<String, usize>); 3] = [
+        ("Executed", &stats.executed),
+        ("Loads", &stats.loads),
+        ("Stores", &stats.stores),
+    ];
+    for (kind, counts) in counts {
+        rows.extend(counts.iter().map(|(annotation, count)| (kind, annotation.clone(), *count)));
+    }
+    for (register, (reads, writes)) in stats.registers.iter() {
+        if *reads > 0 {
+            rows.push(("Register reads", register.to_string(), *reads));
+        }
+        if *writes > 0 {
+            rows.push(("Register writes", register.to_string(), *writes));
+        }
+    }
+
+    match options.format {
+        OutputFormat::Table => {
+            let mut table: Table = Table::new("{:<} {:<} {:>}");
+            for (kind, name, count) in rows {
+                table.add_row(
+                    Row::new()
+                        .with_cell(format!("{}:", kind))
+                        .with_cell(name)
+                        .with_cell(count)
                 );
             }
-            None => println!("{}", line),
+            print!("{}", table);
+        }
+        OutputFormat::Json | OutputFormat::JsonLines => {
+            let registers: serde_json::Map<String, Value> = stats.registers.iter()
+                .map(|(register, (reads, writes))| {
+                    (register.to_string(), json!({ "reads": reads, "writes": writes }))
+                })
+                .collect();
+            let json: Value = json!({
+                "lines": stats.lines,
+                "instructions": stats.instructions,
+                "executed": stats.executed,
+                "loads": stats.loads,
+                "stores": stats.stores,
+                "registers": registers,
+            });
+            if options.format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            } else {
+                println!("{}", json);
+            }
+        }
+        OutputFormat::Csv => {
+            println!("{}", csv_row(&["kind", "name", "count"]));
+            for (kind, name, count) in rows {
+                println!("{}", csv_row(&[kind.to_lowercase().replace(' ', "_"), name, count.to_string()]));
+            }
+        }
+    }
+
+    0
+}
+
+/// Handles `table [tree|markdown|header]`, printing each segment, region,
+/// subregion and register with its address range. Registers are listed at
+/// their first address, not at their mirrors. The style only applies to the
+/// table format, the other formats being flat lists of entries.
+fn table(args: &[String], options: &TraceOptions) -> i32 {
+    let style: Option<&str> = args.first().map(|arg| arg.as_str());
+    if args.len() > 1 {
+        eprintln!("Expected at most one style, tree, markdown or header");
+        return EXIT_PARSE;
+    }
+    if style.is_some() && options.format != OutputFormat::Table {
+        eprintln!("A table style can't be combined with --format {}", options.format);
+        return EXIT_PARSE;
+    }
+
+    let entries: Vec<MapEntry> = options.map.entries();
+    match options.format {
+        OutputFormat::Table => {
+            let text: String = match style.unwrap_or("tree") {
+                "tree" => render_tree(&entries),
+                "markdown" | "md" => render_markdown(&entries),
+                "header" | "c" => render_c_header(&entries),
+                style => {
+                    eprintln!("Unknown table style `{}`, expected tree, markdown or header", style);
+                    return EXIT_PARSE;
+                }
+            };
+            print!("{}", text);
+        }
+        OutputFormat::Json | OutputFormat::JsonLines => {
+            let json: Vec<Value> = entries.iter().map(map_entry_to_json).collect();
+            if options.format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            } else {
+                for row in json.iter() {
+                    println!("{}", row);
+                }
+            }
+        }
+        OutputFormat::Csv => {
+            println!("{}", csv_row(MAP_CSV_HEADER));
+            for entry in entries.iter() {
+                println!("{}", csv_row(&map_entry_to_csv(entry)));
+            }
         }
     }
 
-    Ok(())
+    0
 }
 
-/// Prints address details to stdout.
-fn print_location(addr: &AddressLocation) {
-    let mut table: Table = Table::new("{:<} {:<}");
-
-    table.add_row(
-        Row::new()
-            .with_cell("Annotation:")
-            .with_cell(
-                address_location_to_string(addr)
-            )
-    );
-
-    table.add_row(
-        Row::new()
-            .with_cell("Virtual Address:")
-            .with_cell(
-                format!("0x{:08X}", addr.virtual_address)
-            )
-    );
-
-    table.add_row(
-        Row::new()
-            .with_cell("Physical Address:")
-            .with_cell(
-                format!("0x{:08X}", addr.physical_address)
-            )
-    );
-
-    table.add_row(
-        Row::new()
-            .with_cell("Segment:")
-            .with_cell(
-                addr.segment.map_or(
-                    "Unknown".to_string(),
-                    |(a, b)| format!("{}, {}", a, b)
-                )
-            )
-    );
-
-    table.add_row(
-        Row::new()
-            .with_cell("Region:")
-            .with_cell(
-                addr.region.map_or(
-                    "Unknown".to_string(),
-                    |(a, b)| format!("{}, {}", a, b)
-                )
-            )
-    );
-
-    for subregion in addr.subregions.iter() {
-        table.add_row(
-            Row::new()
-                .with_cell("Subregion:")
-                .with_cell(format!("{}, {}", subregion.0, subregion.1).as_str())
+/// Handles `check`, reporting the inconsistencies of the memory map, merged
+/// with the map files given. Fails when any of them is an error.
+fn check(options: &TraceOptions) -> i32 {
+    let issues: Vec<Issue> = check_map(&options.map);
+
+    match options.format {
+        OutputFormat::Table => {
+            if issues.is_empty() {
+                println!("No issues found");
+            } else {
+                let mut table: Table = Table::new("{:<} {:<} {:<} {:<}  {:<}");
+                table.add_row(
+                    Row::new()
+                        .with_cell("Severity")
+                        .with_cell("Kind")
+                        .with_cell("Table")
+                        .with_cell("Range")
+                        .with_cell("Message")
+                );
+                for issue in issues.iter() {
+                    table.add_row(
+                        Row::new()
+                            .with_cell(issue.severity)
+                            .with_cell(issue.kind)
+                            .with_cell(issue.table)
+                            .with_cell(format!("0x{:08X}-0x{:08X}", issue.start, issue.end))
+                            .with_cell(&issue.message)
+                    );
+                }
+                print!("{}", table);
+            }
+        }
+        OutputFormat::Json | OutputFormat::JsonLines => {
+            let json: Vec<Value> = issues.iter().map(issue_to_json).collect();
+            if options.format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            } else {
+                for issue in json.iter() {
+                    println!("{}", issue);
+                }
+            }
+        }
+        OutputFormat::Csv => {
+            println!("{}", csv_row(ISSUE_CSV_HEADER));
+            for issue in issues.iter() {
+                println!("{}", csv_row(&issue_to_csv(issue)));
+            }
+        }
+    }
+
+    if issues.iter().any(|issue| issue.severity == Severity::Error) {
+        EXIT_CHECK_FAILED
+    } else {
+        0
+    }
+}
+
+/// Handles `rom [file]`, printing the header of the ROM file given or of
+/// the one given with `--rom`.
+fn rom_header(args: &[String], options: &TraceOptions) -> i32 {
+    let rom: Rc<Rom> = match (args.first(), &options.rom) {
+        (Some(filename), _) => match Rom::from_file(filename) {
+            Ok(rom) => Rc::new(rom),
+            Err(e) => {
+                eprintln!("Error reading ROM {}: {}", filename, e);
+                return io_exit_status(&e);
+            }
+        },
+        (None, Some(rom)) => rom.clone(),
+        (None, None) => {
+            eprintln!("Expected a ROM file, or --rom <file>");
+            return EXIT_PARSE;
+        }
+    };
+
+    match options.format {
+        OutputFormat::Table => {
+            let mut table: Table = Table::new("{:<} {:<}");
+            for (label, value) in rom_header_fields(&rom) {
+                table.add_row(
+                    Row::new()
+                        .with_cell(format!("{}:", label))
+                        .with_cell(value)
+                );
+            }
+            print!("{}", table);
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&rom_header_to_json(&rom)).unwrap()),
+        OutputFormat::JsonLines => println!("{}", rom_header_to_json(&rom)),
+        OutputFormat::Csv => {
+            println!("{}", csv_row(&["field", "value"]));
+            for (label, value) in rom_header_fields(&rom) {
+                println!("{}", csv_row(&[label.to_lowercase().replace(' ', "_"), value]));
+            }
+        }
+    }
+
+    0
+}
+
+/// Number of instructions `disasm` disassembles when no count is given.
+const DEFAULT_DISASSEMBLY_COUNT: usize = 16;
+
+/// Handles `disasm [--rsp] [--at <address>] <address> [count]`,
+/// disassembling the instructions at the address from the ROM and memory
+/// dumps given. With `--at`, the code is disassembled as running from another
+/// address, e.g. code in ROM that the game copies to RDRAM.
+///
+/// With `--rsp`, the instructions are RSP microcode, at an RSP address or
+/// read from a CPU address, e.g. in ROM, to run from IMEM or from the RSP
+/// address given with `--at`. Microcode symbols apply instead of the symbols
+/// of the CPU.
+fn disasm(args: &[String], options: &TraceOptions, memory: &MemoryImage) -> i32 {
+    let rsp: bool = args.iter().any(|arg| arg == "--rsp");
+    let mut args: Vec<String> = args.iter().filter(|arg| *arg != "--rsp").cloned().collect();
+    let symbols: &SymbolTable = if rsp { &options.rsp_symbols } else { &options.symbols };
+    let mut at: Option<u64> = None;
+    if let Some(position) = args.iter().position(|arg| arg == "--at") {
+        let Some(text) = args.get(position + 1) else {
+            eprintln!("Expected an address after --at");
+            return EXIT_PARSE;
+        };
+        match evaluate(text, &options.map, symbols) {
+            Ok(address) => at = Some(address),
+            Err(e) => {
+                eprintln!("Invalid address {}: {}", text, e);
+                return EXIT_PARSE;
+            }
+        }
+        args.drain(position..position + 2);
+    }
+
+    let Some(text) = args.first() else {
+        eprintln!("Expected an address to disassemble");
+        return EXIT_PARSE;
+    };
+    let address: u64 = match evaluate(text, &options.map, symbols) {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("Invalid address {}: {}", text, e);
+            return EXIT_PARSE;
+        }
+    };
+    let count: usize = match args.get(1).map(|count| parse_number(count)) {
+        None => DEFAULT_DISASSEMBLY_COUNT,
+        Some(Some(count)) if count > 0 => count as usize,
+        Some(_) => {
+            eprintln!("Invalid instruction count: {}", args[1]);
+            return EXIT_PARSE;
+        }
+    };
+    if address & 3 != 0 || at.is_some_and(|at| at & 3 != 0) {
+        eprintln!("Instructions are aligned to 4 bytes: {}", format_virtual_address(at.unwrap_or(address)));
+        return EXIT_PARSE;
+    }
+    if rsp && at.is_some_and(|at| at >= RSP_VIEW_SIZE as u64) {
+        eprintln!("RSP address {} is beyond IMEM", format_virtual_address(at.unwrap_or(address)));
+        return EXIT_PARSE;
+    }
+
+    // RSP code is read through the CPU address of its RSP address
+    let (address, at): (u64, Option<u64>) = match (rsp, at) {
+        (true, _) if address < RSP_VIEW_SIZE as u64 => (rsp_to_virtual(address as u32), at.or(Some(address))),
+        (true, None) => {
+            // DMEM and IMEM run from their own RSP address, anything else from IMEM
+            let location: AddressLocation = options.map.lookup(address, &options.tlb);
+            (address, Some(location.rsp_address().unwrap_or(RSP_IMEM) as u64))
+        }
+        _ => (address, at),
+    };
+
+    let words: Vec<u32> = memory.read_words(address, count, &options.map, &options.tlb);
+    if words.is_empty() {
+        eprintln!(
+            "Nothing known at {}, expected a ROM given with --rom or a dump given with --dump",
+            format_virtual_address(address),
         );
+        return EXIT_NOT_FOUND;
+    }
+    if words.len() < count {
+        eprintln!("Only {} of {} instructions are known", words.len(), count);
+    }
+
+    let instructions: Vec<Instruction> = if rsp {
+        disassemble_rsp(&words, at.unwrap_or(address) as u32)
+    } else {
+        disassemble(&words, at.unwrap_or(address))
+    };
+    match options.format {
+        OutputFormat::Table => {
+            let mut table: Table = Table::new("{:<}  {:<}  {:<}  {:<}");
+            for instruction in instructions.iter() {
+                table.add_row(
+                    Row::new()
+                        .with_cell(instruction.format_address(instruction.address))
+                        .with_cell(format!("{:08X}", instruction.word))
+                        .with_cell(instruction.text())
+                        .with_cell(instruction.comment(&options.map, symbols, &options.tlb)
+                            .map_or(String::new(), |comment| format!("; {}", comment)))
+                );
+            }
+            // Instructions without a comment would be padded to the width of the others
+            for line in table.to_string().lines() {
+                println!("{}", line.trim_end());
+            }
+        }
+        OutputFormat::Json | OutputFormat::JsonLines => {
+            let json: Vec<Value> = instructions.iter()
+                .map(|instruction| instruction_to_json(instruction, &options.map, symbols, &options.tlb))
+                .collect();
+            if options.format == OutputFormat::Json {
+                println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            } else {
+                for instruction in json.iter() {
+                    println!("{}", instruction);
+                }
+            }
+        }
+        OutputFormat::Csv => {
+            println!("{}", csv_row(INSTRUCTION_CSV_HEADER));
+            for instruction in instructions.iter() {
+                println!("{}", csv_row(&instruction_to_csv(instruction, &options.map, symbols, &options.tlb)));
+            }
+        }
+    }
+
+    if words.len() < count {
+        EXIT_NOT_FOUND
+    } else {
+        0
     }
+}
+
+/// Handles `decode <register|address> <value> [write]`.
+fn decode(args: &[String]) -> i32 {
+    if args.len() < 2 {
+        eprintln!("Expected a register name or address and a value to decode");
+        return EXIT_PARSE;
+    }
+
+    let register: Option<&Register> = find_register(&args[0])
+        .map(|(_, register)| register)
+        .or_else(|| parse_address(&args[0]).and_then(|address| get_register(address as u32 & 0x1FFF_FFFF)));
+    let Some(register) = register else {
+        eprintln!("Unknown register: {}", args[0]);
+        return EXIT_NOT_FOUND;
+    };
 
-    print!("{}", table);
+    // Written like the values of the `VI I/O:` lines of Ares traces, e.g.
+    // `00003303`, or like addresses
+    let value: Option<u32> = parse_number(&args[1]).and_then(|value| u32::try_from(value).ok());
+    let Some(value) = value else {
+        eprintln!("Invalid value: {}", args[1]);
+        return EXIT_PARSE;
+    };
+
+    let write: bool = args.get(2).is_some_and(|arg| arg == "write");
+    print!("{}", render_decoded_register(register, value, write));
+    0
+}
+
+/// Handles `find <name>`.
+fn find(args: &[String], options: &TraceOptions) -> i32 {
+    if args.is_empty() {
+        eprintln!("Expected a name to find");
+        return EXIT_PARSE;
+    }
+
+    let query: String = args.join(" ");
+    let matches: Vec<NameMatch> = options.map.find_by_name(&query);
+    if matches.is_empty() {
+        eprintln!("Nothing named like: {}", query);
+        return EXIT_NOT_FOUND;
+    }
 
+    print!("{}", render_matches(&matches));
+    0
 }
 
 fn main() {
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Expected a file name or an address as argument");
-        exit(1);
+    let mut args: Vec<String> = env::args().collect();
+
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
     }
 
-    let arg = &args[1];
-    if arg.starts_with("0x") {
-        // Argument is considered an address
-        if let Ok(address) = u32::from_str_radix(&arg[2..], 16) {
-            let location = get_segment_region_subregion(address);
-            print_location(&location);
-        } else {
-            eprintln!("Invalid address: {}", arg);
-            exit(1);
+    let mut map: MemoryMap = MemoryMap::default();
+    while let Some(position) = args.iter().position(|arg| arg == "--map") {
+        let Some(filename) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a file name after --map");
+            exit(EXIT_PARSE);
+        };
+        if let Err(e) = map.load(&filename) {
+            eprintln!("Error reading memory map {}: {}", filename, e);
+            exit(io_exit_status(&e));
         }
-    } else {
-        // Argument is considered a filename
-        if let Err(e) = rewrite_lines_of_file(arg.clone()) {
-            eprintln!("Error rewriting lines of file {}: {}", arg, e);
-            exit(1);
+        args.drain(position..position + 2);
+    }
+    if let Some(position) = args.iter().position(|arg| arg == "--rdram") {
+        let Some(text) = args.get(position + 1) else {
+            eprintln!("Expected a size after --rdram, e.g. 4M or 8M");
+            exit(EXIT_PARSE);
+        };
+        let size: u32 = parse_size(text).map_or(0, |size| size.min(u32::MAX as u64) as u32);
+        if let Err(e) = map.set_rdram_size(size) {
+            eprintln!("Invalid --rdram {}: {}", text, e);
+            exit(EXIT_PARSE);
+        }
+        args.drain(position..position + 2);
+    }
+
+    let mut tlb: Tlb = Tlb::default();
+    if let Some(position) = args.iter().position(|arg| arg == "--tlb") {
+        let Some(filename) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a file name after --tlb");
+            exit(EXIT_PARSE);
+        };
+        tlb = Tlb::from_file(&filename).unwrap_or_else(|e| {
+            eprintln!("Error reading TLB dump {}: {}", filename, e);
+            exit(io_exit_status(&e));
+        });
+        args.drain(position..position + 2);
+    }
+
+    let mut symbols: SymbolTable = SymbolTable::default();
+    while let Some(position) = args.iter().position(|arg| arg == "--symbols") {
+        let Some(filename) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a file name after --symbols");
+            exit(EXIT_PARSE);
+        };
+        if let Err(e) = symbols.load(&filename) {
+            eprintln!("Error reading symbols {}: {}", filename, e);
+            exit(io_exit_status(&e));
+        }
+        args.drain(position..position + 2);
+    }
+
+    let mut rsp_symbols: SymbolTable = SymbolTable::default();
+    while let Some(position) = args.iter().position(|arg| arg == "--rsp-symbols") {
+        let Some(filename) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a file name after --rsp-symbols");
+            exit(EXIT_PARSE);
+        };
+        if let Err(e) = rsp_symbols.load_rsp(&filename) {
+            eprintln!("Error reading microcode symbols {}: {}", filename, e);
+            exit(io_exit_status(&e));
         }
+        args.drain(position..position + 2);
     }
 
+    let mut source: Option<Rc<SourceLines>> = None;
+    if let Some(position) = args.iter().position(|arg| arg == "--source") {
+        let Some(filename) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a file name after --source");
+            exit(EXIT_PARSE);
+        };
+        source = Some(Rc::new(SourceLines::from_file(&filename).unwrap_or_else(|e| {
+            eprintln!("Error reading debug information {}: {}", filename, e);
+            exit(io_exit_status(&e));
+        })));
+        args.drain(position..position + 2);
+    }
+
+    let mut rom: Option<Rc<Rom>> = None;
+    if let Some(position) = args.iter().position(|arg| arg == "--rom") {
+        let Some(filename) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a file name after --rom");
+            exit(EXIT_PARSE);
+        };
+        rom = Some(Rc::new(Rom::from_file(&filename).unwrap_or_else(|e| {
+            eprintln!("Error reading ROM {}: {}", filename, e);
+            exit(io_exit_status(&e));
+        })));
+        args.drain(position..position + 2);
+    }
+
+    let mut memory: MemoryImage = MemoryImage::default();
+    if let Some(rom) = &rom {
+        memory.add(ROM_BASE, Rc::from(rom.data.as_slice()));
+    }
+    while let Some(position) = args.iter().position(|arg| arg == "--dump") {
+        let Some(argument) = args.get(position + 1).cloned() else {
+            eprintln!("Expected a file name after --dump, optionally followed by @ and a physical address");
+            exit(EXIT_PARSE);
+        };
+        let (filename, address): (&str, u64) = match argument.rsplit_once('@') {
+            Some((filename, address)) => match parse_number(address) {
+                Some(address) if address <= u32::MAX as u64 => (filename, address),
+                _ => {
+                    eprintln!("Invalid --dump address: {}", address);
+                    exit(EXIT_PARSE);
+                }
+            },
+            None => (argument.as_str(), 0),
+        };
+        match std::fs::read(filename) {
+            Ok(data) => memory.add(address as u32, Rc::from(data)),
+            Err(e) => {
+                eprintln!("Error reading memory dump {}: {}", filename, e);
+                exit(io_exit_status(&e));
+            }
+        }
+        args.drain(position..position + 2);
+    }
+
+    let mut format: OutputFormat = OutputFormat::Table;
+    if let Some(position) = args.iter().position(|arg| arg == "--format") {
+        let Some(name) = args.get(position + 1) else {
+            eprintln!("Expected json, jsonl, csv or table after --format");
+            exit(EXIT_PARSE);
+        };
+        format = name.parse().unwrap_or_else(|e| {
+            eprintln!("Invalid --format: {}", e);
+            exit(EXIT_PARSE);
+        });
+        args.drain(position..position + 2);
+    }
+
+    let mut trace_format: Option<TraceFormat> = None;
+    if let Some(position) = args.iter().position(|arg| arg == "--trace-format") {
+        let Some(name) = args.get(position + 1) else {
+            eprintln!("Expected a trace format or auto after --trace-format");
+            exit(EXIT_PARSE);
+        };
+        if name != "auto" {
+            trace_format = Some(name.parse().unwrap_or_else(|e| {
+                eprintln!("Invalid --trace-format: {}", e);
+                exit(EXIT_PARSE);
+            }));
+        }
+        args.drain(position..position + 2);
+    }
+
+    let mut trace_patterns: Vec<Regex> = Vec::new();
+    while let Some(position) = args.iter().position(|arg| arg == "--trace-pattern" || arg == "--trace-patterns") {
+        let Some(value) = args.get(position + 1) else {
+            eprintln!("Expected a pattern or file name after {}", args[position]);
+            exit(EXIT_PARSE);
+        };
+        if args[position] == "--trace-pattern" {
+            match trace_pattern(value) {
+                Ok(regex) => trace_patterns.push(regex),
+                Err(e) => {
+                    eprintln!("Invalid --trace-pattern: {}", e);
+                    exit(EXIT_PARSE);
+                }
+            }
+        } else {
+            match load_trace_patterns(value) {
+                Ok(regexes) => trace_patterns.extend(regexes),
+                Err(e) => {
+                    eprintln!("Error reading trace patterns {}: {}", value, e);
+                    exit(io_exit_status(&e));
+                }
+            }
+        }
+        args.drain(position..position + 2);
+    }
+
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        exit(EXIT_PARSE);
+    }
+
+    let options: TraceOptions = TraceOptions {
+        map,
+        tlb,
+        symbols,
+        rsp_symbols,
+        source,
+        rom,
+        format,
+        trace_format,
+        trace_patterns,
+    };
+    let rest: &[String] = &args[2..];
+    let status: i32 = match args[1].as_str() {
+        "lookup" => lookup(rest, &options),
+        "annotate" => annotate(rest, &options),
+        "decode" => decode(rest),
+        "find" => find(rest, &options),
+        "table" => table(rest, &options),
+        "stats" => stats(rest, &options),
+        "check" => check(&options),
+        "rom" => rom_header(rest, &options),
+        "disasm" => disasm(rest, &options, &memory),
+        "scan" => scan(rest, &options),
+        // Without a subcommand, an address or the file name of a trace
+        arg if arg.starts_with("0x") => lookup(&args[1..], &options),
+        arg if arg == "-" || Path::new(arg).is_file() => annotate(&args[1..2], &options),
+        arg => {
+            eprintln!("Unknown command: {}", arg);
+            eprintln!("{}", USAGE);
+            EXIT_PARSE
+        }
+    };
+
+    exit(status);
 }
@@ -0,0 +1,506 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Memory map tables and address lookup
+//!
+//! Based on information the memory map documentation here:
+//! https://n64brew.dev/wiki/Memory_map
+//!
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::mapfile::{MemoryMap, RDRAM_UNPOPULATED};
+use crate::registers::{get_register, Register, REGISTER_BLOCKS};
+use crate::rom::Rom;
+use crate::rsp::physical_to_rsp;
+use crate::tlb::{Tlb, TlbTranslation};
+
+pub type Region = (
+    u32,            // start
+    u32,            // end
+    &'static str,   // short name
+    &'static str,   // long name
+);
+
+pub type Segment64 = (
+    u64,            // start
+    u64,            // end
+    &'static str,   // short name
+    &'static str,   // long name
+);
+
+/// Segments of 32-bit mode. In 64-bit mode the same segments are reached
+/// through sign-extended addresses (CKSEG0, CKSEG1, CKSSEG and CKSEG3, plus
+/// the bottom 2 GiB of XKUSEG).
+pub static SEGMENTS: &[Region] = &[
+    (0x00000000, 0x7FFFFFFF, "U", "KUSEG"),
+    (0x80000000, 0x9FFFFFFF, "0", "KSEG0"),
+    (0xA0000000, 0xBFFFFFFF, "1", "KSEG1"),
+    (0xC0000000, 0xDFFFFFFF, "S", "KSSEG"),
+    (0xE0000000, 0xFFFFFFFF, "3", "KSEG3"),
+];
+
+/// Segments only reachable in 64-bit mode. Addresses outside of these raise an
+/// address error.
+pub static SEGMENTS_64: &[Segment64] = &[
+    (0x0000_0000_0000_0000, 0x0000_00FF_FFFF_FFFF, "XU", "XKUSEG"),
+    (0x4000_0000_0000_0000, 0x4000_00FF_FFFF_FFFF, "XS", "XKSSEG"),
+    (0x8000_0000_0000_0000, 0xBFFF_FFFF_FFFF_FFFF, "XP", "XKPHYS"),
+    (0xC000_0000_0000_0000, 0xC000_00FF_7FFF_FFFF, "XK", "XKSEG"),
+];
+
+/// Built-in regions, which map files may add to, see [`crate::mapfile`].
+pub static REGIONS: &[Region] = &[
+    (0x00000000, 0x03FFFFFF, "R", "RDRAM"),
+    (0x04000000, 0x04FFFFFF, "G", "RCP"),
+    (0x05000000, 0x1FBFFFFF, "P", "PI 1/2"),
+    (0x1FC00000, 0x1FCFFFFF, "S", "SI"),
+    (0x1FD00000, 0x7FFFFFFF, "B", "PI 2/2"),
+    (0x80000000, 0xFFFFFFFF, "U", "Unmapped"),
+];
+
+/// Built-in subregions, which map files may add to, see [`crate::mapfile`].
+pub static SUBREGIONS: &[Region] = &[
+
+    // RDRAM (RDR)
+    (0x00000000, 0x03EFFFFF, "RDRM", "RDRAM memory-space"),
+    (0x03F00000, 0x03F7FFFF, "RDRR", "RDRAM registers"),
+    (0x03F80000, 0x03FFFFFF, "RDRB", "RDRAM broadcast registers"),
+
+    // RCP (RSP or RCP)
+    (0x04000000, 0x04000FFF, "RSPD", "RSP Data Memory"),
+    (0x04001000, 0x04001FFF, "RSPI", "RSP Instruction Memory"),
+    (0x04002000, 0x0403FFFF, "RSPM", "RSP DMEM/IMEM Mirrors"),
+    (0x04040000, 0x040BFFFF, "RSPR", "RSP Registers"),
+    (0x040C0000, 0x040FFFFF, "RCPU", "Unmapped/fatal"),
+    (0x04100000, 0x041FFFFF, "RDPC", "RDP Command Registers"),
+    (0x04200000, 0x042FFFFF, "RDPS", "RDP Span Registers"),
+    (0x04300000, 0x043FFFFF, "InMI", "MIPS Interface"),
+    (0x04400000, 0x044FFFFF, "InVI", "Video Interface"),
+    (0x04500000, 0x045FFFFF, "InAI", "Audio Interface"),
+    (0x04600000, 0x046FFFFF, "InPI", "Peripheral Interface"),
+    (0x04700000, 0x047FFFFF, "InRI", "RDRAM Interface"),
+    (0x04800000, 0x048FFFFF, "InSI", "Serial Interface"),
+    (0x04900000, 0x04FFFFFF, "RCPH", "Unmapped/fatal"),
+
+    // PI
+    (0x05000000, 0x05FFFFFF, "NDDR", "N64DD Registers"),
+    (0x06000000, 0x07FFFFFF, "NDDI", "N64DD IPL ROM"),
+    (0x08000000, 0x0FFFFFFF, "CSRM", "Cartridge SRAM"),
+    (0x10000000, 0x1FBFFFFF, "CROM", "Cartridge ROM"),
+
+    // SI
+    (0x1FC00000, 0x1FC007BF, "PIFR", "PIF ROM"),
+    (0x1FC007C0, 0x1FC007FF, "PIFM", "PIF RAM"),
+    (0x1FC00800, 0x1FCFFFFF, "RSVD", "Reserved"),
+
+    // PI, pt.2
+    (0x1FD00000, 0x1FFFFFFF, "UPB1", "Unused / PI BUS Domain 1"),
+    (0x20000000, 0x7FFFFFFF, "UCPA", "Unused / PI BUS Domain 1 [CPU Accessible]"),
+
+    // No device
+    (0x80000000, 0xFFFFFFFF, "UNMP", "Unmapped/fatal"),
+
+];
+
+pub type Mirror = (
+    u32,            // start
+    u32,            // end
+    u32,            // start of the mirrored area
+    u32,            // size of the mirrored area
+);
+
+/// Ranges repeating a smaller area. Register blocks repeat their registers
+/// every `stride` bytes too, see [`crate::registers::RegisterBlock`].
+pub static MIRRORS: &[Mirror] = &[
+    (0x04002000, 0x0403FFFF, 0x04000000, 0x2000), // RSP DMEM and IMEM
+];
+
+/// Short and long name of a region or subregion, borrowed from the built-in
+/// tables or owned by a [`MemoryMap`] loaded from map files.
+pub type AreaNames = (Cow<'static, str>, Cow<'static, str>);
+
+/// The address a mirrored address aliases.
+#[derive(Debug, Clone)]
+pub struct CanonicalAddress {
+    pub physical_address: u32,
+    /// Innermost subregion holding the canonical address
+    pub subregion: Option<AreaNames>,
+    /// Offset of the canonical address from the start of its subregion
+    pub offset: u32,
+    /// Register at the canonical address
+    pub register: Option<&'static Register>,
+}
+
+impl fmt::Display for CanonicalAddress {
+    /// Formats as e.g. `0x04000010 (RSPD+0x10)`, or `0x04040010 (SP_STATUS)`
+    /// for registers.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:08X}", self.physical_address)?;
+        match (self.register, &self.subregion) {
+            (Some(register), _) => write!(f, " ({})", register.name),
+            (None, Some((short, _))) => write!(f, " ({}+{:#x})", short, self.offset),
+            (None, None) => Ok(()),
+        }
+    }
+}
+
+/// Given a physical address, return the address it aliases if it lies in a
+/// mirror, e.g. 0x04003010 aliases 0x04001010 in IMEM, and 0x04042010 the
+/// `SP_STATUS` register at 0x04040010. Addresses that aren't mirrors give
+/// `None`. The subregion is named from the built-in tables, see
+/// [`MemoryMap::resolve_mirror`] for another map.
+pub fn resolve_mirror(physical_address: u32) -> Option<CanonicalAddress> {
+    MemoryMap::default().resolve_mirror(physical_address)
+}
+
+/// The physical address a mirrored physical address aliases, or `None` if it
+/// isn't in a mirror, see [`resolve_mirror`].
+pub(crate) fn mirrored_address(physical_address: u32) -> Option<u32> {
+    let mirrored: u32 = MIRRORS.iter()
+        .find(|mirror| mirror.0 <= physical_address && physical_address <= mirror.1)
+        .map(|mirror| mirror.2 + (physical_address - mirror.0) % mirror.3)
+        .or_else(|| {
+            REGISTER_BLOCKS.iter()
+                .find(|block| block.start <= physical_address && physical_address <= block.end)
+                .map(|block| block.start + (physical_address - block.start) % block.stride)
+        })?;
+    (mirrored != physical_address).then_some(mirrored)
+}
+
+impl MemoryMap {
+    /// Same as [`resolve_mirror`], naming the subregion from this map.
+    pub fn resolve_mirror(&self, physical_address: u32) -> Option<CanonicalAddress> {
+        let mirrored: u32 = mirrored_address(physical_address)?;
+        let subregion = self.subregions(mirrored).last().copied();
+        Some(CanonicalAddress {
+            physical_address: mirrored,
+            subregion: subregion.map(|subregion| (subregion.2.clone(), subregion.3.clone())),
+            offset: subregion.map_or(0, |subregion| mirrored - subregion.0),
+            register: get_register(mirrored),
+        })
+    }
+}
+
+/// Describes the location of the address by naming its segment, region, and
+/// subregion as documented in the mappings above.
+#[derive(Debug, Clone)]
+pub struct AddressLocation {
+    pub virtual_address: u64,
+    /// None if the address raises an address error or misses the TLB
+    pub physical_address: Option<u32>,
+    pub segment: Option<(&'static str, &'static str)>,
+    pub region: Option<AreaNames>,
+    pub subregions: Vec<AreaNames>,
+    pub register: Option<&'static Register>,
+    /// Cache coherency attribute of XKPHYS addresses
+    pub cache_attribute: Option<(u8, &'static str)>,
+    /// Translation of addresses in mapped segments
+    pub tlb: Option<TlbTranslation>,
+    /// Address aliased by a mirrored physical address
+    pub canonical: Option<CanonicalAddress>,
+}
+
+impl AddressLocation {
+    /// False if the address raises an address error in 64-bit mode.
+    pub fn is_valid(&self) -> bool {
+        self.segment.is_some()
+    }
+
+    /// True if the address is RDRAM beyond the installed memory, see
+    /// [`crate::mapfile::MemoryMap::set_rdram_size`].
+    pub fn is_unpopulated(&self) -> bool {
+        self.subregions.iter().any(|(short, _)| *short == RDRAM_UNPOPULATED)
+    }
+
+    /// RSP address of an address of DMEM, IMEM or their mirrors.
+    pub fn rsp_address(&self) -> Option<u32> {
+        self.physical_address.and_then(physical_to_rsp)
+    }
+
+    /// Offset in the cartridge ROM of an address of the cartridge ROM area.
+    pub fn rom_offset(&self) -> Option<u32> {
+        self.physical_address.and_then(Rom::offset)
+    }
+}
+
+/// Sign-extends a 32-bit address the way the CPU does in 32-bit mode.
+pub fn sign_extend(address: u32) -> u64 {
+    address as i32 as i64 as u64
+}
+
+/// True if the 64-bit address is the sign-extension of a 32-bit address, i.e.
+/// it lies in one of the compatibility segments also reachable in 32-bit mode.
+pub fn is_32bit_compatible(address: u64) -> bool {
+    sign_extend(address as u32) == address
+}
+
+/// Formats a virtual address with 8 digits if it is 32-bit compatible, and 16
+/// digits otherwise.
+pub fn format_virtual_address(address: u64) -> String {
+    if is_32bit_compatible(address) {
+        format!("0x{:08X}", address as u32)
+    } else {
+        format!("0x{:016X}", address)
+    }
+}
+
+/// Parse a number written as `0x` or `$` prefixed hexadecimal, bare
+/// hexadecimal or decimal. Bare numbers are hexadecimal when they hold a
+/// letter or are 8 or 16 digits long, as addresses usually are written, and
+/// decimal otherwise. Underscores may separate digits.
+pub fn parse_number(text: &str) -> Option<u64> {
+    let text: &str = text.trim();
+    if let Some(digits) = text.strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+    {
+        return u64::from_str_radix(&digits.replace('_', ""), 16).ok();
+    }
+
+    let digits: String = text.replace('_', "");
+    let is_hex: bool = digits.len() == 8
+        || digits.len() == 16
+        || digits.chars().any(|c| c.is_ascii_hexdigit() && !c.is_ascii_digit());
+    if is_hex {
+        u64::from_str_radix(&digits, 16).ok()
+    } else {
+        digits.parse::<u64>().ok()
+    }
+}
+
+/// Addresses that fit in 32 bits are sign-extended, as in 32-bit mode, while
+/// longer ones are taken as they are, e.g. `0xFFFFFFFFA4400004`.
+pub fn normalize_address(address: u64) -> u64 {
+    if address <= u32::MAX as u64 {
+        sign_extend(address as u32)
+    } else {
+        address
+    }
+}
+
+/// Parse a virtual address written as a number, see [`parse_number`], or as
+/// a negative decimal number. The address is normalized with
+/// [`normalize_address`].
+pub fn parse_address(text: &str) -> Option<u64> {
+    let text: &str = text.trim();
+    if let Some(digits) = text.strip_prefix('-') {
+        let value: i64 = digits.parse::<i64>().ok()?.checked_neg()?;
+        return (value >= i32::MIN as i64).then_some(value as u64);
+    }
+    parse_number(text).map(normalize_address)
+}
+
+/// Describes a cache coherency attribute, e.g. bits 61:59 of an XKPHYS address.
+/// The VR4300 treats every attribute other than uncached as cacheable
+/// noncoherent.
+pub fn cache_attribute_name(attribute: u8) -> &'static str {
+    match attribute {
+        2 => "Uncached",
+        _ => "Cacheable noncoherent",
+    }
+}
+
+/// True if addresses of the segment are translated by the TLB.
+fn is_tlb_mapped(segment: &str) -> bool {
+    matches!(segment, "KUSEG" | "KSSEG" | "KSEG3" | "XKUSEG" | "XKSSEG" | "XKSEG")
+}
+
+/// Given a 32-bit address, return the name of the segment, region, and
+/// subregion where the address is located, along with the register it
+/// accesses. The address is sign-extended as in 32-bit mode.
+pub fn get_segment_region_subregion(address: u32) -> AddressLocation {
+    get_segment_region_subregion_64(sign_extend(address))
+}
+
+/// Given a 64-bit address, return the name of the segment, region, and
+/// subregion where the address is located, along with the register it
+/// accesses. Addresses that are not 32-bit compatible are looked up in the
+/// 64-bit only segments, and are left without a segment if they raise an
+/// address error. No TLB entries exist, so addresses of mapped segments miss.
+pub fn get_segment_region_subregion_64(address: u64) -> AddressLocation {
+    get_segment_region_subregion_tlb(address, &Tlb::default())
+}
+
+/// Same as [`get_segment_region_subregion_64`], translating addresses of the
+/// mapped segments (KUSEG, KSSEG, KSEG3, XKUSEG, XKSSEG and XKSEG) through the
+/// given TLB. Regions and subregions are those of the built-in tables, see
+/// [`MemoryMap::lookup`] for another map.
+pub fn get_segment_region_subregion_tlb(address: u64, tlb: &Tlb) -> AddressLocation {
+    MemoryMap::default().lookup(address, tlb)
+}
+
+impl MemoryMap {
+    /// Same as [`get_segment_region_subregion_tlb`], looking up the regions
+    /// and subregions of this map.
+    pub fn lookup(&self, address: u64, tlb: &Tlb) -> AddressLocation {
+        let mut segment: Option<(&str, &str)> = None;
+        let mut physical_address: Option<u32> = None;
+        let mut cache_attribute: Option<(u8, &str)> = None;
+        let mut translation: Option<TlbTranslation> = None;
+
+        if is_32bit_compatible(address) {
+            let address: u32 = address as u32;
+
+            segment = SEGMENTS.iter()
+                .find(|seg| seg.0 <= address && address <= seg.1)
+                .map(|seg| (seg.2, seg.3));
+
+            // Remove bits about cached/uncached access
+            physical_address = Some(address & 0x1FFF_FFFF);
+
+        } else if let Some(seg) = SEGMENTS_64.iter().find(|seg| seg.0 <= address && address <= seg.1) {
+
+            if seg.3 == "XKPHYS" {
+                // Only 32 bits of physical address exist, the rest must be zero
+                if address & 0x07FF_FFFF_0000_0000 == 0 {
+                    let attribute: u8 = ((address >> 59) & 0b111) as u8;
+                    segment = Some((seg.2, seg.3));
+                    physical_address = Some(address as u32);
+                    cache_attribute = Some((attribute, cache_attribute_name(attribute)));
+                }
+            } else {
+                segment = Some((seg.2, seg.3));
+            }
+        }
+
+        if segment.is_some_and(|seg| is_tlb_mapped(seg.1)) {
+            let tlb_translation: TlbTranslation = tlb.translate(address);
+            physical_address = match tlb_translation {
+                TlbTranslation::Hit { physical_address, .. } => Some(physical_address),
+                TlbTranslation::Miss => None,
+            };
+            translation = Some(tlb_translation);
+        }
+
+        let region: Option<AreaNames> = physical_address.and_then(|address_raw| {
+            self.region(address_raw).map(|reg| (reg.2.clone(), reg.3.clone()))
+        });
+
+        let subregions: Vec<AreaNames> = physical_address.map_or(Vec::new(), |address_raw| {
+            self.subregions(address_raw).iter()
+                .map(|reg| (reg.2.clone(), reg.3.clone()))
+                .collect()
+        });
+
+        AddressLocation {
+            virtual_address: address,
+            physical_address,
+            segment,
+            region,
+            subregions,
+            register: physical_address.and_then(get_register),
+            cache_attribute,
+            tlb: translation,
+            canonical: physical_address.and_then(|address| self.resolve_mirror(address)),
+        }
+    }
+}
+
+/// Produces the short-form description of an address. The short form is meant
+/// to fit into a tight column width.
+pub fn address_location_to_string(address_location: &AddressLocation) -> String {
+    if !address_location.is_valid() {
+        return "ADDRESS_ERR".to_string();
+    }
+    if address_location.tlb == Some(TlbTranslation::Miss) {
+        return format!("{}.TLBMISS", address_location.segment.unwrap_or(("?", "?")).0);
+    }
+    let subregion_short_names: Vec<&str> = address_location.subregions.iter().map(|s| &*s.0).collect();
+    format!(
+        "{}{}.{}",
+        address_location.segment.unwrap_or(("?", "?")).0,
+        address_location.region.as_ref().map_or("?", |region| &*region.0),
+        subregion_short_names.join("."),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_segment_region_and_subregion() {
+        let location: AddressLocation = get_segment_region_subregion(0x80246000);
+        assert_eq!(location.segment, Some(("0", "KSEG0")));
+        assert_eq!(location.physical_address, Some(0x00246000));
+        assert_eq!(location.region, Some(("R".into(), "RDRAM".into())));
+        assert_eq!(location.subregions, [("RDRM".into(), "RDRAM memory-space".into())]);
+        assert_eq!(address_location_to_string(&location), "0R.RDRM");
+    }
+
+    #[test]
+    fn describes_kseg1_interface_addresses() {
+        let location: AddressLocation = get_segment_region_subregion(0xA4400010);
+        assert_eq!(location.physical_address, Some(0x04400010));
+        assert_eq!(address_location_to_string(&location), "1G.InVI");
+    }
+
+    #[test]
+    fn xkphys_addresses_carry_a_cache_attribute() {
+        let location: AddressLocation = get_segment_region_subregion_64(0x9000000004400010);
+        assert_eq!(location.segment, Some(("XP", "XKPHYS")));
+        assert_eq!(location.cache_attribute, Some((2, "Uncached")));
+        assert_eq!(location.physical_address, Some(0x04400010));
+        assert_eq!(address_location_to_string(&location), "XPG.InVI");
+    }
+
+    #[test]
+    fn addresses_outside_the_segments_raise_an_address_error() {
+        let location: AddressLocation = get_segment_region_subregion_64(0x0000010000000000);
+        assert!(!location.is_valid());
+        assert_eq!(location.physical_address, None);
+        assert_eq!(address_location_to_string(&location), "ADDRESS_ERR");
+    }
+
+    #[test]
+    fn formats_compatible_addresses_with_8_digits() {
+        assert!(is_32bit_compatible(0xFFFFFFFF80246000));
+        assert!(!is_32bit_compatible(0x0000000080246000));
+        assert_eq!(format_virtual_address(0xFFFFFFFF80246000), "0x80246000");
+        assert_eq!(format_virtual_address(0x9000000004400010), "0x9000000004400010");
+    }
+
+    #[test]
+    fn parses_numbers_like_addresses_are_written() {
+        assert_eq!(parse_number("0x10"), Some(0x10));
+        assert_eq!(parse_number("$a440_0010"), Some(0xA4400010));
+        assert_eq!(parse_number("80246000"), Some(0x80246000));
+        assert_eq!(parse_number("1f"), Some(0x1F));
+        assert_eq!(parse_number("1234"), Some(1234));
+        assert_eq!(parse_number("1_000"), Some(1000));
+        assert_eq!(parse_number("0xg"), None);
+        assert_eq!(parse_number(""), None);
+    }
+
+    #[test]
+    fn parses_addresses_sign_extended() {
+        assert_eq!(parse_address("80246000"), Some(0xFFFFFFFF80246000));
+        assert_eq!(parse_address("0x9000000004400010"), Some(0x9000000004400010));
+        assert_eq!(parse_address("-1"), Some(0xFFFFFFFFFFFFFFFF));
+        assert_eq!(parse_address("-2147483649"), None);
+        assert_eq!(normalize_address(0x00400000), 0x00400000);
+    }
+
+    #[test]
+    fn resolves_mirrors_to_the_address_they_alias() {
+        let canonical: CanonicalAddress = resolve_mirror(0x04003010).unwrap();
+        assert_eq!(canonical.physical_address, 0x04001010);
+        assert_eq!(canonical.to_string(), "0x04001010 (RSPI+0x10)");
+
+        let canonical: CanonicalAddress = resolve_mirror(0x04042010).unwrap();
+        assert_eq!(canonical.physical_address, 0x04040010);
+        assert_eq!(canonical.to_string(), "0x04040010 (SP_STATUS)");
+
+        assert!(resolve_mirror(0x04001010).is_none());
+        assert!(resolve_mirror(0x04040010).is_none());
+        assert!(resolve_mirror(0x00246000).is_none());
+    }
+
+    #[test]
+    fn lookups_carry_the_canonical_address() {
+        let location: AddressLocation = get_segment_region_subregion(0xA4002000);
+        assert_eq!(location.canonical.map(|canonical| canonical.physical_address), Some(0x04000000));
+        assert!(get_segment_region_subregion(0xA4000000).canonical.is_none());
+    }
+}
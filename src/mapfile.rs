@@ -0,0 +1,459 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Custom memory maps loaded from TOML or JSON files
+//!
+//! A map file adds regions and subregions to the built-in tables, e.g.
+//! flashcart registers, development hardware, or the heap, stack and
+//! framebuffers of a project's RDRAM layout:
+//!
+//! ```toml
+//! [[subregions]]
+//! start = 0x00100000
+//! size = 0x00200000
+//! short = "HEAP"
+//! name = "Game heap"
+//!
+//! [[regions]]
+//! start = "0x1E000000"
+//! end = "0x1E00FFFF"
+//! short = "F"
+//! name = "Flashcart registers"
+//! ```
+//!
+//! or the same as JSON, `{"regions": [...], "subregions": [...]}`. Addresses
+//! are physical, given as numbers or strings, and `size` may be given instead
+//! of `end`. `name` defaults to `short`.
+//!
+//! Files are merged in the order they are loaded, and later entries take
+//! precedence over earlier ones and over the built-ins:
+//!
+//! - An address is in the first region holding it, and added regions come
+//!   before the existing ones, so a region may be carved out of another.
+//! - An address is in every subregion holding it, listed outermost first, so
+//!   an added subregion nested in another is shown after it, e.g. `RDRM.HEAP`.
+//! - An entry with the same range as an existing one of its table replaces
+//!   it, renaming the area.
+//! - `replace = true` at the top of a file drops the entries loaded before it
+//!   from each table the file has entries for.
+//!
+//! `rdram_size = "8M"` at the top of a file sets the installed RDRAM, see
+//! [`MemoryMap::set_rdram_size`].
+//!
+//! Entries of a table must nest: an entry partially overlapping another one
+//! of its table is an error, as is an empty or inverted range.
+//!
+//! A loaded map is used through its methods, e.g. [`MemoryMap::lookup`] and
+//! [`MemoryMap::split_range`], and by [`crate::check::check_map`]. The free
+//! lookup functions, e.g. [`crate::map::get_segment_region_subregion_tlb`],
+//! use the built-in map.
+//!
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::map::{parse_number, Region, REGIONS, SUBREGIONS};
+
+/// RDRAM of a stock console.
+pub const RDRAM_STOCK_SIZE: u32 = 0x0040_0000;
+/// RDRAM with the Expansion Pak installed.
+pub const RDRAM_EXPANSION_SIZE: u32 = 0x0080_0000;
+/// Largest RDRAM the memory-space can hold, as on some development units.
+pub const RDRAM_MAX_SIZE: u32 = 0x03F0_0000;
+/// Short name of the subregion of RDRAM addresses beyond the installed
+/// memory, which read back open bus.
+pub const RDRAM_UNPOPULATED: &str = "RAMO";
+
+/// Parts of the RDRAM memory-space by the memory backing them, clipped to the
+/// installed size.
+static RDRAM_AREAS: &[Region] = &[
+    (0x0000_0000, 0x003F_FFFF, "RAMB", "Base RDRAM"),
+    (0x0040_0000, 0x007F_FFFF, "RAMX", "Expansion Pak RDRAM"),
+    (0x0080_0000, 0x03EF_FFFF, "RAMD", "Development RDRAM"),
+    (0x0000_0000, 0x03EF_FFFF, RDRAM_UNPOPULATED, "Unpopulated RDRAM (open bus)"),
+];
+
+/// A region or subregion of a [`MemoryMap`]. Like a [`Region`], but owning
+/// the names of entries loaded from map files.
+pub type MapRegion = (
+    u32,                // start
+    u32,                // end
+    Cow<'static, str>,  // short name
+    Cow<'static, str>,  // long name
+);
+
+/// The entry of a built-in table.
+pub(crate) fn built_in(region: &Region) -> MapRegion {
+    (region.0, region.1, Cow::Borrowed(region.2), Cow::Borrowed(region.3))
+}
+
+/// The regions and subregions of the memory map, the built-in ones merged
+/// with those of map files.
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    /// In order of precedence
+    pub regions: Vec<MapRegion>,
+    pub subregions: Vec<MapRegion>,
+    /// Installed RDRAM in bytes, when known
+    pub rdram_size: Option<u32>,
+}
+
+impl Default for MemoryMap {
+    /// The built-in memory map.
+    fn default() -> MemoryMap {
+        MemoryMap {
+            regions: REGIONS.iter().map(built_in).collect(),
+            subregions: SUBREGIONS.iter().map(built_in).collect(),
+            rdram_size: None,
+        }
+    }
+}
+
+impl MemoryMap {
+    /// The region holding a physical address.
+    pub fn region(&self, address: u32) -> Option<&MapRegion> {
+        self.regions.iter().find(|region| region.0 <= address && address <= region.1)
+    }
+
+    /// The subregions holding a physical address, outermost first.
+    pub fn subregions(&self, address: u32) -> Vec<&MapRegion> {
+        let mut subregions: Vec<&MapRegion> = self.subregions.iter()
+            .filter(|subregion| subregion.0 <= address && address <= subregion.1)
+            .collect();
+        subregions.sort_by_key(|subregion| Reverse(subregion.1 - subregion.0));
+        subregions
+    }
+
+    /// Set the installed RDRAM, e.g. [`RDRAM_STOCK_SIZE`] or
+    /// [`RDRAM_EXPANSION_SIZE`], a multiple of 1 MiB. The RDRAM memory-space is
+    /// then split into subregions for the base RDRAM (`RAMB`), the Expansion
+    /// Pak (`RAMX`), the memory of development units beyond it (`RAMD`), and
+    /// the unpopulated addresses past the installed size ([`RDRAM_UNPOPULATED`]).
+    /// Subregions of map files with the same range as one of these win over
+    /// it, whether they were loaded before or after.
+    pub fn set_rdram_size(&mut self, size: u32) -> Result<(), String> {
+        if size == 0 || size % 0x10_0000 != 0 || size > RDRAM_MAX_SIZE {
+            return Err(format!(
+                "invalid RDRAM size 0x{:X}, expected a multiple of 1 MiB up to {} MiB",
+                size,
+                RDRAM_MAX_SIZE >> 20,
+            ));
+        }
+
+        self.subregions.retain(|subregion| {
+            !RDRAM_AREAS.iter().any(|area| area.2 == subregion.2 && area.3 == subregion.3)
+        });
+        let last: u32 = size - 1;
+        for area in RDRAM_AREAS.iter() {
+            let (start, end) = if area.2 == RDRAM_UNPOPULATED {
+                (size, area.1)
+            } else {
+                (area.0, area.1.min(last))
+            };
+            let taken: bool = self.subregions.iter().any(|subregion| subregion.0 == start && subregion.1 == end);
+            if start <= end && !taken {
+                merge_entry(&mut self.subregions, (start, end, Cow::Borrowed(area.2), Cow::Borrowed(area.3)))?;
+            }
+        }
+
+        self.rdram_size = Some(size);
+        Ok(())
+    }
+
+    /// Merge the entries of a map file, given as TOML or JSON, see the
+    /// [module documentation](self) for the format and precedence rules.
+    pub fn parse(&mut self, text: &str) -> io::Result<()> {
+        let root: Value = match text.trim_start().chars().next() {
+            Some('{') => serde_json::from_str(text).map_err(|e| invalid_data(e.to_string()))?,
+            _ => toml::from_str(text).map_err(|e| {
+                let line: usize = e.span().map_or(0, |span| text[..span.start].matches('\n').count() + 1);
+                invalid_data(format!("line {}: {}", line, e.message().trim().replace('\n', ", ")))
+            })?,
+        };
+        let Value::Object(object) = &root else {
+            return Err(invalid_data("expected an object".to_string()));
+        };
+
+        let keys = ["replace", "rdram_size", "regions", "subregions"];
+        if let Some(key) = object.keys().find(|key| !keys.contains(&key.as_str())) {
+            return Err(invalid_data(format!(
+                "unknown key `{}`, expected regions, subregions, rdram_size or replace",
+                key,
+            )));
+        }
+        let replace: bool = match object.get("replace") {
+            None => false,
+            Some(Value::Bool(replace)) => *replace,
+            Some(_) => return Err(invalid_data("`replace` must be true or false".to_string())),
+        };
+
+        let tables: [(&str, &mut Vec<MapRegion>); 2] = [
+            ("regions", &mut self.regions),
+            ("subregions", &mut self.subregions),
+        ];
+        for (key, table) in tables {
+            let Some(entries) = object.get(key) else {
+                continue;
+            };
+            let entries: &Vec<Value> = entries.as_array()
+                .ok_or_else(|| invalid_data(format!("`{}` must be an array", key)))?;
+
+            if replace && !entries.is_empty() {
+                table.clear();
+            }
+            for (position, entry) in entries.iter().enumerate() {
+                let region: MapRegion = parse_entry(entry)
+                    .map_err(|message| invalid_data(format!("{} {}: {}", key, position, message)))?;
+                merge_entry(table, region)
+                    .map_err(|message| invalid_data(format!("{} {}: {}", key, position, message)))?;
+            }
+        }
+
+        if let Some(size) = object.get("rdram_size") {
+            let size: u64 = match size {
+                Value::Number(number) => number.as_u64(),
+                Value::String(text) => parse_size(text),
+                _ => None,
+            }.ok_or_else(|| invalid_data("invalid `rdram_size`".to_string()))?;
+            self.set_rdram_size(size.min(u32::MAX as u64) as u32).map_err(invalid_data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Merge the entries of a map file, see [`MemoryMap::parse`].
+    pub fn load<P: AsRef<Path>>(&mut self, filename: P) -> io::Result<()> {
+        self.parse(&fs::read_to_string(filename)?)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads a JSON number or a number string, see [`parse_number`].
+fn json_address(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(text) => parse_number(text),
+        _ => None,
+    }
+}
+
+/// Parse a size in bytes, written as a number (see [`parse_number`]) or with
+/// a `K`, `KiB`, `M` or `MiB` suffix, e.g. `8M`.
+pub fn parse_size(text: &str) -> Option<u64> {
+    let text: &str = text.trim();
+    let units: [(&str, u64); 4] = [("MiB", 1 << 20), ("KiB", 1 << 10), ("M", 1 << 20), ("K", 1 << 10)];
+    for (suffix, unit) in units {
+        let digits: Option<&str> = text.strip_suffix(suffix)
+            .or_else(|| text.strip_suffix(suffix.to_lowercase().as_str()));
+        if let Some(digits) = digits {
+            return digits.trim().parse::<u64>().ok()?.checked_mul(unit);
+        }
+    }
+    parse_number(text)
+}
+
+fn parse_entry(entry: &Value) -> Result<MapRegion, String> {
+    let Value::Object(object) = entry else {
+        return Err("expected an object".to_string());
+    };
+    if let Some(key) = object.keys().find(|key| !matches!(key.as_str(), "start" | "end" | "size" | "short" | "name")) {
+        return Err(format!("unknown key `{}`, expected start, end, size, short or name", key));
+    }
+
+    let address = |name: &str| -> Result<Option<u64>, String> {
+        entry.get(name)
+            .map(|value| json_address(value).ok_or_else(|| format!("invalid `{}`", name)))
+            .transpose()
+    };
+    let text = |name: &str| -> Result<Option<&str>, String> {
+        entry.get(name)
+            .map(|value| value.as_str().ok_or_else(|| format!("`{}` must be a string", name)))
+            .transpose()
+    };
+
+    let start: u64 = address("start")?.ok_or("missing `start`")?;
+    let end: u64 = match (address("end")?, address("size")?) {
+        (Some(end), None) => end,
+        (None, Some(0)) => return Err("empty range".to_string()),
+        (None, Some(size)) => start.saturating_add(size - 1),
+        (Some(_), Some(_)) => return Err("expected either `end` or `size`, not both".to_string()),
+        (None, None) => return Err("missing `end` or `size`".to_string()),
+    };
+    if end > u32::MAX as u64 {
+        return Err(format!("range 0x{:08X}-0x{:X} exceeds the 32-bit physical address space", start, end));
+    }
+    if end < start {
+        return Err(format!("range 0x{:08X}-0x{:08X} ends before it starts", start, end));
+    }
+
+    let short: &str = text("short")?.ok_or("missing `short`")?;
+    if short.is_empty() || short.contains(|c: char| c.is_whitespace() || c == '.') {
+        return Err(format!("invalid short name `{}`, expected no spaces or dots", short));
+    }
+    let name: &str = text("name")?.unwrap_or(short);
+
+    Ok((start as u32, end as u32, Cow::Owned(short.to_string()), Cow::Owned(name.to_string())))
+}
+
+/// Add an entry to a table with the precedence rules of map files.
+fn merge_entry(table: &mut Vec<MapRegion>, region: MapRegion) -> Result<(), String> {
+    if let Some(existing) = table.iter_mut().find(|existing| existing.0 == region.0 && existing.1 == region.1) {
+        *existing = region;
+        return Ok(());
+    }
+
+    let overlapping = table.iter().find(|existing| {
+        let overlaps: bool = existing.0 <= region.1 && region.0 <= existing.1;
+        let nested: bool = (existing.0 <= region.0 && region.1 <= existing.1)
+            || (region.0 <= existing.0 && existing.1 <= region.1);
+        overlaps && !nested
+    });
+    if let Some(existing) = overlapping {
+        return Err(format!(
+            "{} 0x{:08X}-0x{:08X} partially overlaps {} 0x{:08X}-0x{:08X}",
+            region.2, region.0, region.1, existing.2, existing.0, existing.1,
+        ));
+    }
+
+    table.insert(0, region);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::address_location_to_string;
+    use crate::tlb::Tlb;
+
+    fn annotation(map: &MemoryMap, address: u64) -> String {
+        address_location_to_string(&map.lookup(address, &Tlb::default()))
+    }
+
+    fn parse(text: &str) -> io::Result<MemoryMap> {
+        let mut map: MemoryMap = MemoryMap::default();
+        map.parse(text)?;
+        Ok(map)
+    }
+
+    #[test]
+    fn nests_added_subregions_and_carves_out_regions() {
+        let toml: &str = "[[subregions]]\n\
+                          start = 0x00100000\n\
+                          size = 0x00200000\n\
+                          short = \"HEAP\"\n\
+                          name = \"Game heap\"\n\
+                          \n\
+                          [[regions]]\n\
+                          start = \"0x1E000000\"\n\
+                          end = \"0x1E00FFFF\"\n\
+                          short = \"F\"\n";
+        let map: MemoryMap = parse(toml).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80100000), "0R.RDRM.HEAP");
+        assert_eq!(annotation(&map, 0xFFFFFFFF80300000), "0R.RDRM");
+        assert_eq!(annotation(&map, 0xFFFFFFFFBE000010), "1F.CROM");
+        assert_eq!(annotation(&map, 0xFFFFFFFFBE010000), "1P.CROM");
+        assert_eq!(map.region(0x1E000000), Some(&(0x1E000000, 0x1E00FFFF, "F".into(), "F".into())));
+    }
+
+    #[test]
+    fn later_entries_rename_or_replace_earlier_ones() {
+        let mut map: MemoryMap = parse(r#"{"subregions": [{"start": 1048576, "size": "0x200000", "short": "HEAP"}]}"#).unwrap();
+        map.parse(r#"{"subregions": [{"start": "0x00100000", "end": "0x002FFFFF", "short": "POOL"}]}"#).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80100000), "0R.RDRM.POOL");
+
+        map.parse(r#"{"replace": true, "subregions": [{"start": 0, "end": "0x3FFFFF", "short": "LOW"}]}"#).unwrap();
+        assert_eq!(map.subregions, vec![(0, 0x3FFFFF, "LOW".into(), "LOW".into())]);
+        assert_eq!(map.regions, MemoryMap::default().regions);
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let error = |text: &str| parse(text).unwrap_err().to_string();
+        assert_eq!(
+            error("x = 1"),
+            "unknown key `x`, expected regions, subregions, rdram_size or replace",
+        );
+        assert_eq!(error("[[regions]]\nstart = 0\n"), "regions 0: missing `end` or `size`");
+        assert_eq!(error("[[regions]]\nstart = 0x\n"), "line 2: invalid hexadecimal integer");
+        assert_eq!(
+            error(r#"{"subregions": [{"start": "0x03E00000", "size": "0x200000", "short": "TAIL"}]}"#),
+            "subregions 0: TAIL 0x03E00000-0x03FFFFFF partially overlaps RDRM 0x00000000-0x03EFFFFF",
+        );
+        assert_eq!(
+            error(r#"{"regions": [{"start": 16, "end": 15, "short": "X"}]}"#),
+            "regions 0: range 0x00000010-0x0000000F ends before it starts",
+        );
+        assert_eq!(
+            error(r#"{"regions": [{"start": 0, "size": 1, "short": "A B"}]}"#),
+            "regions 0: invalid short name `A B`, expected no spaces or dots",
+        );
+    }
+
+    #[test]
+    fn parses_sizes_with_units() {
+        assert_eq!(parse_size("8M"), Some(0x800000));
+        assert_eq!(parse_size("4 MiB"), Some(0x400000));
+        assert_eq!(parse_size("64k"), Some(0x10000));
+        assert_eq!(parse_size("0x1000"), Some(0x1000));
+        assert_eq!(parse_size("1G"), None);
+    }
+
+    #[test]
+    fn splits_rdram_by_the_installed_size() {
+        let mut map: MemoryMap = MemoryMap::default();
+        map.set_rdram_size(RDRAM_STOCK_SIZE).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80100000), "0R.RDRM.RAMB");
+        assert_eq!(annotation(&map, 0xFFFFFFFF80500000), "0R.RDRM.RAMO");
+        assert!(map.lookup(0xFFFFFFFF80500000, &Tlb::default()).is_unpopulated());
+
+        // Changing the size replaces the areas of the previous one
+        map.set_rdram_size(RDRAM_EXPANSION_SIZE).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80500000), "0R.RDRM.RAMX");
+        assert_eq!(annotation(&map, 0xFFFFFFFF80900000), "0R.RDRM.RAMO");
+        assert!(!map.lookup(0xFFFFFFFF80500000, &Tlb::default()).is_unpopulated());
+        assert_eq!(map.rdram_size, Some(RDRAM_EXPANSION_SIZE));
+
+        map.set_rdram_size(RDRAM_MAX_SIZE).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80900000), "0R.RDRM.RAMD");
+        assert!(!map.subregions.iter().any(|subregion| subregion.2 == RDRAM_UNPOPULATED));
+    }
+
+    #[test]
+    fn subregions_of_map_files_win_over_rdram_areas() {
+        let heap: &str = "[[subregions]]\nstart = 0\nend = 0x003FFFFF\nshort = \"heap\"\n";
+        let mut map: MemoryMap = parse(heap).unwrap();
+        map.set_rdram_size(RDRAM_STOCK_SIZE).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80100000), "0R.RDRM.heap");
+        assert_eq!(annotation(&map, 0xFFFFFFFF80500000), "0R.RDRM.RAMO");
+
+        let mut map: MemoryMap = MemoryMap::default();
+        map.set_rdram_size(RDRAM_STOCK_SIZE).unwrap();
+        map.parse(heap).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80100000), "0R.RDRM.heap");
+
+        // Resizing keeps them too
+        map.set_rdram_size(RDRAM_EXPANSION_SIZE).unwrap();
+        assert_eq!(annotation(&map, 0xFFFFFFFF80100000), "0R.RDRM.heap");
+        assert_eq!(annotation(&map, 0xFFFFFFFF80500000), "0R.RDRM.RAMX");
+    }
+
+    #[test]
+    fn rejects_rdram_sizes_off_a_mebibyte() {
+        let mut map: MemoryMap = MemoryMap::default();
+        assert!(map.set_rdram_size(0).is_err());
+        assert!(map.set_rdram_size(0x180000).is_err());
+        assert_eq!(
+            map.set_rdram_size(0x4000000),
+            Err("invalid RDRAM size 0x4000000, expected a multiple of 1 MiB up to 63 MiB".to_string()),
+        );
+        map.parse("rdram_size = \"4M\"").unwrap();
+        assert_eq!(map.rdram_size, Some(RDRAM_STOCK_SIZE));
+    }
+}
@@ -0,0 +1,333 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Machine-readable output of address lookups
+//!
+//! Addresses are written as hexadecimal strings rather than numbers, as 64-bit
+//! values don't survive the double precision numbers of most JSON parsers.
+//!
+//! The schema of a location is stable: fields are only ever added, and absent
+//! values are `null` (or an empty array) rather than left out.
+//!
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde_json::{json, Value};
+
+use crate::map::{address_location_to_string, format_virtual_address, AddressLocation};
+use crate::range::{range_size, RangePiece};
+use crate::rom::{format_rom_bytes, Rom};
+use crate::rsp::format_rsp_address;
+use crate::source::{SourceFrame, SourceLines};
+use crate::symbols::SymbolTable;
+use crate::tlb::TlbTranslation;
+
+/// How lookups and trace annotations are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable tables and annotated lines
+    #[default]
+    Table,
+    /// A single JSON document
+    Json,
+    /// One JSON object per line
+    JsonLines,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<OutputFormat, String> {
+        match text.to_lowercase().as_str() {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            "csv" => Ok(OutputFormat::Csv),
+            _ => Err(format!("unknown output format `{}`, expected json, jsonl, csv or table", text)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputFormat::Table => write!(f, "table"),
+            OutputFormat::Json => write!(f, "json"),
+            OutputFormat::JsonLines => write!(f, "jsonl"),
+            OutputFormat::Csv => write!(f, "csv"),
+        }
+    }
+}
+
+/// Number of bytes of the ROM file shown at an address.
+pub const ROM_BYTES: usize = 16;
+
+fn name_pair(short: &str, name: &str) -> Value {
+    json!({ "short": short, "name": name })
+}
+
+fn source_frame_to_json(frame: &SourceFrame) -> Value {
+    json!({ "function": frame.function, "file": frame.file, "line": frame.line })
+}
+
+/// The location of an address as a JSON object with the fields `annotation`,
+/// `virtual`, `physical`, `segment`, `cache_attribute`, `tlb`, `region`,
+/// `subregions`, `register`, `symbol`, `source`, `canonical`, the address
+/// a mirror aliases, `rom`, the offset in the cartridge ROM with the bytes
+/// of the ROM file there, and `rsp`, the address in the RSP view of DMEM and
+/// IMEM.
+pub fn location_to_json(
+    location: &AddressLocation,
+    symbols: &SymbolTable,
+    source: Option<&SourceLines>,
+    rom: Option<&Rom>,
+) -> Value {
+    let tlb: Value = match location.tlb {
+        Some(TlbTranslation::Hit { index, page_size, asid, global, valid, dirty, cache_attribute, .. }) => json!({
+            "hit": true,
+            "index": index,
+            "page_size": page_size,
+            "asid": asid,
+            "global": global,
+            "valid": valid,
+            "dirty": dirty,
+            "cache_attribute": cache_attribute,
+        }),
+        Some(TlbTranslation::Miss) => json!({ "hit": false }),
+        None => Value::Null,
+    };
+
+    let frames: Vec<SourceFrame> = source.map_or(Vec::new(), |source| source.lookup(location.virtual_address));
+
+    json!({
+        "annotation": address_location_to_string(location),
+        "virtual": format_virtual_address(location.virtual_address),
+        "physical": location.physical_address.map(|address| format!("0x{:08X}", address)),
+        "segment": location.segment.map(|(short, name)| name_pair(short, name)),
+        "cache_attribute": location.cache_attribute
+            .map(|(attribute, description)| json!({ "value": attribute, "name": description })),
+        "tlb": tlb,
+        "region": location.region.as_ref().map(|(short, name)| name_pair(short, name)),
+        "subregions": location.subregions.iter()
+            .map(|(short, name)| name_pair(short, name))
+            .collect::<Vec<Value>>(),
+        "register": location.register.map(|register| json!({
+            "name": register.name,
+            "description": register.description,
+            "access": register.access.to_string(),
+            "width": register.width,
+        })),
+        "symbol": symbols.describe(location.virtual_address),
+        "source": frames.iter().map(source_frame_to_json).collect::<Vec<Value>>(),
+        "canonical": location.canonical.as_ref().map(|canonical| json!({
+            "physical": format!("0x{:08X}", canonical.physical_address),
+            "subregion": canonical.subregion.as_ref().map(|(short, name)| name_pair(short, name)),
+            "offset": canonical.offset,
+            "register": canonical.register.map(|register| register.name),
+        })),
+        "rom": location.rom_offset().map(|offset| json!({
+            "offset": format!("0x{:08X}", offset),
+            "field": Rom::describe_offset(offset),
+            "bytes": rom.map(|rom| rom.bytes(offset, ROM_BYTES))
+                .filter(|bytes| !bytes.is_empty())
+                .map(format_rom_bytes),
+        })),
+        "rsp": location.rsp_address().map(format_rsp_address),
+    })
+}
+
+/// Column names of [`location_to_csv`].
+pub const LOCATION_CSV_HEADER: &[&str] = &[
+    "annotation",
+    "virtual",
+    "physical",
+    "segment",
+    "region",
+    "subregions",
+    "register",
+    "symbol",
+    "source",
+    "canonical",
+    "rom_offset",
+    "rsp",
+];
+
+/// The location of an address as CSV fields, see [`LOCATION_CSV_HEADER`].
+/// Names are the short names, subregions are joined with `.` and source
+/// locations with ` < ` as in the annotations. The canonical address of
+/// mirrors is physical.
+pub fn location_to_csv(
+    location: &AddressLocation,
+    symbols: &SymbolTable,
+    source: Option<&SourceLines>,
+) -> Vec<String> {
+    vec![
+        address_location_to_string(location),
+        format_virtual_address(location.virtual_address),
+        location.physical_address.map_or(String::new(), |address| format!("0x{:08X}", address)),
+        location.segment.map_or(String::new(), |(short, _)| short.to_string()),
+        location.region.as_ref().map_or(String::new(), |(short, _)| short.to_string()),
+        location.subregions.iter().map(|(short, _)| &**short).collect::<Vec<&str>>().join("."),
+        location.register.map_or(String::new(), |register| register.name.to_string()),
+        symbols.describe(location.virtual_address).unwrap_or_default(),
+        source.and_then(|source| source.describe(location.virtual_address)).unwrap_or_default(),
+        location.canonical.as_ref().map_or(String::new(), |canonical| format!("0x{:08X}", canonical.physical_address)),
+        location.rom_offset().map_or(String::new(), |offset| format!("0x{:08X}", offset)),
+        location.rsp_address().map_or(String::new(), format_rsp_address),
+    ]
+}
+
+/// A range split into pieces as a JSON object with the fields `start`, `end`
+/// and `size` of the whole range, and `pieces`, each with its own `start`,
+/// `end`, `size`, `physical_start`, `physical_end`, `location` (see
+/// [`location_to_json`]) and `warnings`.
+pub fn range_to_json(
+    pieces: &[RangePiece],
+    symbols: &SymbolTable,
+    source: Option<&SourceLines>,
+    rom: Option<&Rom>,
+) -> Value {
+    let (Some(first), Some(last)) = (pieces.first(), pieces.last()) else {
+        return Value::Null;
+    };
+
+    let pieces: Vec<Value> = pieces.iter()
+        .map(|piece| {
+            let physical: Option<(u32, u32)> = piece.physical_range();
+            json!({
+                "start": format_virtual_address(piece.start),
+                "end": format_virtual_address(piece.end),
+                "size": piece.size(),
+                "physical_start": physical.map(|(start, _)| format!("0x{:08X}", start)),
+                "physical_end": physical.map(|(_, end)| format!("0x{:08X}", end)),
+                "location": location_to_json(&piece.location, symbols, source, rom),
+                "warnings": piece.warnings,
+            })
+        })
+        .collect();
+
+    json!({
+        "start": format_virtual_address(first.start),
+        "end": format_virtual_address(last.end),
+        "size": range_size(first.start, last.end),
+        "pieces": pieces,
+    })
+}
+
+/// Column names following [`LOCATION_CSV_HEADER`] in the rows of range
+/// pieces, see [`range_piece_to_csv`]. Single addresses leave them empty.
+pub const RANGE_CSV_HEADER: &[&str] = &["end", "size", "warnings"];
+
+/// A piece of a range as CSV fields: the location of its first address, then
+/// its last address, its size in bytes and its warnings joined with `; `.
+pub fn range_piece_to_csv(piece: &RangePiece, symbols: &SymbolTable, source: Option<&SourceLines>) -> Vec<String> {
+    let mut fields: Vec<String> = location_to_csv(&piece.location, symbols, source);
+    fields.push(format_virtual_address(piece.end));
+    fields.push(piece.size().to_string());
+    fields.push(piece.warnings.join("; "));
+    fields
+}
+
+/// Joins fields into a CSV row, quoting fields as RFC 4180 requires.
+pub fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    fields.iter()
+        .map(|field| {
+            let field: &str = field.as_ref();
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Writes records of the machine-readable formats, keeping track of the
+/// separators a JSON array needs and the CSV header.
+pub(crate) struct RecordWriter<W: Write> {
+    pub(crate) writer: W,
+    format: OutputFormat,
+    records: usize,
+}
+
+impl<W: Write> RecordWriter<W> {
+    pub(crate) fn new(writer: W, format: OutputFormat, csv_header: &[&str]) -> io::Result<RecordWriter<W>> {
+        let mut records = RecordWriter { writer, format, records: 0 };
+        match format {
+            OutputFormat::Json => write!(records.writer, "[")?,
+            OutputFormat::Csv => writeln!(records.writer, "{}", csv_row(csv_header))?,
+            _ => {}
+        }
+        Ok(records)
+    }
+
+    pub(crate) fn json(&mut self, record: &Value) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Json => {
+                let separator: &str = if self.records == 0 { "\n" } else { ",\n" };
+                write!(self.writer, "{}{}", separator, record)?;
+            }
+            OutputFormat::JsonLines => writeln!(self.writer, "{}", record)?,
+            _ => {}
+        }
+        self.records += 1;
+        Ok(())
+    }
+
+    pub(crate) fn csv(&mut self, fields: &[String]) -> io::Result<()> {
+        self.records += 1;
+        writeln!(self.writer, "{}", csv_row(fields))
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            let separator: &str = if self.records == 0 { "" } else { "\n" };
+            writeln!(self.writer, "{}]", separator)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::get_segment_region_subregion;
+
+    #[test]
+    fn output_formats_round_trip_through_their_names() {
+        for format in [OutputFormat::Table, OutputFormat::Json, OutputFormat::JsonLines, OutputFormat::Csv] {
+            assert_eq!(format.to_string().parse::<OutputFormat>(), Ok(format));
+        }
+        assert_eq!("NDJSON".parse::<OutputFormat>(), Ok(OutputFormat::JsonLines));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn quotes_csv_fields_only_when_needed() {
+        assert_eq!(csv_row(&["a", "b c", ""]), "a,b c,");
+        assert_eq!(csv_row(&["t0,v0", "say \"hi\"", "two\nlines"]), "\"t0,v0\",\"say \"\"hi\"\"\",\"two\nlines\"");
+    }
+
+    #[test]
+    fn writes_locations_as_json_and_csv() {
+        let location: AddressLocation = get_segment_region_subregion(0xA4400004);
+        let symbols: SymbolTable = SymbolTable::default();
+
+        let value: Value = location_to_json(&location, &symbols, None, None);
+        assert_eq!(value["annotation"], "1G.InVI");
+        assert_eq!(value["virtual"], "0xA4400004");
+        assert_eq!(value["physical"], "0x04400004");
+        assert_eq!(value["segment"], json!({ "short": "1", "name": "KSEG1" }));
+        assert_eq!(value["register"]["name"], "VI_ORIGIN");
+        assert_eq!(value["symbol"], Value::Null);
+        assert_eq!(value["source"], json!([]));
+
+        let fields: Vec<String> = location_to_csv(&location, &symbols, None);
+        assert_eq!(fields.len(), LOCATION_CSV_HEADER.len());
+        assert_eq!(fields[..7], ["1G.InVI", "0xA4400004", "0x04400004", "1", "G", "InVI", "VI_ORIGIN"]);
+    }
+}
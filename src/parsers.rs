@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Instruction line shapes of the traces of emulators and debuggers
+//!
+//! Each format is a regular expression with named captures: `pc`, the
+//! address of the instruction, `rest`, the instruction as printed, and
+//! optionally `prefix`, the processor the line is from, and `addr`, the
+//! effective address of a load or store. Addresses of 8 hexadecimal digits or
+//! fewer are sign-extended as in 32-bit mode.
+//!
+//! When no format is given, it is detected from the first lines of a trace,
+//! see [`TraceFormat::detect`]. Patterns of the same captures may be given
+//! instead for the traces of other tools, see [`trace_pattern`].
+//!
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use regex::Regex;
+
+/// Names of the captures of an instruction line pattern.
+pub const CAPTURE_NAMES: [&str; 4] = ["pc", "prefix", "rest", "addr"];
+
+/// Number of lines [`TraceFormat::detect`] looks at.
+pub const DETECT_LINES: usize = 64;
+
+/// A trace format understood by the annotation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// Ares, e.g. `CPU  ffffffff80246000  lui     sp,$8020`, with the RSP
+    /// lines and the effective addresses of loads and stores
+    #[default]
+    Ares,
+    /// cen64, e.g. `[VR4300] 0x80246000: lui $sp,0x8020`
+    Cen64,
+    /// The mupen64plus debugger, e.g. `80246000 [3C1D8020] lui $sp, 0x8020`
+    Mupen64Plus,
+    /// Project64 trace logs, e.g. `80246000: 3C1D8020 LUI SP, 0x8020`,
+    /// optionally preceded by a time of day
+    Project64,
+    /// simple64, e.g. `[80246000] lui sp, 0x8020`
+    Simple64,
+    /// gdb `display/i $pc` and `x/i` output, e.g.
+    /// `=> 0x80246000 <main+4>: lui sp,0x8020`
+    Gdb,
+}
+
+impl TraceFormat {
+    /// All formats, in the order detection prefers them.
+    pub const ALL: [TraceFormat; 6] = [
+        TraceFormat::Ares,
+        TraceFormat::Cen64,
+        TraceFormat::Mupen64Plus,
+        TraceFormat::Project64,
+        TraceFormat::Simple64,
+        TraceFormat::Gdb,
+    ];
+
+    /// The regular expression matching the instruction lines, see the
+    /// captures in the [module documentation](self).
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            TraceFormat::Ares => r"^(?P<prefix>[A-Z]{3})\s*(?P<pc>[a-f0-9]{16})\s*(?P<rest>.*)$",
+            TraceFormat::Cen64 => r"^\[(?P<prefix>VR4300)\]\s+0x(?P<pc>[0-9a-fA-F]{8}|[0-9a-fA-F]{16}):\s+(?P<rest>.*)$",
+            TraceFormat::Mupen64Plus => r"^(?P<pc>[0-9a-fA-F]{8})\s+\[[0-9a-fA-F]{8}\]\s+(?P<rest>.*)$",
+            TraceFormat::Project64 => {
+                r"^(?:\d{2}:\d{2}:\d{2}\.\d{3}\s+)?(?P<pc>[0-9A-F]{8}):\s+[0-9A-F]{8}\s+(?P<rest>.*)$"
+            }
+            TraceFormat::Simple64 => r"^\[(?P<pc>[0-9a-fA-F]{8}|[0-9a-fA-F]{16})\]\s+(?P<rest>.*)$",
+            TraceFormat::Gdb => r"^(?:=>)?\s+0x(?P<pc>[0-9a-fA-F]{1,16})(?:\s+<[^>]*>)?:\s+(?P<rest>.*)$",
+        }
+    }
+
+    /// Compiles [`TraceFormat::pattern`].
+    pub fn regex(&self) -> Regex {
+        Regex::new(self.pattern()).unwrap()
+    }
+
+    /// The format matching the most of the lines, preferring the earlier
+    /// formats of [`TraceFormat::ALL`] on ties. Ares when none matches, its
+    /// register access lines being annotated even without instructions.
+    pub fn detect<S: AsRef<str>>(lines: &[S]) -> TraceFormat {
+        let mut best: (TraceFormat, usize) = (TraceFormat::Ares, 0);
+        for format in TraceFormat::ALL {
+            let regex: Regex = format.regex();
+            let matches: usize = lines.iter().filter(|line| regex.is_match(line.as_ref())).count();
+            if matches > best.1 {
+                best = (format, matches);
+            }
+        }
+        best.0
+    }
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<TraceFormat, String> {
+        TraceFormat::ALL.iter()
+            .find(|format| format.to_string() == text.to_lowercase())
+            .copied()
+            .ok_or_else(|| format!(
+                "unknown trace format `{}`, expected {} or auto",
+                text,
+                TraceFormat::ALL.map(|format| format.to_string()).join(", "),
+            ))
+    }
+}
+
+impl fmt::Display for TraceFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceFormat::Ares => write!(f, "ares"),
+            TraceFormat::Cen64 => write!(f, "cen64"),
+            TraceFormat::Mupen64Plus => write!(f, "mupen64plus"),
+            TraceFormat::Project64 => write!(f, "project64"),
+            TraceFormat::Simple64 => write!(f, "simple64"),
+            TraceFormat::Gdb => write!(f, "gdb"),
+        }
+    }
+}
+
+/// Compiles a user-defined instruction line pattern. It must capture `pc`,
+/// and may capture `prefix`, `rest` and `addr` only besides it, e.g.
+/// `^(?P<pc>[0-9A-F]{8}) (?P<rest>.*)$`.
+pub fn trace_pattern(pattern: &str) -> Result<Regex, String> {
+    let regex: Regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+    if let Some(name) = regex.capture_names().flatten().find(|name| !CAPTURE_NAMES.contains(name)) {
+        return Err(format!("unknown capture `{}`, expected {}", name, CAPTURE_NAMES.join(", ")));
+    }
+    if !regex.capture_names().flatten().any(|name| name == "pc") {
+        return Err("missing capture `pc`".to_string());
+    }
+    Ok(regex)
+}
+
+/// Reads instruction line patterns from a file, one per line, see
+/// [`trace_pattern`]. Empty lines and lines starting with `#` are skipped.
+pub fn load_trace_patterns<P: AsRef<Path>>(filename: P) -> io::Result<Vec<Regex>> {
+    let text: String = fs::read_to_string(filename)?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(number, line)| {
+            trace_pattern(line).map_err(|e| invalid_data(format!("line {}: {}", number + 1, e)))
+        })
+        .collect()
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_each_format_from_its_lines() {
+        let traces: [(TraceFormat, &[&str]); 6] = [
+            (TraceFormat::Ares, &["CPU  ffffffff80246000  lui     sp,$8020"]),
+            (TraceFormat::Cen64, &["[VR4300] 0x80246000: lui $sp,0x8020"]),
+            (TraceFormat::Mupen64Plus, &["80246000 [3C1D8020] lui $sp, 0x8020"]),
+            (TraceFormat::Project64, &["12:01:02.345 80246000: 3C1D8020 LUI SP, 0x8020", "80246004: 0C0A0000 JAL 0x80280000"]),
+            (TraceFormat::Simple64, &["[80246000] lui sp, 0x8020"]),
+            (TraceFormat::Gdb, &["1: x/i $pc", "=> 0x80246004 <main>:\tlui\tsp,0x8020"]),
+        ];
+        for (format, lines) in traces {
+            assert_eq!(TraceFormat::detect(lines), format);
+        }
+        assert_eq!(TraceFormat::detect(&["VI I/O: VI_CONTROL <= 00003303"]), TraceFormat::Ares);
+    }
+
+    #[test]
+    fn trace_formats_round_trip_through_their_names() {
+        for format in TraceFormat::ALL {
+            assert_eq!(format.to_string().parse::<TraceFormat>(), Ok(format));
+        }
+        assert_eq!("Project64".parse::<TraceFormat>(), Ok(TraceFormat::Project64));
+        assert_eq!(
+            "bizhawk".parse::<TraceFormat>(),
+            Err("unknown trace format `bizhawk`, expected ares, cen64, mupen64plus, project64, simple64, gdb or auto"
+                .to_string()),
+        );
+    }
+
+    #[test]
+    fn validates_the_captures_of_user_defined_patterns() {
+        assert!(trace_pattern(r"^(?P<pc>[0-9A-F]{8}) (?P<rest>.*)$").is_ok());
+        assert!(trace_pattern(r"^PC=(?P<pc>\w+)(?: @(?P<addr>\w+))?$").is_ok());
+        assert_eq!(trace_pattern(r"^(?P<rest>.*)$").unwrap_err(), "missing capture `pc`");
+        assert_eq!(
+            trace_pattern(r"^(?P<pc>\w+) (?P<op>.*)$").unwrap_err(),
+            "unknown capture `op`, expected pc, prefix, rest, addr",
+        );
+        assert!(trace_pattern(r"^(?P<pc>\w+").is_err());
+    }
+}
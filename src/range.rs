@@ -0,0 +1,316 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Description of the areas an address range crosses
+//!
+//! A range of virtual addresses, e.g. the target of a DMA, is split where its
+//! segment, TLB page, region or subregion changes, so that each piece lies in
+//! a single area of the memory map.
+//!
+
+use crate::map::{
+    address_location_to_string,
+    is_32bit_compatible,
+    sign_extend,
+    AddressLocation,
+    SEGMENTS,
+    SEGMENTS_64,
+};
+use crate::mapfile::MemoryMap;
+use crate::tlb::{Tlb, TlbTranslation};
+
+/// A part of a range lying in a single area of the memory map.
+#[derive(Debug, Clone)]
+pub struct RangePiece {
+    /// First virtual address
+    pub start: u64,
+    /// Last virtual address
+    pub end: u64,
+    /// Location of the first address
+    pub location: AddressLocation,
+    /// What is wrong with accessing the piece, e.g. crossing into it from a
+    /// different subregion
+    pub warnings: Vec<String>,
+}
+
+impl RangePiece {
+    /// Size in bytes, saturated for the range of the whole address space.
+    pub fn size(&self) -> u64 {
+        range_size(self.start, self.end)
+    }
+
+    /// First and last physical address of the piece, if it translates.
+    pub fn physical_range(&self) -> Option<(u32, u32)> {
+        self.location.physical_address
+            .map(|start| (start, start.wrapping_add((self.size() - 1) as u32)))
+    }
+}
+
+/// Size in bytes of the range from `start` to `end`, both included, in the
+/// 32-bit address space if both are 32-bit compatible.
+pub fn range_size(start: u64, end: u64) -> u64 {
+    if is_32bit_compatible(start) && is_32bit_compatible(end) {
+        (end as u32).wrapping_sub(start as u32) as u64 + 1
+    } else {
+        end.wrapping_sub(start).saturating_add(1)
+    }
+}
+
+/// Adds an offset to an address, wrapping around within the 32-bit address
+/// space for 32-bit compatible addresses, as the CPU does in 32-bit mode.
+pub fn offset_address(address: u64, offset: u64) -> u64 {
+    if is_32bit_compatible(address) {
+        sign_extend((address as u32).wrapping_add(offset as u32))
+    } else {
+        address.wrapping_add(offset)
+    }
+}
+
+/// Virtual addresses where a segment starts or ends, i.e. where the way
+/// addresses translate changes. In 32-bit mode only the 32-bit segments are
+/// reachable, and addresses are the lower 32 bits.
+fn segment_boundaries(mode_32bit: bool) -> Vec<u64> {
+    let mut boundaries: Vec<u64> = Vec::new();
+    if mode_32bit {
+        for segment in SEGMENTS.iter() {
+            boundaries.push(segment.0 as u64);
+            boundaries.push(segment.1 as u64 + 1);
+        }
+        return boundaries;
+    }
+
+    for segment in SEGMENTS.iter() {
+        boundaries.push(sign_extend(segment.0));
+        boundaries.push(sign_extend(segment.1).wrapping_add(1));
+    }
+    // KUSEG is followed by the 64-bit XKUSEG addresses
+    boundaries.push(0x8000_0000);
+    for segment in SEGMENTS_64.iter() {
+        boundaries.push(segment.0);
+        boundaries.push(segment.1.wrapping_add(1));
+    }
+    // XKPHYS only holds 32 bits of physical address per cache attribute
+    for attribute in 0..8u64 {
+        let start: u64 = 0x8000_0000_0000_0000 | (attribute << 59);
+        boundaries.push(start);
+        boundaries.push(start + 0x1_0000_0000);
+    }
+    boundaries
+}
+
+/// Virtual addresses where the pages of the TLB entries start or end.
+fn tlb_boundaries(tlb: &Tlb, mode_32bit: bool) -> Vec<u64> {
+    let mut boundaries: Vec<u64> = Vec::new();
+    for entry in tlb.entries.iter().flatten() {
+        let size: u64 = entry.page_size() * 2;
+        let start: u64 = entry.entry_hi & !(size - 1) & 0xC000_00FF_FFFF_FFFF;
+        if mode_32bit {
+            boundaries.push(start & 0xFFFF_FFFF);
+            boundaries.push((start & 0xFFFF_FFFF) + size);
+        } else {
+            boundaries.push(start);
+            boundaries.push(start.wrapping_add(size));
+        }
+    }
+    boundaries
+}
+
+/// Last physical address of the region and subregions holding the address,
+/// or before the next area nested in them.
+fn area_end(map: &MemoryMap, physical_address: u32) -> u32 {
+    map.regions.iter()
+        .chain(map.subregions.iter())
+        .filter_map(|area| {
+            if area.0 <= physical_address && physical_address <= area.1 {
+                Some(area.1)
+            } else if area.0 > physical_address {
+                Some(area.0 - 1)
+            } else {
+                None
+            }
+        })
+        .min()
+        .unwrap_or(u32::MAX)
+}
+
+/// Why accessing the location raises an exception, hangs the bus or reads
+/// open bus, if it does.
+fn fatal_reason(location: &AddressLocation) -> Option<&'static str> {
+    if !location.is_valid() {
+        Some("raises an address error")
+    } else if location.tlb == Some(TlbTranslation::Miss) {
+        Some("misses the TLB")
+    } else if location.subregions.iter().any(|(_, name)| name.starts_with("Unmapped")) {
+        Some("is unmapped")
+    } else if location.is_unpopulated() {
+        Some("is beyond installed RDRAM")
+    } else {
+        None
+    }
+}
+
+/// First physical address of the RDRAM registers, past the RDRAM
+/// memory-space.
+const RDRAM_REGISTERS: u32 = 0x03F0_0000;
+/// Last physical address of the RDRAM registers, broadcast ones included.
+const RDRAM_REGISTERS_END: u32 = 0x03FF_FFFF;
+
+/// True if a range going from one location into the next straddles areas it
+/// likely wasn't meant to: RDRAM running into the RDRAM registers, or an
+/// accessible area running into one that isn't (see [`fatal_reason`]).
+fn straddles(last: &AddressLocation, next: &AddressLocation) -> bool {
+    let into_registers: bool = match (last.physical_address, next.physical_address) {
+        (Some(last), Some(next)) => {
+            last < RDRAM_REGISTERS && (RDRAM_REGISTERS..=RDRAM_REGISTERS_END).contains(&next)
+        }
+        _ => false,
+    };
+    into_registers || (fatal_reason(last).is_none() && fatal_reason(next).is_some())
+}
+
+/// Split the virtual address range from `start` to `end`, both included,
+/// along the boundaries of segments, TLB pages, regions and subregions. When
+/// both ends are 32-bit compatible the range lies in the 32-bit address
+/// space, so that it may cross from KUSEG into KSEG0. The range is empty if
+/// it ends before it starts.
+///
+/// Pieces are flagged when a span straddles RDRAM into the RDRAM registers or
+/// an accessible area into one that isn't, and when they are unmapped, miss
+/// the TLB or raise an address error.
+///
+/// Regions and subregions are those of the built-in tables, see
+/// [`MemoryMap::split_range`] for another map.
+pub fn split_range(start: u64, end: u64, tlb: &Tlb) -> Vec<RangePiece> {
+    MemoryMap::default().split_range(start, end, tlb)
+}
+
+impl MemoryMap {
+    /// Same as [`split_range`], along the regions and subregions of this map.
+    pub fn split_range(&self, start: u64, end: u64, tlb: &Tlb) -> Vec<RangePiece> {
+        // Addresses are walked as the lower 32 bits in 32-bit mode, and
+        // sign-extended to be looked up
+        let mode_32bit: bool = is_32bit_compatible(start) && is_32bit_compatible(end);
+        let (start, end) = if mode_32bit { (start & 0xFFFF_FFFF, end & 0xFFFF_FFFF) } else { (start, end) };
+        let to_virtual = |address: u64| if mode_32bit { sign_extend(address as u32) } else { address };
+
+        let mut boundaries: Vec<u64> = segment_boundaries(mode_32bit);
+        boundaries.extend(tlb_boundaries(tlb, mode_32bit));
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut pieces: Vec<RangePiece> = Vec::new();
+        if end < start {
+            return pieces;
+        }
+
+        let mut cursor: u64 = start;
+        loop {
+            let location: AddressLocation = self.lookup(to_virtual(cursor), tlb);
+
+            let mut piece_end: u64 = end;
+            if let Some(boundary) = boundaries.iter().find(|boundary| **boundary > cursor) {
+                piece_end = piece_end.min(boundary - 1);
+            }
+            if let Some(physical_address) = location.physical_address {
+                let remaining: u64 = (area_end(self, physical_address) - physical_address) as u64;
+                piece_end = piece_end.min(cursor.saturating_add(remaining));
+            }
+            if let Some(TlbTranslation::Hit { page_size, .. }) = location.tlb {
+                piece_end = piece_end.min(cursor | (page_size - 1));
+            }
+
+            // Consecutive pages of the same area make one piece
+            let merged: bool = pieces.last_mut().is_some_and(|last| {
+                let contiguous: bool = match (last.physical_range(), location.physical_address) {
+                    (Some((_, last_end)), Some(next)) => last_end.wrapping_add(1) == next,
+                    (None, None) => true,
+                    _ => false,
+                };
+                if contiguous && address_location_to_string(&last.location) == address_location_to_string(&location) {
+                    last.end = to_virtual(piece_end);
+                    true
+                } else {
+                    false
+                }
+            });
+
+            if !merged {
+                let mut warnings: Vec<String> = Vec::new();
+                if let Some(last) = pieces.last() {
+                    if straddles(&last.location, &location) {
+                        warnings.push(format!(
+                            "straddles {} into {}",
+                            area_name(&last.location),
+                            area_name(&location),
+                        ));
+                    }
+                }
+                if let Some(reason) = fatal_reason(&location) {
+                    warnings.push(reason.to_string());
+                }
+                pieces.push(RangePiece { start: to_virtual(cursor), end: to_virtual(piece_end), location, warnings });
+            }
+
+            if piece_end >= end {
+                break;
+            }
+            cursor = piece_end + 1;
+        }
+
+        pieces
+    }
+}
+
+/// Names the innermost subregion of a location, or else its region.
+fn area_name(location: &AddressLocation) -> String {
+    match (location.subregions.last(), &location.region) {
+        (Some((short, long)), _) | (None, Some((short, long))) => format!("{} ({})", long, short),
+        (None, None) => address_location_to_string(location),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pieces(start: u64, end: u64) -> Vec<(u64, u64, String, Vec<String>)> {
+        split_range(start, end, &Tlb::default()).into_iter()
+            .map(|piece| (piece.start, piece.end, address_location_to_string(&piece.location), piece.warnings))
+            .collect()
+    }
+
+    #[test]
+    fn splits_dmem_into_imem_without_warnings() {
+        assert_eq!(pieces(0xFFFFFFFFA4000F00, 0xFFFFFFFFA40010FF), vec![
+            (0xFFFFFFFFA4000F00, 0xFFFFFFFFA4000FFF, "1G.RSPD".to_string(), vec![]),
+            (0xFFFFFFFFA4001000, 0xFFFFFFFFA40010FF, "1G.RSPI".to_string(), vec![]),
+        ]);
+    }
+
+    #[test]
+    fn flags_rdram_running_into_its_registers() {
+        let found: Vec<(u64, u64, String, Vec<String>)> = pieces(0xFFFFFFFF83EFF000, 0xFFFFFFFF83F00FFF);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].3.is_empty());
+        assert_eq!(found[1].3, vec!["straddles RDRAM memory-space (RDRM) into RDRAM registers (RDRR)"]);
+    }
+
+    #[test]
+    fn crosses_from_kuseg_into_kseg0_in_32_bit_mode() {
+        let found: Vec<(u64, u64, String, Vec<String>)> = pieces(0x7FFFFFF0, 0xFFFFFFFF8000000F);
+        assert_eq!(found.len(), 2);
+        assert_eq!((found[0].1, found[0].2.as_str()), (0x7FFFFFFF, "U.TLBMISS"));
+        assert_eq!(found[0].3, vec!["misses the TLB"]);
+        assert_eq!((found[1].0, found[1].2.as_str()), (0xFFFFFFFF80000000, "0R.RDRM"));
+        assert!(found[1].3.is_empty());
+        assert!(pieces(0x80000010, 0x80000000).is_empty());
+    }
+
+    #[test]
+    fn sizes_and_offsets_wrap_in_the_32_bit_address_space() {
+        assert_eq!(range_size(0x7FFFFFF0, 0xFFFFFFFF8000000F), 0x20);
+        assert_eq!(range_size(0, u64::MAX), 0x1_0000_0000);
+        assert_eq!(range_size(0x4000000000000000, 0x3FFFFFFFFFFFFFFF), u64::MAX);
+        assert_eq!(offset_address(0x7FFFFFF0, 0x10), 0xFFFFFFFF80000000);
+        assert_eq!(offset_address(0x9000000000000000, 0x10), 0x9000000000000010);
+    }
+}
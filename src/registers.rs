@@ -0,0 +1,727 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Register tables of the RCP interfaces
+//!
+//! Based on information the documentation here:
+//! https://n64brew.dev/wiki/Reality_Signal_Processor/Interface
+//! https://n64brew.dev/wiki/Reality_Display_Processor/Interface
+//! https://n64brew.dev/wiki/MIPS_Interface
+//! https://n64brew.dev/wiki/Video_Interface
+//! https://n64brew.dev/wiki/Audio_Interface
+//! https://n64brew.dev/wiki/Peripheral_Interface
+//! https://n64brew.dev/wiki/RDRAM_Interface
+//! https://n64brew.dev/wiki/Serial_Interface
+//!
+//! Each interface only decodes the low bits of the address, so its registers
+//! repeat every `stride` bytes across the whole block it occupies.
+//!
+
+use std::fmt;
+
+use Access::{ReadOnly as R, ReadWrite as RW, WriteOnly as W};
+
+/// How the CPU may access a register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadOnly,
+    WriteOnly,
+    ReadWrite,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Access::ReadOnly => "R",
+            Access::WriteOnly => "W",
+            Access::ReadWrite => "RW",
+        })
+    }
+}
+
+/// A single memory-mapped register.
+#[derive(Debug)]
+pub struct Register {
+    /// Offset from the start of the register block
+    pub offset: u32,
+    /// Width in bits
+    pub width: u32,
+    pub access: Access,
+    pub name: &'static str,
+    /// Other names used by libultra, libdragon or Ares
+    pub aliases: &'static [&'static str],
+    /// What the register holds, and side effects of accessing it
+    pub description: &'static str,
+    /// Bitfields of the value read from the register
+    pub fields: &'static [Bitfield],
+    /// Bitfields of the value written to the register, when they differ from
+    /// the ones read back
+    pub write_fields: &'static [Bitfield],
+}
+
+impl Register {
+    /// The bitfields that apply when the register is read or written.
+    pub fn bitfields(&self, write: bool) -> &'static [Bitfield] {
+        if write && !self.write_fields.is_empty() {
+            self.write_fields
+        } else {
+            self.fields
+        }
+    }
+
+    /// True if `name` is the name or one of the aliases of the register,
+    /// ignoring case.
+    pub fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self.aliases.iter().any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+
+    const fn with_fields(self, fields: &'static [Bitfield]) -> Register {
+        Register { fields, ..self }
+    }
+
+    const fn with_write_fields(self, write_fields: &'static [Bitfield]) -> Register {
+        Register { write_fields, ..self }
+    }
+}
+
+/// A range of bits within a register value.
+#[derive(Debug)]
+pub struct Bitfield {
+    /// Least significant bit
+    pub lsb: u32,
+    /// Number of bits
+    pub bits: u32,
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Names of well-known values of the field
+    pub values: &'static [(u32, &'static str)],
+}
+
+impl Bitfield {
+    /// Extracts the field from a register value.
+    pub fn extract(&self, value: u32) -> u32 {
+        let mask: u64 = (1u64 << self.bits) - 1;
+        ((value as u64 >> self.lsb) & mask) as u32
+    }
+
+    /// Most significant bit
+    pub fn msb(&self) -> u32 {
+        self.lsb + self.bits - 1
+    }
+
+    /// Name of a field value, if it is one of the well-known ones.
+    pub fn value_name(&self, field_value: u32) -> Option<&'static str> {
+        self.values.iter()
+            .find(|(v, _)| *v == field_value)
+            .map(|(_, name)| *name)
+    }
+
+    const fn with_values(self, values: &'static [(u32, &'static str)]) -> Bitfield {
+        Bitfield { values, ..self }
+    }
+}
+
+/// A window of physical address space filled with a repeating register file.
+#[derive(Debug)]
+pub struct RegisterBlock {
+    pub start: u32,
+    pub end: u32,
+    /// Distance between mirrored copies of the register file
+    pub stride: u32,
+    /// Short name of the subregion holding the block
+    pub subregion: &'static str,
+    pub registers: &'static [Register],
+}
+
+const fn reg(
+    offset: u32,
+    access: Access,
+    name: &'static str,
+    aliases: &'static [&'static str],
+    description: &'static str,
+) -> Register {
+    Register { offset, width: 32, access, name, aliases, description, fields: &[], write_fields: &[] }
+}
+
+/// Bitfield spanning bits `msb` down to `lsb`, inclusive.
+const fn field(msb: u32, lsb: u32, name: &'static str, description: &'static str) -> Bitfield {
+    Bitfield { lsb, bits: msb - lsb + 1, name, description, values: &[] }
+}
+
+/// Single bit bitfield.
+const fn flag(bit: u32, name: &'static str, description: &'static str) -> Bitfield {
+    field(bit, bit, name, description)
+}
+
+static ADDRESS_24: &[Bitfield] = &[
+    field(23, 0, "ADDRESS", "RDRAM address"),
+];
+
+static SP_MEM_ADDR_FIELDS: &[Bitfield] = &[
+    field(11, 0, "ADDRESS", "DMEM/IMEM offset"),
+    flag(12, "BANK", "Memory bank").with_values(&[(0, "DMEM"), (1, "IMEM")]),
+];
+
+static SP_DMA_LEN_FIELDS: &[Bitfield] = &[
+    field(11, 0, "LENGTH", "Bytes per row, minus one"),
+    field(19, 12, "COUNT", "Rows, minus one"),
+    field(31, 20, "SKIP", "Bytes skipped in RDRAM after each row"),
+];
+
+static SP_STATUS_FIELDS: &[Bitfield] = &[
+    flag(0, "HALTED", "RSP is halted"),
+    flag(1, "BROKE", "RSP executed a break"),
+    flag(2, "DMA_BUSY", "DMA in progress"),
+    flag(3, "DMA_FULL", "DMA pending"),
+    flag(4, "IO_BUSY", "IO in progress"),
+    flag(5, "SSTEP", "Single step mode"),
+    flag(6, "INTBREAK", "Break raises an RSP interrupt"),
+    flag(7, "SIG0", "Signal 0"),
+    flag(8, "SIG1", "Signal 1"),
+    flag(9, "SIG2", "Signal 2"),
+    flag(10, "SIG3", "Signal 3"),
+    flag(11, "SIG4", "Signal 4"),
+    flag(12, "SIG5", "Signal 5"),
+    flag(13, "SIG6", "Signal 6"),
+    flag(14, "SIG7", "Signal 7"),
+];
+
+static SP_STATUS_WRITE_FIELDS: &[Bitfield] = &[
+    flag(0, "CLR_HALT", "Resume execution"),
+    flag(1, "SET_HALT", "Halt execution"),
+    flag(2, "CLR_BROKE", "Clear the broke flag"),
+    flag(3, "CLR_INTR", "Acknowledge the RSP interrupt"),
+    flag(4, "SET_INTR", "Raise the RSP interrupt"),
+    flag(5, "CLR_SSTEP", "Leave single step mode"),
+    flag(6, "SET_SSTEP", "Enter single step mode"),
+    flag(7, "CLR_INTBREAK", "Break does not interrupt"),
+    flag(8, "SET_INTBREAK", "Break raises an RSP interrupt"),
+    flag(9, "CLR_SIG0", "Clear signal 0"),
+    flag(10, "SET_SIG0", "Set signal 0"),
+    flag(11, "CLR_SIG1", "Clear signal 1"),
+    flag(12, "SET_SIG1", "Set signal 1"),
+    flag(13, "CLR_SIG2", "Clear signal 2"),
+    flag(14, "SET_SIG2", "Set signal 2"),
+    flag(15, "CLR_SIG3", "Clear signal 3"),
+    flag(16, "SET_SIG3", "Set signal 3"),
+    flag(17, "CLR_SIG4", "Clear signal 4"),
+    flag(18, "SET_SIG4", "Set signal 4"),
+    flag(19, "CLR_SIG5", "Clear signal 5"),
+    flag(20, "SET_SIG5", "Set signal 5"),
+    flag(21, "CLR_SIG6", "Clear signal 6"),
+    flag(22, "SET_SIG6", "Set signal 6"),
+    flag(23, "CLR_SIG7", "Clear signal 7"),
+    flag(24, "SET_SIG7", "Set signal 7"),
+];
+
+static SP_FLAG_FIELDS: &[Bitfield] = &[
+    flag(0, "FLAG", "Flag value"),
+];
+
+static SP_PC_FIELDS: &[Bitfield] = &[
+    field(11, 0, "PC", "IMEM offset of the next instruction"),
+];
+
+static DPC_COUNTER_FIELDS: &[Bitfield] = &[
+    field(23, 0, "COUNT", "Counter value"),
+];
+
+static DPC_STATUS_FIELDS: &[Bitfield] = &[
+    flag(0, "XBUS", "Commands are fetched from DMEM"),
+    flag(1, "FREEZE", "Command processing is frozen"),
+    flag(2, "FLUSH", "Command buffer is being flushed"),
+    flag(3, "START_GCLK", "Clock counter is running"),
+    flag(4, "TMEM_BUSY", "TMEM is busy"),
+    flag(5, "PIPE_BUSY", "Pipeline is busy"),
+    flag(6, "CMD_BUSY", "Command unit is busy"),
+    flag(7, "CBUF_READY", "Command buffer is ready"),
+    flag(8, "DMA_BUSY", "DMA in progress"),
+    flag(9, "END_PENDING", "DPC_END was written while busy"),
+    flag(10, "START_PENDING", "DPC_START was written while busy"),
+];
+
+static DPC_STATUS_WRITE_FIELDS: &[Bitfield] = &[
+    flag(0, "CLR_XBUS", "Fetch commands from RDRAM"),
+    flag(1, "SET_XBUS", "Fetch commands from DMEM"),
+    flag(2, "CLR_FREEZE", "Resume command processing"),
+    flag(3, "SET_FREEZE", "Freeze command processing"),
+    flag(4, "CLR_FLUSH", "Stop flushing the command buffer"),
+    flag(5, "SET_FLUSH", "Flush the command buffer"),
+    flag(6, "CLR_TMEM_CTR", "Reset the TMEM counter"),
+    flag(7, "CLR_PIPE_CTR", "Reset the pipe counter"),
+    flag(8, "CLR_CMD_CTR", "Reset the command counter"),
+    flag(9, "CLR_CLOCK_CTR", "Reset the clock counter"),
+];
+
+static MI_MODE_FIELDS: &[Bitfield] = &[
+    field(6, 0, "REPEAT_COUNT", "Bytes written by repeat mode, minus one"),
+    flag(7, "REPEAT", "Repeat mode is enabled"),
+    flag(8, "EBUS", "EBus test mode is enabled"),
+    flag(9, "RDRAM_REG", "RDRAM register mode is enabled"),
+];
+
+static MI_MODE_WRITE_FIELDS: &[Bitfield] = &[
+    field(6, 0, "REPEAT_COUNT", "Bytes written by repeat mode, minus one"),
+    flag(7, "CLR_REPEAT", "Disable repeat mode"),
+    flag(8, "SET_REPEAT", "Enable repeat mode"),
+    flag(9, "CLR_EBUS", "Disable EBus test mode"),
+    flag(10, "SET_EBUS", "Enable EBus test mode"),
+    flag(11, "CLR_DP_INTR", "Acknowledge the DP interrupt"),
+    flag(12, "CLR_RDRAM_REG", "Disable RDRAM register mode"),
+    flag(13, "SET_RDRAM_REG", "Enable RDRAM register mode"),
+];
+
+static MI_VERSION_FIELDS: &[Bitfield] = &[
+    field(7, 0, "IO", "IO chip version"),
+    field(15, 8, "RAC", "RAC version"),
+    field(23, 16, "RDP", "RDP version"),
+    field(31, 24, "RSP", "RSP version"),
+];
+
+static MI_INTERRUPT_FIELDS: &[Bitfield] = &[
+    flag(0, "SP", "RSP interrupt"),
+    flag(1, "SI", "SI interrupt"),
+    flag(2, "AI", "AI interrupt"),
+    flag(3, "VI", "VI interrupt"),
+    flag(4, "PI", "PI interrupt"),
+    flag(5, "DP", "RDP interrupt"),
+];
+
+static MI_MASK_WRITE_FIELDS: &[Bitfield] = &[
+    flag(0, "CLR_SP", "Mask the RSP interrupt"),
+    flag(1, "SET_SP", "Unmask the RSP interrupt"),
+    flag(2, "CLR_SI", "Mask the SI interrupt"),
+    flag(3, "SET_SI", "Unmask the SI interrupt"),
+    flag(4, "CLR_AI", "Mask the AI interrupt"),
+    flag(5, "SET_AI", "Unmask the AI interrupt"),
+    flag(6, "CLR_VI", "Mask the VI interrupt"),
+    flag(7, "SET_VI", "Unmask the VI interrupt"),
+    flag(8, "CLR_PI", "Mask the PI interrupt"),
+    flag(9, "SET_PI", "Unmask the PI interrupt"),
+    flag(10, "CLR_DP", "Mask the RDP interrupt"),
+    flag(11, "SET_DP", "Unmask the RDP interrupt"),
+];
+
+static VI_CTRL_FIELDS: &[Bitfield] = &[
+    field(1, 0, "TYPE", "Pixel type").with_values(&[
+        (0, "blank"),
+        (1, "reserved"),
+        (2, "16-bit RGBA5551"),
+        (3, "32-bit RGBA8888"),
+    ]),
+    flag(2, "GAMMA_DITHER", "Gamma dither enable"),
+    flag(3, "GAMMA", "Gamma correction enable"),
+    flag(4, "DIVOT", "Divot filter enable"),
+    flag(5, "VBUS_CLOCK", "VBus clock enable, must never be set"),
+    flag(6, "SERRATE", "Serrated vsync, for interlaced modes"),
+    flag(7, "TEST_MODE", "Test mode"),
+    field(9, 8, "AA_MODE", "Anti-alias and resample mode").with_values(&[
+        (0, "AA and resample, always fetch extra lines"),
+        (1, "AA and resample, fetch extra lines as needed"),
+        (2, "resample only"),
+        (3, "no AA, no resample, replicate pixels"),
+    ]),
+    flag(11, "KILL_WE", "Disable writes to the line buffer"),
+    field(15, 12, "PIXEL_ADVANCE", "Pixel advance timing"),
+    flag(16, "DEDITHER", "Dither filter enable"),
+];
+
+static VI_WIDTH_FIELDS: &[Bitfield] = &[
+    field(11, 0, "WIDTH", "Framebuffer line width in pixels"),
+];
+
+static VI_V_INTR_FIELDS: &[Bitfield] = &[
+    field(9, 0, "V_INTR", "Half-line of the interrupt"),
+];
+
+static VI_V_CURRENT_FIELDS: &[Bitfield] = &[
+    field(9, 0, "V_CURRENT", "Current half-line"),
+];
+
+static VI_BURST_FIELDS: &[Bitfield] = &[
+    field(7, 0, "HSYNC_WIDTH", "Horizontal sync width in pixels"),
+    field(15, 8, "BURST_WIDTH", "Color burst width in pixels"),
+    field(19, 16, "VSYNC_WIDTH", "Vertical sync width in half-lines"),
+    field(29, 20, "BURST_START", "Color burst start after hsync"),
+];
+
+static VI_V_SYNC_FIELDS: &[Bitfield] = &[
+    field(9, 0, "V_SYNC", "Half-lines per field"),
+];
+
+static VI_H_SYNC_FIELDS: &[Bitfield] = &[
+    field(11, 0, "H_SYNC", "Line duration in quarter pixels, minus one"),
+    field(20, 16, "LEAP", "Leap pattern for PAL"),
+];
+
+static VI_H_SYNC_LEAP_FIELDS: &[Bitfield] = &[
+    field(11, 0, "LEAP_B", "Alternate line duration B"),
+    field(27, 16, "LEAP_A", "Alternate line duration A"),
+];
+
+static VI_H_VIDEO_FIELDS: &[Bitfield] = &[
+    field(9, 0, "H_END", "End of active video in pixels"),
+    field(25, 16, "H_START", "Start of active video in pixels"),
+];
+
+static VI_V_VIDEO_FIELDS: &[Bitfield] = &[
+    field(9, 0, "V_END", "End of active video in half-lines"),
+    field(25, 16, "V_START", "Start of active video in half-lines"),
+];
+
+static VI_V_BURST_FIELDS: &[Bitfield] = &[
+    field(9, 0, "V_BURST_END", "End of color burst in half-lines"),
+    field(25, 16, "V_BURST_START", "Start of color burst in half-lines"),
+];
+
+static VI_X_SCALE_FIELDS: &[Bitfield] = &[
+    field(11, 0, "X_SCALE", "Horizontal scale, 2.10 fixed point"),
+    field(27, 16, "X_OFFSET", "Horizontal subpixel offset, 2.10 fixed point"),
+];
+
+static VI_Y_SCALE_FIELDS: &[Bitfield] = &[
+    field(11, 0, "Y_SCALE", "Vertical scale, 2.10 fixed point"),
+    field(27, 16, "Y_OFFSET", "Vertical subpixel offset, 2.10 fixed point"),
+];
+
+static AI_LENGTH_FIELDS: &[Bitfield] = &[
+    field(17, 0, "LENGTH", "Buffer length in bytes"),
+];
+
+static AI_CONTROL_FIELDS: &[Bitfield] = &[
+    flag(0, "DMA_ENABLE", "DMA enable"),
+];
+
+static AI_STATUS_FIELDS: &[Bitfield] = &[
+    flag(0, "FULL2", "Both DMA slots are full (copy of bit 31)"),
+    flag(25, "ENABLED", "DMA is enabled"),
+    flag(30, "BUSY", "DMA in progress"),
+    flag(31, "FULL", "Both DMA slots are full"),
+];
+
+static AI_DACRATE_FIELDS: &[Bitfield] = &[
+    field(13, 0, "DACRATE", "Video clock cycles per sample, minus one"),
+];
+
+static AI_BITRATE_FIELDS: &[Bitfield] = &[
+    field(3, 0, "BITRATE", "DAC clock cycles per bit, minus one"),
+];
+
+static PI_LEN_FIELDS: &[Bitfield] = &[
+    field(23, 0, "LENGTH", "Transfer length in bytes, minus one"),
+];
+
+static PI_STATUS_FIELDS: &[Bitfield] = &[
+    flag(0, "DMA_BUSY", "DMA in progress"),
+    flag(1, "IO_BUSY", "IO in progress"),
+    flag(2, "DMA_ERROR", "DMA error"),
+    flag(3, "INTERRUPT", "PI interrupt is pending"),
+];
+
+static PI_STATUS_WRITE_FIELDS: &[Bitfield] = &[
+    flag(0, "RESET", "Reset the DMA controller"),
+    flag(1, "CLR_INTR", "Acknowledge the PI interrupt"),
+];
+
+static PI_BSD_LAT_FIELDS: &[Bitfield] = &[
+    field(7, 0, "LATENCY", "Cycles before the first access, minus one"),
+];
+
+static PI_BSD_PWD_FIELDS: &[Bitfield] = &[
+    field(7, 0, "PULSE_WIDTH", "Cycles the read/write strobe is held, minus one"),
+];
+
+static PI_BSD_PGS_FIELDS: &[Bitfield] = &[
+    field(3, 0, "PAGE_SIZE", "Page size as a power of two, minus two"),
+];
+
+static PI_BSD_RLS_FIELDS: &[Bitfield] = &[
+    field(1, 0, "RELEASE", "Cycles between accesses, minus one"),
+];
+
+static RI_MODE_FIELDS: &[Bitfield] = &[
+    field(1, 0, "OPMODE", "Operating mode"),
+    flag(2, "STOP_T", "Stop transmitting"),
+    flag(3, "STOP_R", "Stop receiving"),
+];
+
+static RI_CONFIG_FIELDS: &[Bitfield] = &[
+    field(5, 0, "CC", "Current control value"),
+    flag(6, "AUTO_CC", "Automatic current calibration"),
+];
+
+static RI_LATENCY_FIELDS: &[Bitfield] = &[
+    field(3, 0, "LATENCY", "DMA latency in cycles"),
+];
+
+static SI_STATUS_FIELDS: &[Bitfield] = &[
+    flag(0, "DMA_BUSY", "DMA in progress"),
+    flag(1, "IO_BUSY", "IO in progress"),
+    flag(2, "READ_PENDING", "Read pending"),
+    flag(3, "DMA_ERROR", "DMA error"),
+    field(7, 4, "PCH_STATE", "PIF channel state"),
+    field(11, 8, "DMA_STATE", "DMA state"),
+    flag(12, "INTERRUPT", "SI interrupt is pending"),
+];
+
+static SP_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "SP_DMA_SPADDR", &["SP_MEM_ADDR"], "DMEM/IMEM address for DMA")
+        .with_fields(SP_MEM_ADDR_FIELDS),
+    reg(0x04, RW, "SP_DMA_RAMADDR", &["SP_DRAM_ADDR"], "RDRAM address for DMA")
+        .with_fields(ADDRESS_24),
+    reg(0x08, RW, "SP_DMA_RDLEN", &["SP_RD_LEN"], "DMA length RDRAM to DMEM/IMEM; write starts transfer")
+        .with_fields(SP_DMA_LEN_FIELDS),
+    reg(0x0C, RW, "SP_DMA_WRLEN", &["SP_WR_LEN"], "DMA length DMEM/IMEM to RDRAM; write starts transfer")
+        .with_fields(SP_DMA_LEN_FIELDS),
+    reg(0x10, RW, "SP_STATUS", &[], "RSP status; write sets/clears individual bits")
+        .with_fields(SP_STATUS_FIELDS)
+        .with_write_fields(SP_STATUS_WRITE_FIELDS),
+    reg(0x14, R, "SP_DMA_FULL", &[], "DMA pending slot is full")
+        .with_fields(SP_FLAG_FIELDS),
+    reg(0x18, R, "SP_DMA_BUSY", &[], "DMA transfer in progress")
+        .with_fields(SP_FLAG_FIELDS),
+    reg(0x1C, RW, "SP_SEMAPHORE", &[], "Semaphore; read acquires, write releases")
+        .with_fields(SP_FLAG_FIELDS),
+];
+
+static SP_PC_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "SP_PC", &[], "RSP program counter")
+        .with_fields(SP_PC_FIELDS),
+    reg(0x04, RW, "SP_IBIST", &[], "IMEM built-in self test"),
+];
+
+static DPC_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "DPC_START", &[], "Start of the RDP command buffer")
+        .with_fields(ADDRESS_24),
+    reg(0x04, RW, "DPC_END", &[], "End of the RDP command buffer; write kicks off processing")
+        .with_fields(ADDRESS_24),
+    reg(0x08, R, "DPC_CURRENT", &[], "Current RDP command address")
+        .with_fields(ADDRESS_24),
+    reg(0x0C, RW, "DPC_STATUS", &[], "RDP status; write sets/clears individual bits")
+        .with_fields(DPC_STATUS_FIELDS)
+        .with_write_fields(DPC_STATUS_WRITE_FIELDS),
+    reg(0x10, R, "DPC_CLOCK", &[], "Clock counter")
+        .with_fields(DPC_COUNTER_FIELDS),
+    reg(0x14, R, "DPC_BUF_BUSY", &["DPC_BUFBUSY"], "Buffer busy counter")
+        .with_fields(DPC_COUNTER_FIELDS),
+    reg(0x18, R, "DPC_PIPE_BUSY", &["DPC_PIPEBUSY"], "Pipe busy counter")
+        .with_fields(DPC_COUNTER_FIELDS),
+    reg(0x1C, R, "DPC_TMEM_BUSY", &["DPC_TMEM"], "TMEM load counter")
+        .with_fields(DPC_COUNTER_FIELDS),
+];
+
+static DPS_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "DPS_TBIST", &[], "TMEM built-in self test"),
+    reg(0x04, RW, "DPS_TEST_MODE", &[], "Span buffer test mode"),
+    reg(0x08, RW, "DPS_BUFTEST_ADDR", &[], "Span buffer test address"),
+    reg(0x0C, RW, "DPS_BUFTEST_DATA", &[], "Span buffer test data"),
+];
+
+static MI_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "MI_MODE", &["MI_INIT_MODE"], "Init mode, EBus test and RDRAM register mode; write sets/clears bits")
+        .with_fields(MI_MODE_FIELDS)
+        .with_write_fields(MI_MODE_WRITE_FIELDS),
+    reg(0x04, R, "MI_VERSION", &["MI_NOOP"], "RSP, RDP, RAC and IO chip versions")
+        .with_fields(MI_VERSION_FIELDS),
+    reg(0x08, R, "MI_INTERRUPT", &["MI_INTR"], "Pending RCP interrupts")
+        .with_fields(MI_INTERRUPT_FIELDS),
+    reg(0x0C, RW, "MI_MASK", &["MI_INTR_MASK"], "RCP interrupt mask; write sets/clears individual bits")
+        .with_fields(MI_INTERRUPT_FIELDS)
+        .with_write_fields(MI_MASK_WRITE_FIELDS),
+];
+
+static VI_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "VI_CTRL", &["VI_CONTROL", "VI_STATUS"], "Video mode and output control")
+        .with_fields(VI_CTRL_FIELDS),
+    reg(0x04, RW, "VI_ORIGIN", &["VI_DRAM_ADDRESS", "VI_DRAM_ADDR"], "Framebuffer origin in RDRAM")
+        .with_fields(ADDRESS_24),
+    reg(0x08, RW, "VI_WIDTH", &["VI_H_WIDTH"], "Framebuffer line width in pixels")
+        .with_fields(VI_WIDTH_FIELDS),
+    reg(0x0C, RW, "VI_V_INTR", &["VI_INTR"], "Half-line that raises the VI interrupt")
+        .with_fields(VI_V_INTR_FIELDS),
+    reg(0x10, RW, "VI_V_CURRENT", &["VI_V_CURRENT_LINE", "VI_CURRENT"], "Current half-line; write clears the VI interrupt")
+        .with_fields(VI_V_CURRENT_FIELDS),
+    reg(0x14, RW, "VI_BURST", &["VI_TIMING"], "Color burst and sync pulse timing")
+        .with_fields(VI_BURST_FIELDS),
+    reg(0x18, RW, "VI_V_SYNC", &["VI_V_TOTAL"], "Half-lines per field")
+        .with_fields(VI_V_SYNC_FIELDS),
+    reg(0x1C, RW, "VI_H_SYNC", &["VI_H_TOTAL"], "Line duration and leap pattern")
+        .with_fields(VI_H_SYNC_FIELDS),
+    reg(0x20, RW, "VI_H_SYNC_LEAP", &["VI_LEAP", "VI_H_TOTAL_LEAP"], "Alternate line durations")
+        .with_fields(VI_H_SYNC_LEAP_FIELDS),
+    reg(0x24, RW, "VI_H_VIDEO", &["VI_H_START"], "Horizontal start and end of active video")
+        .with_fields(VI_H_VIDEO_FIELDS),
+    reg(0x28, RW, "VI_V_VIDEO", &["VI_V_START"], "Vertical start and end of active video")
+        .with_fields(VI_V_VIDEO_FIELDS),
+    reg(0x2C, RW, "VI_V_BURST", &[], "Vertical color burst start and end")
+        .with_fields(VI_V_BURST_FIELDS),
+    reg(0x30, RW, "VI_X_SCALE", &[], "Horizontal scale and offset")
+        .with_fields(VI_X_SCALE_FIELDS),
+    reg(0x34, RW, "VI_Y_SCALE", &[], "Vertical scale and offset")
+        .with_fields(VI_Y_SCALE_FIELDS),
+    reg(0x38, RW, "VI_TEST_ADDR", &[], "Test address"),
+    reg(0x3C, RW, "VI_STAGED_DATA", &[], "Test data"),
+];
+
+static AI_REGISTERS: &[Register] = &[
+    reg(0x00, W, "AI_DRAM_ADDR", &["AI_DRAM_ADDRESS"], "RDRAM address of the next sample buffer")
+        .with_fields(ADDRESS_24),
+    reg(0x04, RW, "AI_LENGTH", &["AI_LEN"], "Sample buffer length; write queues the buffer")
+        .with_fields(AI_LENGTH_FIELDS),
+    reg(0x08, W, "AI_CONTROL", &[], "DMA enable")
+        .with_fields(AI_CONTROL_FIELDS),
+    reg(0x0C, RW, "AI_STATUS", &[], "FIFO status; write clears the AI interrupt")
+        .with_fields(AI_STATUS_FIELDS),
+    reg(0x10, W, "AI_DACRATE", &[], "DAC sample period")
+        .with_fields(AI_DACRATE_FIELDS),
+    reg(0x14, W, "AI_BITRATE", &[], "Serial clock divider")
+        .with_fields(AI_BITRATE_FIELDS),
+];
+
+static PI_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "PI_DRAM_ADDR", &["PI_DRAM_ADDRESS"], "RDRAM address for DMA")
+        .with_fields(ADDRESS_24),
+    reg(0x04, RW, "PI_CART_ADDR", &["PI_PBUS_ADDRESS"], "PI bus address for DMA"),
+    reg(0x08, RW, "PI_RD_LEN", &["PI_READ_LENGTH"], "DMA length RDRAM to PI bus; write starts transfer")
+        .with_fields(PI_LEN_FIELDS),
+    reg(0x0C, RW, "PI_WR_LEN", &["PI_WRITE_LENGTH"], "DMA length PI bus to RDRAM; write starts transfer")
+        .with_fields(PI_LEN_FIELDS),
+    reg(0x10, RW, "PI_STATUS", &[], "DMA status; write resets the controller or clears the PI interrupt")
+        .with_fields(PI_STATUS_FIELDS)
+        .with_write_fields(PI_STATUS_WRITE_FIELDS),
+    reg(0x14, RW, "PI_BSD_DOM1_LAT", &["PI_BSD_DOM1_LAT_REG"], "Domain 1 latency")
+        .with_fields(PI_BSD_LAT_FIELDS),
+    reg(0x18, RW, "PI_BSD_DOM1_PWD", &["PI_BSD_DOM1_PWD_REG"], "Domain 1 pulse width")
+        .with_fields(PI_BSD_PWD_FIELDS),
+    reg(0x1C, RW, "PI_BSD_DOM1_PGS", &["PI_BSD_DOM1_PGS_REG"], "Domain 1 page size")
+        .with_fields(PI_BSD_PGS_FIELDS),
+    reg(0x20, RW, "PI_BSD_DOM1_RLS", &["PI_BSD_DOM1_RLS_REG"], "Domain 1 release duration")
+        .with_fields(PI_BSD_RLS_FIELDS),
+    reg(0x24, RW, "PI_BSD_DOM2_LAT", &["PI_BSD_DOM2_LAT_REG"], "Domain 2 latency")
+        .with_fields(PI_BSD_LAT_FIELDS),
+    reg(0x28, RW, "PI_BSD_DOM2_PWD", &["PI_BSD_DOM2_PWD_REG"], "Domain 2 pulse width")
+        .with_fields(PI_BSD_PWD_FIELDS),
+    reg(0x2C, RW, "PI_BSD_DOM2_PGS", &["PI_BSD_DOM2_PGS_REG"], "Domain 2 page size")
+        .with_fields(PI_BSD_PGS_FIELDS),
+    reg(0x30, RW, "PI_BSD_DOM2_RLS", &["PI_BSD_DOM2_RLS_REG"], "Domain 2 release duration")
+        .with_fields(PI_BSD_RLS_FIELDS),
+];
+
+static RI_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "RI_MODE", &[], "Operating mode")
+        .with_fields(RI_MODE_FIELDS),
+    reg(0x04, RW, "RI_CONFIG", &[], "Current control configuration")
+        .with_fields(RI_CONFIG_FIELDS),
+    reg(0x08, W, "RI_CURRENT_LOAD", &[], "Write applies the current control value"),
+    reg(0x0C, RW, "RI_SELECT", &[], "Receive and transmit select"),
+    reg(0x10, RW, "RI_REFRESH", &["RI_COUNT"], "Refresh delay and banks"),
+    reg(0x14, RW, "RI_LATENCY", &[], "DMA latency")
+        .with_fields(RI_LATENCY_FIELDS),
+    reg(0x18, R, "RI_ERROR", &["RI_RERROR"], "Read error flags"),
+    reg(0x1C, W, "RI_BANK_STATUS", &["RI_WERROR"], "Write clears error flags"),
+];
+
+static SI_REGISTERS: &[Register] = &[
+    reg(0x00, RW, "SI_DRAM_ADDR", &["SI_DRAM_ADDRESS"], "RDRAM address for DMA")
+        .with_fields(ADDRESS_24),
+    reg(0x04, W, "SI_PIF_AD_RD64B", &["SI_PIF_ADDR_RD64B", "SI_PIF_ADDRESS_READ64B"], "PIF RAM to RDRAM 64 byte DMA; write starts transfer"),
+    reg(0x08, W, "SI_PIF_AD_WR4B", &["SI_PIF_ADDRESS_WRITE4B"], "4 byte write to PIF RAM"),
+    reg(0x10, W, "SI_PIF_AD_WR64B", &["SI_PIF_ADDR_WR64B", "SI_PIF_ADDRESS_WRITE64B"], "RDRAM to PIF RAM 64 byte DMA; write starts transfer"),
+    reg(0x14, W, "SI_PIF_AD_RD4B", &["SI_PIF_ADDRESS_READ4B"], "4 byte read from PIF RAM"),
+    reg(0x18, RW, "SI_STATUS", &[], "DMA status; write clears the SI interrupt")
+        .with_fields(SI_STATUS_FIELDS),
+];
+
+pub static REGISTER_BLOCKS: &[RegisterBlock] = &[
+    RegisterBlock { start: 0x04040000, end: 0x0407FFFF, stride: 0x20, subregion: "RSPR", registers: SP_REGISTERS },
+    RegisterBlock { start: 0x04080000, end: 0x040BFFFF, stride: 0x08, subregion: "RSPR", registers: SP_PC_REGISTERS },
+    RegisterBlock { start: 0x04100000, end: 0x041FFFFF, stride: 0x20, subregion: "RDPC", registers: DPC_REGISTERS },
+    RegisterBlock { start: 0x04200000, end: 0x042FFFFF, stride: 0x10, subregion: "RDPS", registers: DPS_REGISTERS },
+    RegisterBlock { start: 0x04300000, end: 0x043FFFFF, stride: 0x10, subregion: "InMI", registers: MI_REGISTERS },
+    RegisterBlock { start: 0x04400000, end: 0x044FFFFF, stride: 0x40, subregion: "InVI", registers: VI_REGISTERS },
+    RegisterBlock { start: 0x04500000, end: 0x045FFFFF, stride: 0x20, subregion: "InAI", registers: AI_REGISTERS },
+    RegisterBlock { start: 0x04600000, end: 0x046FFFFF, stride: 0x40, subregion: "InPI", registers: PI_REGISTERS },
+    RegisterBlock { start: 0x04700000, end: 0x047FFFFF, stride: 0x20, subregion: "InRI", registers: RI_REGISTERS },
+    RegisterBlock { start: 0x04800000, end: 0x048FFFFF, stride: 0x20, subregion: "InSI", registers: SI_REGISTERS },
+];
+
+/// Given a physical address, return the register it accesses, if any. Mirrored
+/// copies of a register file resolve to the same register.
+pub fn get_register(physical_address: u32) -> Option<&'static Register> {
+    let block: &RegisterBlock = REGISTER_BLOCKS.iter()
+        .find(|block| block.start <= physical_address && physical_address <= block.end)?;
+
+    let offset: u32 = (physical_address - block.start) % block.stride;
+
+    block.registers.iter()
+        .find(|reg| reg.offset <= offset && offset < reg.offset + reg.width / 8)
+}
+
+/// Find a register by its name or one of its aliases, ignoring case.
+pub fn find_register(name: &str) -> Option<(&'static RegisterBlock, &'static Register)> {
+    REGISTER_BLOCKS.iter()
+        .flat_map(|block| block.registers.iter().map(move |reg| (block, reg)))
+        .find(|(_, reg)| reg.is_named(name))
+}
+
+/// Split a register value into its bitfields. `write` selects the layout of
+/// values written to the register rather than read from it.
+pub fn decode_register(register: &Register, value: u32, write: bool) -> Vec<(&'static Bitfield, u32)> {
+    register.bitfields(write).iter()
+        .map(|field| (field, field.extract(value)))
+        .collect()
+}
+
+/// Produces the short-form description of a register value, listing the
+/// bitfields that are set. The short form is meant to fit on a trace line.
+pub fn decoded_register_to_string(decoded: &[(&'static Bitfield, u32)]) -> String {
+    let fields: Vec<String> = decoded.iter()
+        .filter(|(field, value)| *value != 0 || !field.values.is_empty())
+        .map(|(field, value)| match (field.bits, field.value_name(*value)) {
+            (_, Some(name)) => format!("{}={} ({})", field.name, value, name),
+            (1, None) => field.name.to_string(),
+            (_, None) => format!("{}={:#x}", field.name, value),
+        })
+        .collect();
+    fields.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrored_copies_resolve_to_the_same_register() {
+        assert_eq!(get_register(0x04400010).map(|reg| reg.name), Some("VI_V_CURRENT"));
+        assert_eq!(get_register(0x04400050).map(|reg| reg.name), Some("VI_V_CURRENT"));
+        assert_eq!(get_register(0x044FFFD0).map(|reg| reg.name), Some("VI_V_CURRENT"));
+        assert_eq!(get_register(0x04040010).map(|reg| reg.name), Some("SP_STATUS"));
+        assert!(get_register(0x04000000).is_none());
+    }
+
+    #[test]
+    fn finds_registers_by_name_or_alias() {
+        let (block, register): (&RegisterBlock, &Register) = find_register("vi_control").unwrap();
+        assert_eq!(register.name, "VI_CTRL");
+        assert_eq!(block.start, 0x04400000);
+        assert!(find_register("VI_NOPE").is_none());
+    }
+
+    #[test]
+    fn decodes_values_into_bitfields() {
+        let (_, register): (&RegisterBlock, &Register) = find_register("VI_CTRL").unwrap();
+        let decoded: Vec<(&Bitfield, u32)> = decode_register(register, 0x3303, false);
+        assert_eq!(decoded.len(), register.fields.len());
+        assert_eq!(
+            decoded_register_to_string(&decoded),
+            "TYPE=3 (32-bit RGBA8888) AA_MODE=3 (no AA, no resample, replicate pixels) PIXEL_ADVANCE=0x3"
+        );
+    }
+
+    #[test]
+    fn decodes_written_values_with_the_write_layout() {
+        let (_, register): (&RegisterBlock, &Register) = find_register("SP_STATUS").unwrap();
+        assert_eq!(decoded_register_to_string(&decode_register(register, 0x3, false)), "HALTED BROKE");
+        assert_eq!(decoded_register_to_string(&decode_register(register, 0x3, true)), "CLR_HALT SET_HALT");
+    }
+}
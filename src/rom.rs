@@ -0,0 +1,451 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Cartridge ROM files and their header
+//!
+//! ROM dumps come in three byte orders, told apart by the first word of the
+//! header: `.z64` files are big-endian like the cartridge, `.v64` files have
+//! the bytes of each 16-bit half swapped and `.n64` files are little-endian
+//! 32-bit words. Files are normalized to big-endian when they are read.
+//!
+//! Based on information from the ROM header documentation here:
+//! https://n64brew.dev/wiki/ROM_Header
+//!
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+/// Physical address of the first byte of the cartridge ROM.
+pub const ROM_BASE: u32 = 0x10000000;
+/// Physical address of the last byte of the cartridge ROM.
+pub const ROM_END: u32 = 0x1FBFFFFF;
+/// Size of the header, followed by the IPL3 boot code.
+pub const ROM_HEADER_SIZE: usize = 0x40;
+/// Offset of the end of the IPL3 boot code.
+pub const ROM_IPL3_END: usize = 0x1000;
+
+/// Byte order of a ROM file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// `.z64`, as on the cartridge
+    BigEndian,
+    /// `.v64`, bytes of each 16-bit half swapped
+    ByteSwapped,
+    /// `.n64`, bytes of each 32-bit word reversed
+    LittleEndian,
+}
+
+impl ByteOrder {
+    /// Detects the byte order from the first word of the header, 0x80371240
+    /// in big-endian.
+    pub fn detect(data: &[u8]) -> Option<ByteOrder> {
+        match data.get(..4)? {
+            [0x80, 0x37, 0x12, 0x40] => Some(ByteOrder::BigEndian),
+            [0x37, 0x80, 0x40, 0x12] => Some(ByteOrder::ByteSwapped),
+            [0x40, 0x12, 0x37, 0x80] => Some(ByteOrder::LittleEndian),
+            _ => None,
+        }
+    }
+
+    /// File extension commonly used for the byte order.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ByteOrder::BigEndian => "z64",
+            ByteOrder::ByteSwapped => "v64",
+            ByteOrder::LittleEndian => "n64",
+        }
+    }
+
+    /// Reorders the bytes of a file in this byte order to big-endian.
+    pub fn normalize(&self, data: &mut [u8]) {
+        match self {
+            ByteOrder::BigEndian => {}
+            ByteOrder::ByteSwapped => data.chunks_exact_mut(2).for_each(|half| half.swap(0, 1)),
+            ByteOrder::LittleEndian => data.chunks_exact_mut(4).for_each(|word| word.reverse()),
+        }
+    }
+}
+
+impl fmt::Display for ByteOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ByteOrder::BigEndian => write!(f, "big-endian (z64)"),
+            ByteOrder::ByteSwapped => write!(f, "byte-swapped (v64)"),
+            ByteOrder::LittleEndian => write!(f, "little-endian (n64)"),
+        }
+    }
+}
+
+/// CRC-32 of the IPL3 boot code, from offset 0x40 to 0x1000, and the CIC
+/// chips it boots with. NTSC and PAL chips sharing a seed are one family.
+static CIC_FAMILIES: &[(u32, &str)] = &[
+    (0x6170A4A1, "6101"),
+    (0x009E9EA3, "7102"),
+    (0x90BB6CB5, "6102/7101"),
+    (0x0B050EE0, "6103/7103"),
+    (0x98BC2C86, "6105/7105"),
+    (0xACC8580A, "6106/7106"),
+    (0x0E018159, "8303"),
+];
+
+/// CRC-32 of the IPL3 boot code of the Aleck64, which ends at offset 0xC00.
+const CIC_5101_CRC: u32 = 0x587BD543;
+
+/// Destination codes of the last character of the game code.
+static DESTINATIONS: &[(u8, &str)] = &[
+    (b'7', "Beta"),
+    (b'A', "Asia"),
+    (b'B', "Brazil"),
+    (b'C', "China"),
+    (b'D', "Germany"),
+    (b'E', "North America"),
+    (b'F', "France"),
+    (b'G', "Gateway 64 (NTSC)"),
+    (b'H', "Netherlands"),
+    (b'I', "Italy"),
+    (b'J', "Japan"),
+    (b'K', "Korea"),
+    (b'L', "Gateway 64 (PAL)"),
+    (b'N', "Canada"),
+    (b'P', "Europe"),
+    (b'S', "Spain"),
+    (b'U', "Australia"),
+    (b'W', "Scandinavia"),
+    (b'X', "Europe"),
+    (b'Y', "Europe"),
+    (b'Z', "Europe"),
+];
+
+/// Media categories of the first character of the game code.
+static CATEGORIES: &[(u8, &str)] = &[
+    (b'C', "Cartridge with 64DD expansion"),
+    (b'D', "64DD disk"),
+    (b'E', "64DD expansion"),
+    (b'M', "Cartridge for iQue"),
+    (b'N', "Cartridge"),
+    (b'Z', "Aleck64 cartridge"),
+];
+
+/// Fields of the header, by offset and size.
+static HEADER_FIELDS: &[(usize, usize, &str)] = &[
+    (0x00, 4, "PI domain 1 configuration"),
+    (0x04, 4, "clock rate"),
+    (0x08, 4, "boot address"),
+    (0x0C, 4, "libultra version"),
+    (0x10, 4, "check code, upper word"),
+    (0x14, 4, "check code, lower word"),
+    (0x18, 8, "reserved"),
+    (0x20, 20, "game title"),
+    (0x34, 7, "reserved"),
+    (0x3B, 1, "game code, category"),
+    (0x3C, 2, "game code, unique code"),
+    (0x3E, 1, "game code, destination"),
+    (0x3F, 1, "ROM version"),
+];
+
+/// The parsed header of a ROM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RomHeader {
+    /// Timings of the PI domain 1, the first word of the header
+    pub pi_config: u32,
+    /// Clock rate override, 0 for the default
+    pub clock_rate: u32,
+    /// Entry point the IPL3 boot code jumps to
+    pub boot_address: u32,
+    /// Version of libultra the game was built with, e.g. `2.0L`
+    pub libultra_version: Option<String>,
+    /// Checksum of the first megabyte after the boot code
+    pub check_code: u64,
+    /// Game title, trailing spaces removed. Characters outside of printable
+    /// ASCII, such as the Shift-JIS of Japanese titles, are replaced.
+    pub title: String,
+    /// Game code, e.g. `NSME`: category, unique code and destination
+    pub game_code: String,
+    /// ROM revision, 0 for the first release
+    pub version: u8,
+    /// CIC family of the IPL3 boot code, if known
+    pub cic: Option<&'static str>,
+    /// CRC-32 of the IPL3 boot code
+    pub ipl3_crc: u32,
+}
+
+impl RomHeader {
+    /// Media category named by the first character of the game code.
+    pub fn category(&self) -> Option<&'static str> {
+        let code: u8 = *self.game_code.as_bytes().first()?;
+        CATEGORIES.iter().find(|(c, _)| *c == code).map(|(_, name)| *name)
+    }
+
+    /// Destination named by the last character of the game code.
+    pub fn destination(&self) -> Option<&'static str> {
+        let code: u8 = *self.game_code.as_bytes().get(3)?;
+        DESTINATIONS.iter().find(|(c, _)| *c == code).map(|(_, name)| *name)
+    }
+}
+
+/// A ROM file normalized to big-endian.
+#[derive(Debug, Clone)]
+pub struct Rom {
+    pub data: Vec<u8>,
+    /// Byte order of the file it was read from
+    pub byte_order: ByteOrder,
+    pub header: RomHeader,
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Reflected CRC-32 as used by zlib and PNG.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = !0;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Text of the title and game code, replacing what isn't printable ASCII.
+fn header_text(bytes: &[u8]) -> String {
+    bytes.iter()
+        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' })
+        .collect::<String>()
+        .trim_end_matches([' ', '?'])
+        .to_string()
+}
+
+impl Rom {
+    /// Parse the contents of a ROM file in any of the byte orders.
+    pub fn parse(mut data: Vec<u8>) -> io::Result<Rom> {
+        let byte_order: ByteOrder = ByteOrder::detect(&data)
+            .ok_or_else(|| invalid_data("not an N64 ROM, unknown byte order".to_string()))?;
+        if data.len() < ROM_IPL3_END {
+            return Err(invalid_data(format!(
+                "ROM of {:#x} bytes is shorter than its header and boot code",
+                data.len(),
+            )));
+        }
+        byte_order.normalize(&mut data);
+
+        let ipl3_crc: u32 = crc32(&data[ROM_HEADER_SIZE..ROM_IPL3_END]);
+        let cic: Option<&'static str> = CIC_FAMILIES.iter()
+            .find(|(crc, _)| *crc == ipl3_crc)
+            .map(|(_, family)| *family)
+            .or_else(|| (crc32(&data[ROM_HEADER_SIZE..0xC00]) == CIC_5101_CRC).then_some("5101"));
+
+        let libultra: [u8; 4] = data[0x0C..0x10].try_into().unwrap();
+        let libultra_version: Option<String> = (libultra[3].is_ascii_alphabetic() && libultra[2] > 0)
+            .then(|| format!("{}.{}{}", libultra[2] / 10, libultra[2] % 10, libultra[3] as char));
+
+        let header: RomHeader = RomHeader {
+            pi_config: read_u32(&data, 0x00),
+            clock_rate: read_u32(&data, 0x04),
+            boot_address: read_u32(&data, 0x08),
+            libultra_version,
+            check_code: (read_u32(&data, 0x10) as u64) << 32 | read_u32(&data, 0x14) as u64,
+            title: header_text(&data[0x20..0x34]),
+            game_code: header_text(&data[0x3B..0x3F]),
+            version: data[0x3F],
+            cic,
+            ipl3_crc,
+        };
+
+        Ok(Rom { data, byte_order, header })
+    }
+
+    /// Read the named ROM file.
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> io::Result<Rom> {
+        Rom::parse(fs::read(filename)?)
+    }
+
+    /// Offset in the ROM of a physical address of the cartridge ROM area,
+    /// whether or not the ROM is that large.
+    pub fn offset(physical_address: u32) -> Option<u32> {
+        (ROM_BASE..=ROM_END).contains(&physical_address).then(|| physical_address - ROM_BASE)
+    }
+
+    /// Up to `length` bytes of the ROM at an offset, fewer at the end of the
+    /// ROM and none beyond it.
+    pub fn bytes(&self, offset: u32, length: usize) -> &[u8] {
+        let start: usize = (offset as usize).min(self.data.len());
+        let end: usize = start.saturating_add(length).min(self.data.len());
+        &self.data[start..end]
+    }
+
+    /// What the ROM holds at an offset: the name of a header field or the
+    /// IPL3 boot code, or nothing past them.
+    pub fn describe_offset(offset: u32) -> Option<&'static str> {
+        let offset: usize = offset as usize;
+        if offset >= ROM_IPL3_END {
+            return None;
+        }
+        if offset >= ROM_HEADER_SIZE {
+            return Some("IPL3 boot code");
+        }
+        HEADER_FIELDS.iter()
+            .find(|(start, size, _)| (*start..start + size).contains(&offset))
+            .map(|(_, _, name)| *name)
+    }
+}
+
+/// Formats bytes as hexadecimal, grouped in 32-bit words.
+pub fn format_rom_bytes(bytes: &[u8]) -> String {
+    bytes.chunks(4)
+        .map(|word| word.iter().map(|byte| format!("{:02X}", byte)).collect::<String>())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// Labelled fields of the ROM header, as listed by the `rom` command.
+pub fn rom_header_fields(rom: &Rom) -> Vec<(&'static str, String)> {
+    let header: &RomHeader = &rom.header;
+    let code: String = match (header.category(), header.destination()) {
+        (Some(category), Some(destination)) => format!("{} ({}, {})", header.game_code, category, destination),
+        (None, Some(destination)) => format!("{} ({})", header.game_code, destination),
+        _ => header.game_code.clone(),
+    };
+    vec![
+        ("Title", header.title.clone()),
+        ("Game Code", code),
+        ("Version", format!("1.{}", header.version)),
+        ("Byte Order", rom.byte_order.to_string()),
+        ("Size", format!("{:#x} bytes ({} Mbit)", rom.data.len(), rom.data.len() / (1 << 17))),
+        ("Entry Point", format!("0x{:08X}", header.boot_address)),
+        ("Clock Rate", if header.clock_rate == 0 { "default".to_string() } else { header.clock_rate.to_string() }),
+        ("libultra", header.libultra_version.clone().unwrap_or_else(|| "unknown".to_string())),
+        ("Check Code", format!("0x{:016X}", header.check_code)),
+        ("CIC", header.cic.map_or("unknown".to_string(), |family| format!("CIC-NUS-{}", family))),
+        ("IPL3 CRC", format!("0x{:08X}", header.ipl3_crc)),
+    ]
+}
+
+/// The header of a ROM as a JSON object, with numbers written as
+/// hexadecimal strings.
+pub fn rom_header_to_json(rom: &Rom) -> Value {
+    let header: &RomHeader = &rom.header;
+    json!({
+        "title": header.title,
+        "game_code": header.game_code,
+        "category": header.category(),
+        "destination": header.destination(),
+        "version": header.version,
+        "byte_order": rom.byte_order.extension(),
+        "size": rom.data.len(),
+        "pi_config": format!("0x{:08X}", header.pi_config),
+        "clock_rate": header.clock_rate,
+        "entry_point": format!("0x{:08X}", header.boot_address),
+        "libultra_version": header.libultra_version,
+        "check_code": format!("0x{:016X}", header.check_code),
+        "cic": header.cic,
+        "ipl3_crc": format!("0x{:08X}", header.ipl3_crc),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sets the last word of the IPL3 boot code so that its CRC-32 is `crc`.
+    /// Feeding a word into a reflected CRC is the same as feeding zeros with
+    /// the word XORed into the state, so the word is the difference between
+    /// the state before it and the state that zeros take to `crc`.
+    fn forge_ipl3_crc(data: &mut [u8], crc: u32) {
+        let table: Vec<u32> = (0..256u32)
+            .map(|index| (0..8).fold(index, |crc, _| if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 }))
+            .collect();
+
+        let mut state: u32 = !crc;
+        for _ in 0..4 {
+            let index: u32 = table.iter().position(|entry| entry >> 24 == state >> 24).unwrap() as u32;
+            state = ((state ^ table[index as usize]) << 8) | index;
+        }
+        let before: u32 = !crc32(&data[ROM_HEADER_SIZE..ROM_IPL3_END - 4]);
+        data[ROM_IPL3_END - 4..ROM_IPL3_END].copy_from_slice(&(state ^ before).to_le_bytes());
+    }
+
+    fn rom_data() -> Vec<u8> {
+        let mut data: Vec<u8> = vec![0; ROM_IPL3_END + 0x10];
+        data[0x00..0x10].copy_from_slice(&[0x80, 0x37, 0x12, 0x40, 0, 0, 0, 0x0F, 0x80, 0x00, 0x04, 0x00, 0, 0, 0x14, b'L']);
+        data[0x10..0x18].copy_from_slice(&[0x63, 0x5A, 0x2B, 0xFF, 0x8B, 0x02, 0x23, 0x26]);
+        data[0x20..0x34].copy_from_slice(b"SUPER MARIO 64      ");
+        data[0x3B..0x40].copy_from_slice(b"NSME\x01");
+        data[ROM_IPL3_END..].fill(0xAB);
+        forge_ipl3_crc(&mut data, 0x90BB6CB5);
+        data
+    }
+
+    #[test]
+    fn parses_the_header_in_every_byte_order() {
+        for byte_order in [ByteOrder::BigEndian, ByteOrder::ByteSwapped, ByteOrder::LittleEndian] {
+            let mut data: Vec<u8> = rom_data();
+            // Both swaps undo themselves
+            byte_order.normalize(&mut data);
+            let rom: Rom = Rom::parse(data).unwrap();
+
+            assert_eq!(rom.byte_order, byte_order);
+            assert_eq!(rom.data, rom_data());
+            assert_eq!(rom.header, RomHeader {
+                pi_config: 0x80371240,
+                clock_rate: 0x0F,
+                boot_address: 0x80000400,
+                libultra_version: Some("2.0L".to_string()),
+                check_code: 0x635A2BFF8B022326,
+                title: "SUPER MARIO 64".to_string(),
+                game_code: "NSME".to_string(),
+                version: 1,
+                cic: Some("6102/7101"),
+                ipl3_crc: 0x90BB6CB5,
+            });
+            assert_eq!(rom.header.category(), Some("Cartridge"));
+            assert_eq!(rom.header.destination(), Some("North America"));
+        }
+    }
+
+    #[test]
+    fn identifies_the_cic_by_the_crc_of_the_boot_code() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+        let mut data: Vec<u8> = rom_data();
+        forge_ipl3_crc(&mut data, 0x6170A4A1);
+        assert_eq!(Rom::parse(data).unwrap().header.cic, Some("6101"));
+
+        let mut data: Vec<u8> = rom_data();
+        data[0x100] ^= 1;
+        let rom: Rom = Rom::parse(data).unwrap();
+        assert_eq!(rom.header.cic, None);
+        assert_eq!(rom_header_fields(&rom)[9], ("CIC", "unknown".to_string()));
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_roms() {
+        assert_eq!(Rom::parse(vec![0; ROM_IPL3_END]).unwrap_err().to_string(), "not an N64 ROM, unknown byte order");
+        assert_eq!(
+            Rom::parse(rom_data()[..0x40].to_vec()).unwrap_err().to_string(),
+            "ROM of 0x40 bytes is shorter than its header and boot code",
+        );
+    }
+
+    #[test]
+    fn maps_cartridge_addresses_to_rom_offsets() {
+        assert_eq!(Rom::offset(0x10000010), Some(0x10));
+        assert_eq!(Rom::offset(0x1FC00000), None);
+        assert_eq!(Rom::describe_offset(0x3E), Some("game code, destination"));
+        assert_eq!(Rom::describe_offset(0x40), Some("IPL3 boot code"));
+        assert_eq!(Rom::describe_offset(0x1000), None);
+
+        let rom: Rom = Rom::parse(rom_data()).unwrap();
+        assert_eq!(format_rom_bytes(rom.bytes(0x20, 6)), "53555045 5220");
+        assert_eq!(rom.bytes(ROM_IPL3_END as u32 + 0xC, 16), [0xAB; 4]);
+        assert!(rom.bytes(0x10000, 4).is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! The RSP view of memory and the disassembly of RSP microcode
+//!
+//! The RSP addresses its own memories only: DMEM at 0x000-0xFFF and IMEM at
+//! 0x1000-0x1FFF, the CPU reaching them at 0x04000000 and 0x04001000. Its
+//! program counter is 12 bits wide and wraps around within IMEM.
+//!
+//! The scalar unit runs the 32-bit MIPS instructions without multiplication,
+//! division and traps, its COP0 registers are the SP and DP command
+//! registers, and the vector unit is COP2 with its own loads and stores.
+//!
+//! Based on information from the RSP documentation here:
+//! https://n64brew.dev/wiki/Reality_Signal_Processor
+//!
+
+use crate::disasm::{decode, memory_operand, Decoded, Fields, Instruction, Processor, GPR_NAMES};
+use crate::map::{format_virtual_address, mirrored_address, sign_extend};
+use crate::registers::get_register;
+
+/// Physical address of DMEM, the start of the RSP view.
+pub const RSP_BASE: u32 = 0x04000000;
+/// RSP address of IMEM.
+pub const RSP_IMEM: u32 = 0x1000;
+/// Size of DMEM and IMEM together, the whole RSP view.
+pub const RSP_VIEW_SIZE: u32 = 0x2000;
+
+/// Physical address of an RSP address. Addresses wrap around the 8 KiB of
+/// DMEM and IMEM, as they do in the address registers of the RSP.
+pub fn rsp_to_physical(address: u32) -> u32 {
+    RSP_BASE | (address & (RSP_VIEW_SIZE - 1))
+}
+
+/// KSEG1 address the CPU reaches an RSP address through.
+pub fn rsp_to_virtual(address: u32) -> u64 {
+    sign_extend(0xA000_0000 | rsp_to_physical(address))
+}
+
+/// RSP address of a physical address of DMEM or IMEM, or of their mirrors.
+pub fn physical_to_rsp(physical_address: u32) -> Option<u32> {
+    let physical_address: u32 = mirrored_address(physical_address).unwrap_or(physical_address);
+    (RSP_BASE..RSP_BASE + RSP_VIEW_SIZE).contains(&physical_address).then(|| physical_address - RSP_BASE)
+}
+
+/// Formats an RSP address, e.g. `0x1040`.
+pub fn format_rsp_address(address: u32) -> String {
+    format!("0x{:04X}", address)
+}
+
+/// Names of the memory of an RSP address.
+pub fn rsp_memory_name(address: u32) -> &'static str {
+    if address & RSP_IMEM == 0 { "DMEM" } else { "IMEM" }
+}
+
+/// Element suffix of the vector operand of a computational instruction:
+/// whole vector, quarters, halves or a single lane.
+fn element_suffix(element: u32) -> String {
+    match element {
+        0 | 1 => String::new(),
+        2 | 3 => format!("[{}q]", element - 2),
+        4..=7 => format!("[{}h]", element - 4),
+        _ => format!("[{}]", element - 8),
+    }
+}
+
+/// Name of an RSP COP0 register, the SP registers followed by the DP command
+/// registers.
+fn cop0_name(number: usize) -> String {
+    let physical_address: u32 = if number < 8 {
+        0x04040000 + number as u32 * 4
+    } else {
+        0x04100000 + (number as u32 - 8) * 4
+    };
+    get_register(physical_address).map_or(format!("${}", number), |register| register.name.to_string())
+}
+
+/// Names of the vector control registers.
+static VECTOR_CONTROL_NAMES: [&str; 3] = ["vco", "vcc", "vce"];
+
+/// Vector instructions by function, with the operand layout of the single
+/// lane instructions marked.
+static VECTOR_OPERATIONS: &[(u32, &str, bool)] = &[
+    (0x00, "vmulf", false), (0x01, "vmulu", false), (0x02, "vrndp", false), (0x03, "vmulq", false),
+    (0x04, "vmudl", false), (0x05, "vmudm", false), (0x06, "vmudn", false), (0x07, "vmudh", false),
+    (0x08, "vmacf", false), (0x09, "vmacu", false), (0x0A, "vrndn", false), (0x0B, "vmacq", false),
+    (0x0C, "vmadl", false), (0x0D, "vmadm", false), (0x0E, "vmadn", false), (0x0F, "vmadh", false),
+    (0x10, "vadd", false), (0x11, "vsub", false), (0x13, "vabs", false), (0x14, "vaddc", false),
+    (0x15, "vsubc", false), (0x1D, "vsar", false),
+    (0x20, "vlt", false), (0x21, "veq", false), (0x22, "vne", false), (0x23, "vge", false),
+    (0x24, "vcl", false), (0x25, "vch", false), (0x26, "vcr", false), (0x27, "vmrg", false),
+    (0x28, "vand", false), (0x29, "vnand", false), (0x2A, "vor", false), (0x2B, "vnor", false),
+    (0x2C, "vxor", false), (0x2D, "vnxor", false),
+    (0x30, "vrcp", true), (0x31, "vrcpl", true), (0x32, "vrcph", true), (0x33, "vmov", true),
+    (0x34, "vrsq", true), (0x35, "vrsql", true), (0x36, "vrsqh", true),
+];
+
+/// Vector loads and stores by the function in the `rd` field, with the size
+/// their offset is scaled by.
+static VECTOR_MEMORY: [(&str, i64); 12] = [
+    ("bv", 1), ("sv", 2), ("lv", 4), ("dv", 8), ("qv", 16), ("rv", 16),
+    ("pv", 8), ("uv", 8), ("hv", 16), ("fv", 16), ("wv", 16), ("tv", 16),
+];
+
+fn decode_cop0(f: &Fields) -> Decoded {
+    match f.rs {
+        0x00 => Decoded::new("mfc0", format!("{},{}", GPR_NAMES[f.rt], cop0_name(f.rd))),
+        0x04 => Decoded::new("mtc0", format!("{},{}", GPR_NAMES[f.rt], cop0_name(f.rd))),
+        _ => Decoded::word(f.word),
+    }
+}
+
+fn decode_cop2(f: &Fields) -> Decoded {
+    let element: u32 = f.word >> 7 & 0xF;
+    let move_vector = |mnemonic: &str| Decoded::new(mnemonic, format!("{},$v{}[{}]", GPR_NAMES[f.rt], f.rd, element));
+    let move_control = |mnemonic: &str| match VECTOR_CONTROL_NAMES.get(f.rd & 3) {
+        Some(name) => Decoded::new(mnemonic, format!("{},{}", GPR_NAMES[f.rt], name)),
+        None => Decoded::word(f.word),
+    };
+
+    match f.rs {
+        0x00 => move_vector("mfc2"),
+        0x02 => move_control("cfc2"),
+        0x04 => move_vector("mtc2"),
+        0x06 => move_control("ctc2"),
+        0x10..=0x1F => {
+            let element: u32 = f.rs as u32 & 0xF;
+            let (vd, vs, vt) = (f.sa, f.rd, f.rt);
+            match f.funct {
+                0x37 => Decoded::new("vnop", String::new()),
+                0x3F => Decoded::new("vnull", String::new()),
+                funct => match VECTOR_OPERATIONS.iter().find(|(code, _, _)| *code == funct) {
+                    Some((_, mnemonic, true)) => {
+                        Decoded::new(*mnemonic, format!("$v{}[{}],$v{}[{}]", vd, vs & 7, vt, element & 7))
+                    }
+                    Some((_, mnemonic, false)) => {
+                        Decoded::new(*mnemonic, format!("$v{},$v{},$v{}{}", vd, vs, vt, element_suffix(element)))
+                    }
+                    None => Decoded::word(f.word),
+                },
+            }
+        }
+        _ => Decoded::word(f.word),
+    }
+}
+
+/// Offset of a vector load or store, the signed 7 bits scaled by its size.
+fn vector_offset(f: &Fields) -> Option<(&'static str, i64)> {
+    let (name, scale) = VECTOR_MEMORY.get(f.rd)?;
+    let offset: i64 = (((f.word & 0x7F) as i8) << 1 >> 1) as i64;
+    Some((name, offset * scale))
+}
+
+fn decode_vector_memory(f: &Fields, prefix: &str) -> Decoded {
+    let element: u32 = f.word >> 7 & 0xF;
+    match vector_offset(f) {
+        Some((name, offset)) => Decoded::new(
+            format!("{}{}", prefix, name),
+            format!("$v{}[{}],{}", f.rt, element, memory_operand(offset, f.rs)),
+        ),
+        None => Decoded::word(f.word),
+    }
+}
+
+/// True if the scalar unit runs the instruction, the MIPS instructions
+/// besides COP0 and COP2 that it shares with the CPU.
+fn is_scalar(f: &Fields) -> bool {
+    match f.op {
+        0x00 => matches!(f.funct, 0x00 | 0x02..=0x04 | 0x06..=0x09 | 0x0D | 0x20..=0x27 | 0x2A | 0x2B),
+        0x01 => matches!(f.rt, 0x00 | 0x01 | 0x10 | 0x11),
+        0x02..=0x0F => true,
+        0x20 | 0x21 | 0x23..=0x25 | 0x27 | 0x28 | 0x29 | 0x2B => true,
+        _ => false,
+    }
+}
+
+/// Decodes an RSP instruction at an RSP address. Branch and jump targets
+/// wrap around within IMEM.
+fn decode_rsp(word: u32, address: u32) -> Decoded {
+    let f: Fields = Fields::new(word);
+    match f.op {
+        0x10 => decode_cop0(&f),
+        0x12 => decode_cop2(&f),
+        0x32 => decode_vector_memory(&f, "l"),
+        0x3A => decode_vector_memory(&f, "s"),
+        _ if is_scalar(&f) => {
+            let mut decoded: Decoded = decode(word, address as u64);
+            if let Some(target) = decoded.target {
+                let imem_target: u32 = RSP_IMEM | (target as u32 & 0xFFF);
+                let operands: &str = decoded.operands.strip_suffix(&format_virtual_address(target)).unwrap_or("");
+                decoded.operands = format!("{}{}", operands, format_rsp_address(imem_target));
+                decoded.target = Some(imem_target as u64);
+            }
+            decoded
+        }
+        _ => Decoded::word(word),
+    }
+}
+
+/// DMEM address of a scalar or vector load or store based on `zero`, the
+/// usual way microcode addresses its data.
+fn dmem_reference(f: &Fields) -> Option<u64> {
+    if f.rs != 0 {
+        return None;
+    }
+    let offset: i64 = match f.op {
+        0x20..=0x2B if is_scalar(f) => f.simm() as i64,
+        0x32 | 0x3A => vector_offset(f)?.1,
+        _ => return None,
+    };
+    Some((offset as u64) & 0xFFF)
+}
+
+/// Disassembles consecutive RSP instruction words, the first one being at
+/// the RSP address given, usually in IMEM. Addresses wrap around within the
+/// memory of the first one.
+pub fn disassemble_rsp(words: &[u32], address: u32) -> Vec<Instruction> {
+    words.iter()
+        .enumerate()
+        .map(|(index, &word)| {
+            let address: u32 = (address & RSP_IMEM) | (address.wrapping_add(index as u32 * 4) & 0xFFF);
+            let decoded: Decoded = decode_rsp(word, address);
+            Instruction {
+                processor: Processor::Rsp,
+                address: address as u64,
+                word,
+                mnemonic: decoded.mnemonic,
+                operands: decoded.operands,
+                target: decoded.target,
+                reference: dmem_reference(&Fields::new(word)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_rsp_addresses_to_cpu_ones() {
+        assert_eq!(rsp_to_physical(0x1040), 0x04001040);
+        assert_eq!(rsp_to_physical(0x3040), 0x04001040);
+        assert_eq!(rsp_to_virtual(0x0010), 0xFFFFFFFFA4000010);
+        assert_eq!(physical_to_rsp(0x04001040), Some(0x1040));
+        assert_eq!(physical_to_rsp(0x04003040), Some(0x1040));
+        assert_eq!(physical_to_rsp(0x04040000), None);
+        assert_eq!(rsp_memory_name(0x0FFC), "DMEM");
+        assert_eq!(rsp_memory_name(0x1000), "IMEM");
+        assert_eq!(format_rsp_address(0x10), "0x0010");
+    }
+
+    #[test]
+    fn decodes_scalar_and_vector_instructions() {
+        let words: [u32; 6] = [0x40803800, 0x40082000, 0x4ACC6A10, 0x4B0A5BB3, 0xC8041800, 0x7C000000];
+        let texts: Vec<String> = disassemble_rsp(&words, 0x1000).iter().map(Instruction::text).collect();
+        assert_eq!(texts, [
+            "mtc0    zero,SP_SEMAPHORE",
+            "mfc0    t0,SP_STATUS",
+            "vadd    $v8,$v13,$v12[2h]",
+            "vmov    $v14[3],$v10[0]",
+            "ldv     $v4[0],0x0(zero)",
+            ".word   0x7C000000",
+        ]);
+    }
+
+    #[test]
+    fn wraps_addresses_and_targets_within_imem() {
+        let words: [u32; 4] = [0xE81F207F, 0x8C080010, 0x09000400, 0x00000018];
+        let instructions: Vec<Instruction> = disassemble_rsp(&words, 0x1FF8);
+
+        let addresses: Vec<u64> = instructions.iter().map(|instruction| instruction.address).collect();
+        assert_eq!(addresses, [0x1FF8, 0x1FFC, 0x1000, 0x1004]);
+        assert_eq!(instructions[0].text(), "sqv     $v31[0],-0x10(zero)");
+        assert_eq!(instructions[0].reference, Some(0xFF0));
+        assert_eq!(instructions[1].reference, Some(0x10));
+        assert_eq!(instructions[2].text(), "j       0x1000");
+        assert_eq!(instructions[2].target, Some(0x1000));
+        // The scalar unit has no multiplication
+        assert_eq!(instructions[3].text(), ".word   0x00000018");
+        assert!(instructions.iter().all(|instruction| instruction.processor == Processor::Rsp));
+    }
+}
@@ -0,0 +1,228 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Annotation of the addresses found in any text
+//!
+//! Crash logs, printf output, disassembly listings and bug reports hold
+//! addresses anywhere in their lines. Hexadecimal tokens looking like N64
+//! addresses, those of KSEG0 and KSEG1 with or without a `0x` or `$` prefix,
+//! e.g. `0x80246000`, `a4400010` or `ffffffff80246000`, are annotated where
+//! they are found. Other tokens of 8 or 16 digits are too often data to be
+//! looked up.
+//!
+
+use std::io::{self, BufRead, Write};
+
+use regex::{Captures, Match, Regex};
+use serde_json::json;
+
+use crate::dump::MapEntryKind;
+use crate::map::{address_location_to_string, sign_extend, AddressLocation};
+use crate::mapfile::MemoryMap;
+use crate::output::{location_to_csv, location_to_json, OutputFormat, RecordWriter};
+use crate::source::SourceLines;
+use crate::trace::TraceOptions;
+
+/// Column names of the CSV output of [`annotate_addresses`]: the line
+/// number, the column of the address in the line, the address as written,
+/// and the location columns (see [`crate::output::LOCATION_CSV_HEADER`]).
+pub const SCAN_CSV_HEADER: &[&str] = &[
+    "line",
+    "column",
+    "text",
+    "annotation",
+    "virtual",
+    "physical",
+    "segment",
+    "region",
+    "subregions",
+    "register",
+    "symbol",
+    "source",
+    "canonical",
+    "rom_offset",
+    "rsp",
+];
+
+/// The pattern of the address tokens: KSEG0 and KSEG1 addresses of 8 digits,
+/// or of 16 digits sign-extended.
+fn address_pattern() -> Regex {
+    Regex::new(r"(?i)(?:\b0x|\$|\b)((?:ffffffff)?[89ab][0-9a-f]{7})\b").unwrap()
+}
+
+/// True if a segment, region or subregion of the memory map has the short
+/// or long name, ignoring case.
+pub fn is_area_name(name: &str, map: &MemoryMap) -> bool {
+    map.entries().iter().any(|entry| {
+        entry.kind != MapEntryKind::Register
+            && (entry.short_name.eq_ignore_ascii_case(name) || entry.long_name.eq_ignore_ascii_case(name))
+    })
+}
+
+/// True if the location is in one of the areas, given by the short or long
+/// name of a segment, region or subregion, e.g. `RDRM`, `InVI` or
+/// `Video Interface`. All locations are when no area is given.
+pub fn in_areas(location: &AddressLocation, areas: &[String]) -> bool {
+    if areas.is_empty() {
+        return true;
+    }
+    let names: Vec<(&str, &str)> = location.segment.into_iter()
+        .chain(location.region.iter().chain(location.subregions.iter()).map(|(short, long)| (&**short, &**long)))
+        .collect();
+    areas.iter().any(|area| {
+        names.iter().any(|(short, long)| short.eq_ignore_ascii_case(area) || long.eq_ignore_ascii_case(area))
+    })
+}
+
+/// Read lines from `reader` and annotate each address token found in them,
+/// see the [module documentation](self), that is in one of the areas given
+/// (see [`in_areas`]). Addresses are translated through the TLB of the
+/// options, which the text doesn't update.
+///
+/// Each address is followed by its short-form description in brackets,
+/// preceded by the register it is, if any, and followed by its symbol+offset,
+/// e.g. `0xa4400010 [VI_V_CURRENT 1G.InVI]`. Lines without addresses are
+/// written as they are.
+///
+/// With the JSON formats, each address becomes an object holding the `line`
+/// number, the byte `column` it starts at, counted from 1, the `text` of the
+/// token and its `location` (see [`location_to_json`]). The CSV format holds
+/// the same, see [`SCAN_CSV_HEADER`].
+pub fn annotate_addresses<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    options: &TraceOptions,
+    areas: &[String],
+) -> io::Result<()> {
+    let pattern: Regex = address_pattern();
+    let source: Option<&SourceLines> = options.source.as_deref();
+    let mut records: RecordWriter<W> = RecordWriter::new(writer, options.format, SCAN_CSV_HEADER)?;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line: String = line?;
+        let number: usize = number + 1;
+        let mut annotated: String = String::with_capacity(line.len());
+        let mut written: usize = 0;
+
+        for caps in pattern.captures_iter(&line) {
+            let token: Match = caps.get(0).unwrap();
+            let Some(location) = token_location(&caps, options, areas) else {
+                continue;
+            };
+
+            match options.format {
+                OutputFormat::Table => {
+                    let mut annotation: String = address_location_to_string(&location);
+                    if let Some(register) = location.register {
+                        annotation = format!("{} {}", register.name, annotation);
+                    }
+                    if let Some(symbol) = options.symbols.describe(location.virtual_address) {
+                        annotation = format!("{} {}", annotation, symbol);
+                    }
+                    annotated.push_str(&line[written..token.end()]);
+                    annotated.push_str(&format!(" [{}]", annotation));
+                    written = token.end();
+                }
+                OutputFormat::Json | OutputFormat::JsonLines => {
+                    records.json(&json!({
+                        "line": number,
+                        "column": token.start() + 1,
+                        "text": token.as_str(),
+                        "location": location_to_json(&location, &options.symbols, source, options.rom.as_deref()),
+                    }))?;
+                }
+                OutputFormat::Csv => {
+                    let mut fields: Vec<String> =
+                        vec![number.to_string(), (token.start() + 1).to_string(), token.as_str().to_string()];
+                    fields.extend(location_to_csv(&location, &options.symbols, source));
+                    records.csv(&fields)?;
+                }
+            }
+        }
+
+        if options.format == OutputFormat::Table {
+            annotated.push_str(&line[written..]);
+            writeln!(records.writer, "{}", annotated)?;
+        }
+    }
+
+    records.finish()
+}
+
+/// The location of an address token, if it is in one of the areas.
+fn token_location(caps: &Captures, options: &TraceOptions, areas: &[String]) -> Option<AddressLocation> {
+    let digits: &str = caps.get(1)?.as_str();
+    let address: u64 = sign_extend(u64::from_str_radix(digits, 16).ok()? as u32);
+    let location: AddressLocation = options.map.lookup(address, &options.tlb);
+    in_areas(&location, areas).then_some(location)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    use crate::map::get_segment_region_subregion;
+
+    const CRASH_LOG: &str = "Exception at PC 0x80246008, badvaddr FFFFFFFFA4400010\n\
+                             \x20 ra = 80246004 t0 = 00003303 a0 = $90000000 id = 1234567890abcdef\n";
+
+    fn annotate(options: &TraceOptions, areas: &[&str]) -> String {
+        let areas: Vec<String> = areas.iter().map(|area| area.to_string()).collect();
+        let mut output: Vec<u8> = Vec::new();
+        annotate_addresses(CRASH_LOG.as_bytes(), &mut output, options, &areas).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn annotates_the_addresses_found_in_text() {
+        assert_eq!(
+            annotate(&TraceOptions::default(), &[]),
+            "Exception at PC 0x80246008 [0R.RDRM], badvaddr FFFFFFFFA4400010 [VI_V_CURRENT 1G.InVI]\n\
+             \x20 ra = 80246004 [0R.RDRM] t0 = 00003303 a0 = $90000000 [0P.CROM] id = 1234567890abcdef\n",
+        );
+    }
+
+    #[test]
+    fn only_annotates_addresses_in_the_areas_given() {
+        assert_eq!(
+            annotate(&TraceOptions::default(), &["video interface"]),
+            "Exception at PC 0x80246008, badvaddr FFFFFFFFA4400010 [VI_V_CURRENT 1G.InVI]\n\
+             \x20 ra = 80246004 t0 = 00003303 a0 = $90000000 id = 1234567890abcdef\n",
+        );
+
+        let location: AddressLocation = get_segment_region_subregion(0x80246008);
+        assert!(in_areas(&location, &[]));
+        assert!(in_areas(&location, &["kseg0".to_string()]));
+        assert!(in_areas(&location, &["RDRM".to_string(), "InVI".to_string()]));
+        assert!(!in_areas(&location, &["InVI".to_string()]));
+    }
+
+    #[test]
+    fn area_names_are_segments_regions_or_subregions() {
+        let map: MemoryMap = MemoryMap::default();
+        assert!(is_area_name("KSEG1", &map));
+        assert!(is_area_name("rdram", &map));
+        assert!(is_area_name("InVI", &map));
+        assert!(!is_area_name("VI_ORIGIN", &map));
+        assert!(!is_area_name("nowhere", &map));
+    }
+
+    #[test]
+    fn writes_csv_and_json_records() {
+        let options: TraceOptions = TraceOptions { format: OutputFormat::Csv, ..TraceOptions::default() };
+        let output: String = annotate(&options, &["InVI"]);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines, [
+            SCAN_CSV_HEADER.join(","),
+            "1,38,FFFFFFFFA4400010,1G.InVI,0xA4400010,0x04400010,1,G,InVI,VI_V_CURRENT,,,,,".to_string(),
+        ]);
+
+        let options: TraceOptions = TraceOptions { format: OutputFormat::Json, ..TraceOptions::default() };
+        let records: Value = serde_json::from_str(&annotate(&options, &[])).unwrap();
+        let texts: Vec<&str> = records.as_array().unwrap().iter().map(|record| record["text"].as_str().unwrap()).collect();
+        assert_eq!(texts, ["0x80246008", "FFFFFFFFA4400010", "80246004", "$90000000"]);
+        assert_eq!(records[3]["line"], 2);
+        assert_eq!(records[3]["column"], 36);
+        assert_eq!(records[3]["location"]["annotation"], "0P.CROM");
+    }
+}
@@ -0,0 +1,190 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Reverse lookup from region, subregion or register names to address ranges
+//!
+//! Names are compared ignoring case. Exact matches are preferred over names
+//! containing the query, which in turn are preferred over names within an
+//! edit distance of a quarter of the query length, so typos still find
+//! something.
+//!
+
+use std::borrow::Cow;
+
+use crate::mapfile::{MapRegion, MemoryMap};
+use crate::registers::{Register, REGISTER_BLOCKS};
+
+/// What kind of table entry a name matched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    Region,
+    Subregion,
+    Register,
+}
+
+/// A table entry whose name matched a query.
+#[derive(Debug, Clone)]
+pub struct NameMatch {
+    pub kind: NameKind,
+    pub short_name: Cow<'static, str>,
+    pub long_name: Cow<'static, str>,
+    /// First physical address
+    pub start: u32,
+    /// Last physical address
+    pub end: u32,
+}
+
+impl NameMatch {
+    /// Virtual range of the match through KSEG0, if it is reachable there.
+    pub fn kseg0(&self) -> Option<(u32, u32)> {
+        direct_mapped(self.start, self.end, 0x8000_0000)
+    }
+
+    /// Virtual range of the match through KSEG1, if it is reachable there.
+    pub fn kseg1(&self) -> Option<(u32, u32)> {
+        direct_mapped(self.start, self.end, 0xA000_0000)
+    }
+}
+
+/// KSEG0 and KSEG1 only map the first 512 MiB of physical address space.
+fn direct_mapped(start: u32, end: u32, base: u32) -> Option<(u32, u32)> {
+    if end <= 0x1FFF_FFFF {
+        Some((start | base, end | base))
+    } else {
+        None
+    }
+}
+
+/// How closely a name matches a query, lower is better.
+fn match_tier(query: &str, name: &str) -> Option<u32> {
+    let name: String = name.to_uppercase();
+    if name == query {
+        Some(0)
+    } else if name.contains(query) {
+        Some(1)
+    } else if edit_distance(query, &name) <= query.chars().count() / 4 {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal: usize = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution: usize = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+fn region_match(kind: NameKind, region: &MapRegion) -> NameMatch {
+    NameMatch {
+        kind,
+        short_name: region.2.clone(),
+        long_name: region.3.clone(),
+        start: region.0,
+        end: region.1,
+    }
+}
+
+fn register_match(start: u32, register: &'static Register) -> NameMatch {
+    NameMatch {
+        kind: NameKind::Register,
+        short_name: Cow::Borrowed(register.name),
+        long_name: Cow::Borrowed(register.description),
+        start: start + register.offset,
+        end: start + register.offset + register.width / 8 - 1,
+    }
+}
+
+/// Find the regions, subregions and registers named like `query`, returning
+/// only the closest matches. Registers match by name and aliases, regions and
+/// subregions by short and long name. Regions and subregions are those of
+/// the built-in tables, see [`MemoryMap::find_by_name`] for another map.
+pub fn find_by_name(query: &str) -> Vec<NameMatch> {
+    MemoryMap::default().find_by_name(query)
+}
+
+impl MemoryMap {
+    /// Same as [`find_by_name`], searching the regions and subregions of this
+    /// map.
+    pub fn find_by_name(&self, query: &str) -> Vec<NameMatch> {
+        let query: String = query.trim().to_uppercase();
+        let mut candidates: Vec<(u32, NameMatch)> = Vec::new();
+
+        let tables: [(NameKind, &[MapRegion]); 2] = [
+            (NameKind::Region, &self.regions),
+            (NameKind::Subregion, &self.subregions),
+        ];
+        for (kind, table) in tables {
+            for region in table.iter() {
+                let tier: Option<u32> = [&region.2, &region.3].iter()
+                    .filter_map(|name| match_tier(&query, name))
+                    .min();
+                if let Some(tier) = tier {
+                    candidates.push((tier, region_match(kind, region)));
+                }
+            }
+        }
+
+        for block in REGISTER_BLOCKS.iter() {
+            for register in block.registers.iter() {
+                let tier: Option<u32> = std::iter::once(register.name)
+                    .chain(register.aliases.iter().copied())
+                    .filter_map(|name| match_tier(&query, name))
+                    .min();
+                if let Some(tier) = tier {
+                    candidates.push((tier, register_match(block.start, register)));
+                }
+            }
+        }
+
+        let best: Option<u32> = candidates.iter().map(|(tier, _)| *tier).min();
+        candidates.into_iter()
+            .filter(|(tier, _)| Some(*tier) == best)
+            .map(|(_, found)| found)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(found: &[NameMatch]) -> Vec<&str> {
+        found.iter().map(|found| &*found.short_name).collect()
+    }
+
+    #[test]
+    fn exact_matches_win_over_partial_ones() {
+        let found: Vec<NameMatch> = find_by_name("vi_origin");
+        assert_eq!(names(&found), vec!["VI_ORIGIN"]);
+        assert_eq!(found[0].kind, NameKind::Register);
+        assert_eq!((found[0].start, found[0].end), (0x04400004, 0x04400007));
+        assert_eq!(found[0].kseg1(), Some((0xA4400004, 0xA4400007)));
+    }
+
+    #[test]
+    fn long_names_with_typos_still_match() {
+        let found: Vec<NameMatch> = find_by_name("video interfase");
+        assert_eq!(names(&found), vec!["InVI"]);
+        assert_eq!(found[0].kind, NameKind::Subregion);
+        assert_eq!(found[0].kseg0(), Some((0x84400000, 0x844FFFFF)));
+    }
+
+    #[test]
+    fn ranges_above_512_mib_have_no_direct_mapping() {
+        assert_eq!(direct_mapped(0x20000000, 0x7FFFFFFF, 0x8000_0000), None);
+        assert_eq!(edit_distance("VI_ORGIN", "VI_ORIGIN"), 1);
+        assert!(find_by_name("no such thing at all").is_empty());
+    }
+}
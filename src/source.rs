@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Source line lookup from the DWARF debug information of an ELF file
+//!
+//! Works like addr2line: an address resolves to its `file:line`, preceded by
+//! the chain of functions inlined at that address.
+//!
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
+
+use object::{Object, ObjectSection};
+
+type Reader = gimli::EndianRcSlice<gimli::RunTimeEndian>;
+
+/// One function of the inline chain of an address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFrame {
+    pub function: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+impl SourceFrame {
+    /// Formats the location as `file:line`, with `??` for unknown parts.
+    pub fn location(&self) -> String {
+        format!(
+            "{}:{}",
+            self.file.as_deref().unwrap_or("??"),
+            self.line.map_or("?".to_string(), |line| line.to_string()),
+        )
+    }
+}
+
+/// The DWARF line and inlining information of an ELF file.
+pub struct SourceLines {
+    context: addr2line::Context<Reader>,
+    is_32bit: bool,
+}
+
+fn invalid_data<E: std::fmt::Display>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+impl SourceLines {
+    /// Parse the DWARF sections of an ELF file.
+    pub fn parse(data: &[u8]) -> io::Result<SourceLines> {
+        let file = object::File::parse(data).map_err(invalid_data)?;
+        let endian: gimli::RunTimeEndian = if file.is_little_endian() {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
+
+        let dwarf = gimli::Dwarf::load(|id: gimli::SectionId| -> Result<Reader, io::Error> {
+            let section: Cow<[u8]> = match file.section_by_name(id.name()) {
+                Some(section) => section.data().map_err(invalid_data)?.into(),
+                None => Cow::Borrowed(&[]),
+            };
+            Ok(gimli::EndianRcSlice::new(Rc::from(&*section), endian))
+        })?;
+
+        Ok(SourceLines {
+            context: addr2line::Context::from_dwarf(dwarf).map_err(invalid_data)?,
+            is_32bit: !file.is_64(),
+        })
+    }
+
+    /// Read the DWARF information of the named ELF file.
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> io::Result<SourceLines> {
+        SourceLines::parse(&fs::read(filename)?)
+    }
+
+    /// Source locations of a virtual address, innermost inlined function first
+    /// and the function it was inlined into last. Empty if the address is not
+    /// covered by the debug information.
+    pub fn lookup(&self, address: u64) -> Vec<SourceFrame> {
+        // 32-bit ELF files hold addresses without sign-extension
+        let probe: u64 = if self.is_32bit { address & 0xFFFF_FFFF } else { address };
+
+        let mut frames: Vec<SourceFrame> = Vec::new();
+        let Ok(mut iter) = self.context.find_frames(probe).skip_all_loads() else {
+            return frames;
+        };
+
+        while let Ok(Some(frame)) = iter.next() {
+            frames.push(SourceFrame {
+                function: frame.function
+                    .and_then(|function| function.raw_name().ok().map(|name| name.into_owned())),
+                file: frame.location.as_ref().and_then(|location| location.file.map(str::to_string)),
+                line: frame.location.as_ref().and_then(|location| location.line),
+            });
+        }
+
+        frames
+    }
+
+    /// Short-form source location of an address, e.g. `vi.c:42`, followed by
+    /// the locations of the callers of inlined functions, e.g.
+    /// `vi.h:10 < vi.c:42`.
+    pub fn describe(&self, address: u64) -> Option<String> {
+        let frames: Vec<SourceFrame> = self.lookup(address);
+        if frames.is_empty() {
+            return None;
+        }
+        let locations: Vec<String> = frames.iter()
+            .map(|frame| {
+                let location: String = frame.location();
+                // Only the file name, directories make the column too wide
+                match location.rsplit_once('/') {
+                    Some((_, file)) => file.to_string(),
+                    None => location,
+                }
+            })
+            .collect();
+        Some(locations.join(" < "))
+    }
+}
+
+impl std::fmt::Debug for SourceLines {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceLines").field("is_32bit", &self.is_32bit).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_unknown_parts_of_a_location() {
+        let frame: SourceFrame = SourceFrame {
+            function: Some("osViSetMode".to_string()),
+            file: Some("src/vi.c".to_string()),
+            line: Some(42),
+        };
+        assert_eq!(frame.location(), "src/vi.c:42");
+
+        let frame: SourceFrame = SourceFrame { function: None, file: None, line: None };
+        assert_eq!(frame.location(), "??:?");
+    }
+
+    #[test]
+    fn resolves_addresses_to_lines_and_inlined_functions() {
+        let source: SourceLines = SourceLines::from_file("tests/data/vi.elf").unwrap();
+        assert_eq!(source.lookup(0xFFFF_FFFF_8000_0400), vec![
+            SourceFrame { function: Some("vi_write".to_string()), file: Some("./vi.c".to_string()), line: Some(11) },
+            SourceFrame { function: Some("vi_init".to_string()), file: Some("./vi.c".to_string()), line: Some(16) },
+        ]);
+        assert_eq!(source.describe(0xFFFF_FFFF_8000_0400).as_deref(), Some("vi.c:11 < vi.c:16"));
+        assert_eq!(source.describe(0x8000_0400).as_deref(), Some("vi.c:11 < vi.c:16"));
+        assert_eq!(source.describe(0xFFFF_FFFF_8000_040A).as_deref(), Some("vi.c:17"));
+        assert_eq!(source.describe(0xFFFF_FFFF_8000_1000), None);
+    }
+
+    #[test]
+    fn rejects_files_other_than_elf() {
+        let error: io::Error = SourceLines::parse(b"Linker script and memory map").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
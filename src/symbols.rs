@@ -0,0 +1,264 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Symbol tables to resolve addresses to function+offset
+//!
+//! Symbols are loaded from the symtab of ELF files, from GNU linker map files
+//! (as produced by libdragon builds), and from splat-style `symbol_addrs.txt`
+//! files, e.g. `osViSetMode = 0x80001234; // type:func size:0x40`.
+//!
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use object::{Object, ObjectSymbol, SymbolKind};
+use regex::Regex;
+
+use crate::map::{sign_extend, AddressLocation};
+use crate::mapfile::MemoryMap;
+use crate::rsp::rsp_to_virtual;
+use crate::tlb::Tlb;
+
+/// A named address, sign-extended to 64 bits like the virtual addresses of the
+/// lookup.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: u64,
+    /// Size in bytes, when known
+    pub size: Option<u64>,
+    pub name: String,
+}
+
+/// Symbols sorted by address.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+    /// Size of the largest sized symbol, how far back a lookup looks for a
+    /// symbol holding the address
+    max_size: u64,
+}
+
+impl SymbolTable {
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Add symbols to the table, keeping it sorted by address.
+    pub fn extend<I: IntoIterator<Item = Symbol>>(&mut self, symbols: I) {
+        self.symbols.extend(symbols);
+        self.symbols.sort_by(|a, b| a.address.cmp(&b.address).then_with(|| a.name.cmp(&b.name)));
+        self.symbols.dedup();
+        self.max_size = self.symbols.iter().filter_map(|symbol| symbol.size).max().unwrap_or(0);
+    }
+
+    /// Find the symbol containing the address, along with the offset of the
+    /// address from the symbol. Symbols without a size extend up to the next
+    /// symbol, without leaving the segment and subregion they are in. Sized
+    /// symbols hold the smaller ones starting inside them, e.g. the local
+    /// labels of a function, so the closest symbol holding the address wins.
+    pub fn lookup(&self, address: u64) -> Option<(&Symbol, u64)> {
+        let position: usize = self.symbols.partition_point(|symbol| symbol.address <= address);
+        let closest: &Symbol = self.symbols[..position].last()?;
+        let offset: u64 = address - closest.address;
+        if closest.size.map_or(true, |size| size == 0) && (offset == 0 || same_area(closest.address, address)) {
+            return Some((closest, offset));
+        }
+
+        // Unsized symbols end at the next symbol, so only sized ones may hold
+        // the address from further back
+        self.symbols[..position].iter()
+            .rev()
+            .take_while(|symbol| address - symbol.address < self.max_size)
+            .find(|symbol| symbol.size.is_some_and(|size| address - symbol.address < size))
+            .map(|symbol| (symbol, address - symbol.address))
+    }
+
+    /// Find a symbol by name.
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// Load symbols from a file, detecting whether it is an ELF file, a linker
+    /// map file or a `symbol_addrs.txt` file.
+    pub fn load<P: AsRef<Path>>(&mut self, filename: P) -> io::Result<()> {
+        let data: Vec<u8> = fs::read(filename)?;
+        if data.starts_with(b"\x7fELF") {
+            self.extend(parse_elf(&data)?);
+        } else {
+            let text: String = String::from_utf8(data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if text.contains("Linker script and memory map") || text.contains("Memory Configuration") {
+                self.extend(parse_map(&text));
+            } else {
+                self.extend(parse_symbol_addrs(&text));
+            }
+        }
+        Ok(())
+    }
+
+    /// Load microcode symbols from a file like [`SymbolTable::load`]. Their
+    /// addresses are RSP addresses, IMEM starting at 0x1000, or CPU addresses
+    /// of DMEM and IMEM, and are stored at the KSEG1 address reaching them.
+    pub fn load_rsp<P: AsRef<Path>>(&mut self, filename: P) -> io::Result<()> {
+        let mut table: SymbolTable = SymbolTable::default();
+        table.load(filename)?;
+        self.extend(table.symbols.into_iter().map(|symbol| Symbol {
+            address: rsp_to_virtual(symbol.address as u32),
+            ..symbol
+        }));
+        Ok(())
+    }
+
+    /// Short-form description of the symbol containing the address, e.g.
+    /// `osViSetMode+0x1c`.
+    pub fn describe(&self, address: u64) -> Option<String> {
+        self.lookup(address).map(|(symbol, offset)| symbol_to_string(symbol, offset))
+    }
+}
+
+/// True if both addresses are in the same segment and subregion.
+fn same_area(a: u64, b: u64) -> bool {
+    let map: MemoryMap = MemoryMap::default();
+    let a: AddressLocation = map.lookup(a, &Tlb::default());
+    let b: AddressLocation = map.lookup(b, &Tlb::default());
+    a.segment == b.segment && a.subregions == b.subregions
+}
+
+/// Formats a symbol and offset as `name+0x1c`, or `name` without offset.
+pub fn symbol_to_string(symbol: &Symbol, offset: u64) -> String {
+    if offset == 0 {
+        symbol.name.clone()
+    } else {
+        format!("{}+{:#x}", symbol.name, offset)
+    }
+}
+
+/// Function and data symbols defined in the symtab of an ELF file.
+pub fn parse_elf(data: &[u8]) -> io::Result<Vec<Symbol>> {
+    let file = object::File::parse(data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let is_32bit: bool = !file.is_64();
+
+    let symbols: Vec<Symbol> = file.symbols()
+        .filter(|symbol| symbol.is_definition() && matches!(symbol.kind(), SymbolKind::Text | SymbolKind::Data))
+        .filter_map(|symbol| {
+            let name: &str = symbol.name().ok().filter(|name| !name.is_empty())?;
+            let address: u64 = if is_32bit { sign_extend(symbol.address() as u32) } else { symbol.address() };
+            Some(Symbol {
+                address,
+                size: Some(symbol.size()).filter(|size| *size > 0),
+                name: name.to_string(),
+            })
+        })
+        .collect();
+
+    Ok(symbols)
+}
+
+/// Symbols of a GNU linker map file, i.e. lines holding only an address and a
+/// name, e.g. `                0x0000000080000450                main`.
+pub fn parse_map(text: &str) -> Vec<Symbol> {
+    let re: Regex = Regex::new(r"^\s+0x([0-9a-fA-F]{8,16})\s+([A-Za-z_.$][\w.$]*)\s*$").unwrap();
+
+    text.lines()
+        .filter_map(|line| re.captures(line))
+        .filter_map(|caps| Some(Symbol {
+            address: parse_address(&caps[1])?,
+            size: None,
+            name: caps[2].to_string(),
+        }))
+        .collect()
+}
+
+/// Symbols of a splat `symbol_addrs.txt` file, i.e. lines such as
+/// `func_80000400 = 0x80000400; // type:func size:0x40`.
+pub fn parse_symbol_addrs(text: &str) -> Vec<Symbol> {
+    let re: Regex = Regex::new(r"^\s*([A-Za-z_.$][\w.$]*)\s*=\s*0x([0-9a-fA-F]+)\s*;(.*)$").unwrap();
+    let size_re: Regex = Regex::new(r"\bsize:0x([0-9a-fA-F]+)").unwrap();
+
+    text.lines()
+        .filter_map(|line| re.captures(line))
+        .filter_map(|caps| Some(Symbol {
+            address: parse_address(&caps[2])?,
+            size: size_re.captures(&caps[3])
+                .and_then(|size| u64::from_str_radix(&size[1], 16).ok()),
+            name: caps[1].to_string(),
+        }))
+        .collect()
+}
+
+/// Addresses that fit in 32 bits are sign-extended; those that do not fit in
+/// 64 bits are `None`.
+fn parse_address(digits: &str) -> Option<u64> {
+    let address: u64 = u64::from_str_radix(digits, 16).ok()?;
+    if address <= u32::MAX as u64 {
+        Some(sign_extend(address as u32))
+    } else {
+        Some(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(address: u64, size: Option<u64>, name: &str) -> Symbol {
+        Symbol { address, size, name: name.to_string() }
+    }
+
+    #[test]
+    fn lookup_finds_sized_symbol_holding_smaller_one() {
+        let mut table: SymbolTable = SymbolTable::default();
+        table.extend([
+            symbol(0xFFFF_FFFF_8000_1000, Some(0x100), "func"),
+            symbol(0xFFFF_FFFF_8000_1010, Some(0x4), "func_data"),
+        ]);
+        assert_eq!(table.describe(0xFFFF_FFFF_8000_1012).as_deref(), Some("func_data+0x2"));
+        assert_eq!(table.describe(0xFFFF_FFFF_8000_1020).as_deref(), Some("func+0x20"));
+        assert_eq!(table.describe(0xFFFF_FFFF_8000_1100), None);
+    }
+
+    #[test]
+    fn parses_linker_map_symbols() {
+        let map: String = [
+            " .text          0x0000000080000400     0x1230 build/main.o",
+            "                0x0000000080000450                main",
+            "                0x0000000080000400                _start",
+        ].join("\n");
+        let symbols: Vec<Symbol> = parse_map(&map);
+        assert_eq!(symbols, vec![
+            symbol(0xFFFF_FFFF_8000_0450, None, "main"),
+            symbol(0xFFFF_FFFF_8000_0400, None, "_start"),
+        ]);
+    }
+
+    #[test]
+    fn parses_symbol_addrs_with_sizes() {
+        let text: &str = "osViSetMode = 0x80001234; // type:func size:0x40\n\
+                          // comment\n\
+                          gCounter = 0x80100000;\n\
+                          gHuge = 0x180000000000000000;\n";
+        let symbols: Vec<Symbol> = parse_symbol_addrs(text);
+        assert_eq!(symbols, vec![
+            symbol(0xFFFF_FFFF_8000_1234, Some(0x40), "osViSetMode"),
+            symbol(0xFFFF_FFFF_8010_0000, None, "gCounter"),
+        ]);
+    }
+
+    #[test]
+    fn unsized_symbols_extend_up_to_the_next_area() {
+        let mut table: SymbolTable = SymbolTable::default();
+        table.extend([
+            symbol(0xFFFF_FFFF_8000_0450, None, "main"),
+            symbol(0xFFFF_FFFF_8000_0400, None, "_start"),
+        ]);
+        assert_eq!(table.describe(0xFFFF_FFFF_8000_0404).as_deref(), Some("_start+0x4"));
+        assert_eq!(table.describe(0xFFFF_FFFF_8002_0000).as_deref(), Some("main+0x1fbb0"));
+        assert_eq!(table.describe(0xFFFF_FFFF_A400_0000), None);
+        assert_eq!(table.find("main").map(|symbol| symbol.address), Some(0xFFFF_FFFF_8000_0450));
+    }
+}
@@ -0,0 +1,389 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Human-readable output of address lookups
+//!
+//! The tables printed by the command line tool for the `table` output format,
+//! see [`crate::output`] for the machine-readable ones.
+//!
+
+use std::fmt::Write;
+
+use tabular::{Row, Table};
+
+use crate::map::{address_location_to_string, format_virtual_address, AddressLocation};
+use crate::output::ROM_BYTES;
+use crate::range::{range_size, RangePiece};
+use crate::registers::{decode_register, Register};
+use crate::rom::{format_rom_bytes, Rom};
+use crate::rsp::{format_rsp_address, rsp_memory_name};
+use crate::search::NameMatch;
+use crate::source::{SourceFrame, SourceLines};
+use crate::symbols::SymbolTable;
+use crate::tlb::TlbTranslation;
+
+/// Renders address details as a table of labelled rows, e.g.
+/// `Physical Address: 0x04400004`.
+pub fn render_location(addr: &AddressLocation, symbols: &SymbolTable, source: Option<&SourceLines>, rom: Option<&Rom>) -> String {
+    let mut table: Table = Table::new("{:<} {:<}");
+
+    table.add_row(
+        Row::new()
+            .with_cell("Annotation:")
+            .with_cell(
+                address_location_to_string(addr)
+            )
+    );
+
+    table.add_row(
+        Row::new()
+            .with_cell("Virtual Address:")
+            .with_cell(
+                format_virtual_address(addr.virtual_address)
+            )
+    );
+
+    if let Some(symbol) = symbols.describe(addr.virtual_address) {
+        table.add_row(
+            Row::new()
+                .with_cell("Symbol:")
+                .with_cell(symbol)
+        );
+    }
+
+    let frames: Vec<SourceFrame> = source.map_or(Vec::new(), |source| source.lookup(addr.virtual_address));
+    for frame in frames.iter() {
+        table.add_row(
+            Row::new()
+                .with_cell("Source:")
+                .with_cell(format!(
+                    "{} in {}",
+                    frame.location(),
+                    frame.function.as_deref().unwrap_or("??"),
+                ))
+        );
+    }
+
+    table.add_row(
+        Row::new()
+            .with_cell("Physical Address:")
+            .with_cell(
+                addr.physical_address.map_or(
+                    "None".to_string(),
+                    |address| format!("0x{:08X}", address)
+                )
+            )
+    );
+
+    table.add_row(
+        Row::new()
+            .with_cell("Segment:")
+            .with_cell(
+                addr.segment.map_or(
+                    "Unknown".to_string(),
+                    |(a, b)| format!("{}, {}", a, b)
+                )
+            )
+    );
+
+    match addr.tlb {
+        Some(TlbTranslation::Hit { index, page_size, asid, global, valid, dirty, cache_attribute, .. }) => {
+            table.add_row(
+                Row::new()
+                    .with_cell("TLB:")
+                    .with_cell(format!(
+                        "Entry {}, {} KiB page, {}, {}{}, cache attribute {}",
+                        index,
+                        page_size / 1024,
+                        if global { "global".to_string() } else { format!("ASID {}", asid) },
+                        if valid { "valid" } else { "invalid" },
+                        if dirty { ", dirty" } else { "" },
+                        cache_attribute,
+                    ))
+            );
+        }
+        Some(TlbTranslation::Miss) => {
+            table.add_row(
+                Row::new()
+                    .with_cell("TLB:")
+                    .with_cell("Miss")
+            );
+        }
+        None => {}
+    }
+
+    if let Some((attribute, description)) = addr.cache_attribute {
+        table.add_row(
+            Row::new()
+                .with_cell("Cache Attribute:")
+                .with_cell(format!("{}, {}", attribute, description))
+        );
+    }
+
+    table.add_row(
+        Row::new()
+            .with_cell("Region:")
+            .with_cell(
+                addr.region.as_ref().map_or(
+                    "Unknown".to_string(),
+                    |(a, b)| format!("{}, {}", a, b)
+                )
+            )
+    );
+
+    for subregion in addr.subregions.iter() {
+        table.add_row(
+            Row::new()
+                .with_cell("Subregion:")
+                .with_cell(format!("{}, {}", subregion.0, subregion.1).as_str())
+        );
+    }
+
+    if let Some(canonical) = &addr.canonical {
+        table.add_row(
+            Row::new()
+                .with_cell("Mirror Of:")
+                .with_cell(canonical.to_string())
+        );
+    }
+
+    if let Some(address) = addr.rsp_address() {
+        table.add_row(
+            Row::new()
+                .with_cell("RSP Address:")
+                .with_cell(format!("{}, {}", format_rsp_address(address), rsp_memory_name(address)))
+        );
+    }
+
+    if let Some(offset) = addr.rom_offset() {
+        table.add_row(
+            Row::new()
+                .with_cell("ROM Offset:")
+                .with_cell(match Rom::describe_offset(offset) {
+                    Some(field) => format!("0x{:08X}, {}", offset, field),
+                    None => format!("0x{:08X}", offset),
+                })
+        );
+        if let Some(rom) = rom {
+            let bytes: &[u8] = rom.bytes(offset, ROM_BYTES);
+            table.add_row(
+                Row::new()
+                    .with_cell("ROM Bytes:")
+                    .with_cell(if bytes.is_empty() {
+                        format!("None, beyond the end of the {:#x} byte ROM", rom.data.len())
+                    } else {
+                        format_rom_bytes(bytes)
+                    })
+            );
+        }
+    }
+
+    if let Some(register) = addr.register {
+        table.add_row(
+            Row::new()
+                .with_cell("Register:")
+                .with_cell(format!(
+                    "{}, {} ({}, {}-bit)",
+                    register.name,
+                    register.description,
+                    register.access,
+                    register.width,
+                ))
+        );
+    }
+
+    table.to_string()
+}
+
+/// Renders the pieces of a range as a table under a summary line, followed
+/// by the warnings about them.
+pub fn render_range(pieces: &[RangePiece]) -> String {
+    let mut text: String = String::new();
+    let (Some(first), Some(last)) = (pieces.first(), pieces.last()) else {
+        return text;
+    };
+    let _ = writeln!(
+        text,
+        "Range: {}-{}, {:#x} bytes in {} piece{}",
+        format_virtual_address(first.start),
+        format_virtual_address(last.end),
+        range_size(first.start, last.end),
+        pieces.len(),
+        if pieces.len() == 1 { "" } else { "s" },
+    );
+
+    let mut table: Table = Table::new("{:<} {:<} {:>} {:<} {:<}");
+    table.add_row(
+        Row::new()
+            .with_cell("Virtual")
+            .with_cell("Physical")
+            .with_cell("Size")
+            .with_cell("Annotation")
+            .with_cell("Area")
+    );
+    for piece in pieces.iter() {
+        let area: String = match (piece.location.subregions.last(), &piece.location.region) {
+            (Some((_, name)), _) | (None, Some((_, name))) => name.to_string(),
+            (None, None) => String::new(),
+        };
+        table.add_row(
+            Row::new()
+                .with_cell(format!("{}-{}", format_virtual_address(piece.start), format_virtual_address(piece.end)))
+                .with_cell(format_range(piece.physical_range()))
+                .with_cell(format!("{:#x}", piece.size()))
+                .with_cell(address_location_to_string(&piece.location))
+                .with_cell(area)
+        );
+    }
+    let _ = write!(text, "{}", table);
+
+    for piece in pieces.iter() {
+        for warning in piece.warnings.iter() {
+            let _ = writeln!(text, "Warning: {} at {}", warning, format_virtual_address(piece.start));
+        }
+    }
+
+    text
+}
+
+/// Renders the bitfields of a register value read or written as a table.
+pub fn render_decoded_register(register: &Register, value: u32, write: bool) -> String {
+    let mut table: Table = Table::new("{:<} {:<} {:>} {:<}");
+
+    table.add_row(
+        Row::new()
+            .with_cell("Register:")
+            .with_cell(register.name)
+            .with_cell("")
+            .with_cell(register.description)
+    );
+
+    table.add_row(
+        Row::new()
+            .with_cell("Value:")
+            .with_cell(format!("0x{:08X}", value))
+            .with_cell("")
+            .with_cell(if write { "written" } else { "read" })
+    );
+
+    for (field, field_value) in decode_register(register, value, write) {
+        let bits: String = if field.bits == 1 {
+            format!("[{}]", field.lsb)
+        } else {
+            format!("[{}:{}]", field.msb(), field.lsb)
+        };
+        let meaning: String = match field.value_name(field_value) {
+            Some(name) => format!("{}: {}", field.description, name),
+            None => field.description.to_string(),
+        };
+        table.add_row(
+            Row::new()
+                .with_cell(bits)
+                .with_cell(field.name)
+                .with_cell(format!("{:#x}", field_value))
+                .with_cell(meaning)
+        );
+    }
+
+    table.to_string()
+}
+
+/// Formats an inclusive address range, or a placeholder if there is none.
+pub fn format_range(range: Option<(u32, u32)>) -> String {
+    range.map_or(
+        "-".to_string(),
+        |(start, end)| format!("0x{:08X}-0x{:08X}", start, end)
+    )
+}
+
+/// Renders the address ranges of the matches of a name as a table.
+pub fn render_matches(matches: &[NameMatch]) -> String {
+    let mut table: Table = Table::new("{:<} {:<} {:<} {:<} {:<} {:<}");
+
+    table.add_row(
+        Row::new()
+            .with_cell("Name")
+            .with_cell("Description")
+            .with_cell("Kind")
+            .with_cell("Physical")
+            .with_cell("KSEG0")
+            .with_cell("KSEG1")
+    );
+
+    for found in matches.iter() {
+        table.add_row(
+            Row::new()
+                .with_cell(&*found.short_name)
+                .with_cell(&*found.long_name)
+                .with_cell(format!("{:?}", found.kind))
+                .with_cell(format_range(Some((found.start, found.end))))
+                .with_cell(format_range(found.kseg0()))
+                .with_cell(format_range(found.kseg1()))
+        );
+    }
+
+    table.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::get_segment_region_subregion;
+    use crate::mapfile::MemoryMap;
+    use crate::registers::find_register;
+    use crate::search::find_by_name;
+    use crate::tlb::Tlb;
+
+    #[test]
+    fn renders_locations_as_labelled_rows() {
+        let location: AddressLocation = get_segment_region_subregion(0xA4400004);
+        let text: String = render_location(&location, &SymbolTable::default(), None, None);
+        assert_eq!(text.lines().collect::<Vec<&str>>(), [
+            "Annotation:       1G.InVI",
+            "Virtual Address:  0xA4400004",
+            "Physical Address: 0x04400004",
+            "Segment:          1, KSEG1",
+            "Region:           G, RCP",
+            "Subregion:        InVI, Video Interface",
+            "Register:         VI_ORIGIN, Framebuffer origin in RDRAM (RW, 32-bit)",
+        ]);
+    }
+
+    #[test]
+    fn renders_ranges_with_their_warnings() {
+        let mut map: MemoryMap = MemoryMap::default();
+        map.set_rdram_size(0x400000).unwrap();
+        let pieces: Vec<RangePiece> = map.split_range(0xFFFFFFFF803FFFF0, 0xFFFFFFFF8040000F, &Tlb::default());
+        let text: String = render_range(&pieces);
+        assert_eq!(text.lines().collect::<Vec<&str>>(), [
+            "Range: 0x803FFFF0-0x8040000F, 0x20 bytes in 2 pieces",
+            "Virtual               Physical              Size Annotation   Area",
+            "0x803FFFF0-0x803FFFFF 0x003FFFF0-0x003FFFFF 0x10 0R.RDRM.RAMB Base RDRAM",
+            "0x80400000-0x8040000F 0x00400000-0x0040000F 0x10 0R.RDRM.RAMO Unpopulated RDRAM (open bus)",
+            "Warning: straddles Base RDRAM (RAMB) into Unpopulated RDRAM (open bus) (RAMO) at 0x80400000",
+            "Warning: is beyond installed RDRAM at 0x80400000",
+        ]);
+        assert_eq!(render_range(&[]), "");
+    }
+
+    #[test]
+    fn renders_register_bitfields() {
+        let (_, register) = find_register("VI_CONTROL").unwrap();
+        let text: String = render_decoded_register(register, 0x3303, false);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[..3], [
+            "Register: VI_CTRL           Video mode and output control",
+            "Value:    0x00003303        read",
+            "[1:0]     TYPE          0x3 Pixel type: 32-bit RGBA8888",
+        ]);
+    }
+
+    #[test]
+    fn renders_name_matches_with_their_ranges() {
+        let text: String = render_matches(&find_by_name("vi_orig"));
+        assert_eq!(text.lines().collect::<Vec<&str>>(), [
+            "Name      Description                 Kind     Physical              KSEG0                 KSEG1",
+            "VI_ORIGIN Framebuffer origin in RDRAM Register 0x04400004-0x04400007 0x84400004-0x84400007 0xA4400004-0xA4400007",
+        ]);
+        assert_eq!(format_range(None), "-");
+    }
+}
@@ -0,0 +1,385 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Model of the VR4300 TLB for the mapped segments
+//!
+//! Based on information the documentation here:
+//! https://n64brew.dev/wiki/VR4300
+//! VR4300 User's Manual, chapter 5 "Memory Management System"
+//!
+//! A TLB can be loaded from a dump, either as JSON or as a text file with one
+//! entry per line, or reconstructed from the `mtc0`, `tlbwi` and `tlbwr`
+//! instructions of a trace.
+//!
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Number of entries of the VR4300 TLB.
+pub const TLB_ENTRIES: usize = 32;
+
+/// One TLB entry, mapping a pair of consecutive pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TlbEntry {
+    pub page_mask: u32,
+    pub entry_hi: u64,
+    pub entry_lo0: u32,
+    pub entry_lo1: u32,
+}
+
+impl TlbEntry {
+    /// Size in bytes of each of the two pages of the entry.
+    pub fn page_size(&self) -> u64 {
+        (((self.page_mask as u64 >> 13) & 0xFFF) + 1) << 12
+    }
+
+    pub fn asid(&self) -> u8 {
+        self.entry_hi as u8
+    }
+
+    /// The entry matches any ASID if both halves are marked global.
+    pub fn global(&self) -> bool {
+        self.entry_lo0 & self.entry_lo1 & 1 != 0
+    }
+
+    /// True if the entry maps the virtual address for the given ASID.
+    pub fn matches(&self, address: u64, asid: u8) -> bool {
+        // Compare the region bits 63:62 and the VPN2 bits 39:13 not covered
+        // by the page mask
+        let vpn2_mask: u64 = 0xC000_00FF_FFFF_E000 & !((self.page_size() - 1) << 1);
+        (address & vpn2_mask) == (self.entry_hi & vpn2_mask)
+            && (self.global() || self.asid() == asid)
+    }
+}
+
+/// Result of translating a virtual address through the TLB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlbTranslation {
+    Hit {
+        /// Index of the matching entry
+        index: usize,
+        physical_address: u32,
+        page_size: u64,
+        asid: u8,
+        global: bool,
+        valid: bool,
+        dirty: bool,
+        /// Cache coherency attribute
+        cache_attribute: u8,
+    },
+    Miss,
+}
+
+/// The TLB entries along with the COP0 registers used to write them.
+#[derive(Debug, Clone)]
+pub struct Tlb {
+    pub entries: [Option<TlbEntry>; TLB_ENTRIES],
+    /// ASID of the running process, from EntryHi
+    pub asid: u8,
+    index: u32,
+    wired: u32,
+    random: u32,
+    staged: TlbEntry,
+}
+
+impl Default for Tlb {
+    fn default() -> Tlb {
+        Tlb {
+            entries: [None; TLB_ENTRIES],
+            asid: 0,
+            index: 0,
+            wired: 0,
+            random: TLB_ENTRIES as u32 - 1,
+            staged: TlbEntry::default(),
+        }
+    }
+}
+
+impl Tlb {
+    /// Translate a virtual address of a mapped segment.
+    pub fn translate(&self, address: u64) -> TlbTranslation {
+        let found = self.entries.iter()
+            .enumerate()
+            .find_map(|(index, entry)| entry.filter(|e| e.matches(address, self.asid)).map(|e| (index, e)));
+
+        let Some((index, entry)) = found else {
+            return TlbTranslation::Miss;
+        };
+
+        let page_size: u64 = entry.page_size();
+        let entry_lo: u32 = if address & page_size == 0 { entry.entry_lo0 } else { entry.entry_lo1 };
+        let pfn: u64 = (entry_lo as u64 >> 6) & 0xF_FFFF;
+
+        TlbTranslation::Hit {
+            index,
+            physical_address: ((pfn << 12) | (address & (page_size - 1))) as u32,
+            page_size,
+            asid: entry.asid(),
+            global: entry.global(),
+            valid: entry_lo & 0b10 != 0,
+            dirty: entry_lo & 0b100 != 0,
+            cache_attribute: ((entry_lo >> 3) & 0b111) as u8,
+        }
+    }
+
+    /// Apply an instruction of a trace to the TLB. Writes to the COP0
+    /// registers Index, EntryLo0, EntryLo1, PageMask, Wired and EntryHi are
+    /// tracked, and `tlbwi`/`tlbwr` store them into an entry. Operands are
+    /// expected in the Ares form, e.g. `t0{$0000001e},EntryHi`.
+    ///
+    /// The Random register decrements every cycle on hardware, which a trace
+    /// does not tell, so `tlbwr` cycles through the unwired entries instead.
+    pub fn apply_instruction(&mut self, mnemonic: &str, operands: &str) {
+        match mnemonic {
+            "mtc0" | "dmtc0" => {
+                let Some((value, register)) = parse_mtc0_operands(operands) else {
+                    return;
+                };
+                match register.as_str() {
+                    "index" | "$0" | "0" => self.index = value as u32 & 0x3F,
+                    "entrylo0" | "$2" | "2" => self.staged.entry_lo0 = value as u32,
+                    "entrylo1" | "$3" | "3" => self.staged.entry_lo1 = value as u32,
+                    "pagemask" | "$5" | "5" => self.staged.page_mask = value as u32,
+                    "wired" | "$6" | "6" => {
+                        self.wired = value as u32 & 0x3F;
+                        self.random = TLB_ENTRIES as u32 - 1;
+                    }
+                    "entryhi" | "$10" | "10" => {
+                        self.staged.entry_hi = value;
+                        self.asid = value as u8;
+                    }
+                    _ => {}
+                }
+            }
+            "tlbwi" => self.write_entry(self.index as usize),
+            "tlbwr" => {
+                self.write_entry(self.random as usize);
+                self.random = if self.random <= self.wired {
+                    TLB_ENTRIES as u32 - 1
+                } else {
+                    self.random - 1
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn write_entry(&mut self, index: usize) {
+        if let Some(entry) = self.entries.get_mut(index % TLB_ENTRIES) {
+            *entry = Some(self.staged);
+        }
+    }
+
+    /// Parse a TLB dump. JSON dumps are either an array of entries or an
+    /// object with `asid` and `entries`, where each entry has `page_mask`,
+    /// `entry_hi`, `entry_lo0`, `entry_lo1` and optionally `index`, given as
+    /// numbers or hexadecimal strings.
+    ///
+    /// Text dumps hold one entry per line as `index pagemask entryhi entrylo0
+    /// entrylo1` in hexadecimal, and optionally a line `asid <value>`. Text
+    /// following `#` is ignored.
+    ///
+    /// EntryHi values that fit in 32 bits are sign-extended, as they were
+    /// written in 32-bit mode.
+    pub fn parse(text: &str) -> io::Result<Tlb> {
+        match text.trim_start().chars().next() {
+            Some('[') | Some('{') => parse_json(text),
+            _ => parse_text(text),
+        }
+    }
+
+    /// Read a TLB dump from a file, see [`Tlb::parse`].
+    pub fn from_file<P: AsRef<Path>>(filename: P) -> io::Result<Tlb> {
+        Tlb::parse(&fs::read_to_string(filename)?)
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parses a hexadecimal number with or without a `0x` or `$` prefix.
+fn parse_hex(text: &str) -> Option<u64> {
+    let text: &str = text.trim();
+    let digits: &str = text.strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// Dumps taken in 32-bit mode hold 32-bit EntryHi values, which the CPU
+/// sign-extends.
+fn sign_extend_entry_hi(entry_hi: u64) -> u64 {
+    if entry_hi <= u32::MAX as u64 {
+        entry_hi as u32 as i32 as i64 as u64
+    } else {
+        entry_hi
+    }
+}
+
+/// Values shown with 8 digits come from 32-bit mode and are sign-extended.
+fn parse_register_value(digits: &str) -> Option<u64> {
+    let value: u64 = parse_hex(digits)?;
+    if digits.len() <= 8 {
+        Some(value as u32 as i32 as i64 as u64)
+    } else {
+        Some(value)
+    }
+}
+
+/// Splits `t0{$0000001e},EntryHi` into the value and lowercase register name.
+fn parse_mtc0_operands(operands: &str) -> Option<(u64, String)> {
+    let (source, register) = operands.rsplit_once(',')?;
+    let digits: &str = source.split_once("{$")?.1.strip_suffix('}')?;
+    Some((parse_register_value(digits)?, register.trim().to_lowercase()))
+}
+
+fn parse_text(text: &str) -> io::Result<Tlb> {
+    let mut tlb: Tlb = Tlb::default();
+
+    for (number, line) in text.lines().enumerate() {
+        let line: &str = line.split('#').next().unwrap_or("");
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [] => {}
+            ["asid", asid] => {
+                tlb.asid = parse_hex(asid)
+                    .ok_or_else(|| invalid_data(format!("line {}: invalid ASID", number + 1)))? as u8;
+            }
+            [index, page_mask, entry_hi, entry_lo0, entry_lo1] => {
+                let values: Vec<u64> = [index, page_mask, entry_hi, entry_lo0, entry_lo1].iter()
+                    .map(|field| parse_hex(field))
+                    .collect::<Option<Vec<u64>>>()
+                    .ok_or_else(|| invalid_data(format!("line {}: invalid number", number + 1)))?;
+                let slot = tlb.entries.get_mut(values[0] as usize)
+                    .ok_or_else(|| invalid_data(format!("line {}: index out of range", number + 1)))?;
+                *slot = Some(TlbEntry {
+                    page_mask: values[1] as u32,
+                    entry_hi: sign_extend_entry_hi(values[2]),
+                    entry_lo0: values[3] as u32,
+                    entry_lo1: values[4] as u32,
+                });
+            }
+            _ => return Err(invalid_data(format!(
+                "line {}: expected `index pagemask entryhi entrylo0 entrylo1`", number + 1
+            ))),
+        }
+    }
+
+    Ok(tlb)
+}
+
+/// Reads a JSON number or hexadecimal string.
+fn json_number(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(number) => number.as_u64(),
+        Value::String(text) => parse_hex(text),
+        _ => None,
+    }
+}
+
+fn parse_json(text: &str) -> io::Result<Tlb> {
+    let root: Value = serde_json::from_str(text).map_err(|e| invalid_data(e.to_string()))?;
+    let mut tlb: Tlb = Tlb::default();
+
+    let entries: &Vec<Value> = match &root {
+        Value::Array(entries) => entries,
+        Value::Object(object) => {
+            if let Some(asid) = object.get("asid") {
+                tlb.asid = json_number(asid)
+                    .ok_or_else(|| invalid_data("invalid ASID".to_string()))? as u8;
+            }
+            object.get("entries")
+                .and_then(Value::as_array)
+                .ok_or_else(|| invalid_data("expected an `entries` array".to_string()))?
+        }
+        _ => return Err(invalid_data("expected an array or object".to_string())),
+    };
+
+    for (position, entry) in entries.iter().enumerate() {
+        let field = |name: &str| -> io::Result<u64> {
+            entry.get(name)
+                .and_then(json_number)
+                .ok_or_else(|| invalid_data(format!("entry {}: missing or invalid `{}`", position, name)))
+        };
+        let index: u64 = entry.get("index").map_or(Some(position as u64), json_number)
+            .ok_or_else(|| invalid_data(format!("entry {}: invalid `index`", position)))?;
+        let slot = tlb.entries.get_mut(index as usize)
+            .ok_or_else(|| invalid_data(format!("entry {}: index out of range", position)))?;
+        *slot = Some(TlbEntry {
+            page_mask: field("page_mask")? as u32,
+            entry_hi: sign_extend_entry_hi(field("entry_hi")?),
+            entry_lo0: field("entry_lo0")? as u32,
+            entry_lo1: field("entry_lo1")? as u32,
+        });
+    }
+
+    Ok(tlb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn physical_address(translation: TlbTranslation) -> Option<u32> {
+        match translation {
+            TlbTranslation::Hit { physical_address, .. } => Some(physical_address),
+            TlbTranslation::Miss => None,
+        }
+    }
+
+    #[test]
+    fn translates_both_pages_of_an_entry() {
+        let tlb: Tlb = Tlb::parse("# index pagemask entryhi entrylo0 entrylo1\n3 0 00400000 4007 4047\n").unwrap();
+        assert_eq!(physical_address(tlb.translate(0x00400123)), Some(0x00100123));
+        assert_eq!(physical_address(tlb.translate(0x00401123)), Some(0x00101123));
+        assert_eq!(tlb.translate(0x00402000), TlbTranslation::Miss);
+
+        let TlbTranslation::Hit { index, valid, dirty, global, .. } = tlb.translate(0x00400000) else {
+            panic!("expected a hit");
+        };
+        assert_eq!((index, valid, dirty, global), (3, true, true, true));
+    }
+
+    #[test]
+    fn entries_not_global_only_match_their_asid() {
+        let json: &str = r#"{"asid": 2, "entries": [
+            {"page_mask": 0, "entry_hi": "0xC0000001", "entry_lo0": "0x4006", "entry_lo1": 0}
+        ]}"#;
+        let mut tlb: Tlb = Tlb::parse(json).unwrap();
+        assert_eq!(tlb.entries[0].unwrap().entry_hi, 0xFFFFFFFFC0000001);
+        assert_eq!(tlb.translate(0xFFFFFFFFC0000010), TlbTranslation::Miss);
+        tlb.asid = 1;
+        assert_eq!(physical_address(tlb.translate(0xFFFFFFFFC0000010)), Some(0x00100010));
+    }
+
+    #[test]
+    fn reconstructs_entries_from_trace_instructions() {
+        let mut tlb: Tlb = Tlb::default();
+        tlb.apply_instruction("mtc0", "t0{$00000005},Index");
+        tlb.apply_instruction("mtc0", "t1{$00006000},PageMask");
+        tlb.apply_instruction("mtc0", "t2{$00000000},EntryHi");
+        tlb.apply_instruction("mtc0", "t3{$00008007},EntryLo0");
+        tlb.apply_instruction("mtc0", "t4{$00008807},EntryLo1");
+        tlb.apply_instruction("tlbwi", "");
+
+        let entry: TlbEntry = tlb.entries[5].unwrap();
+        assert_eq!(entry.page_size(), 0x4000);
+        assert_eq!(physical_address(tlb.translate(0x00001234)), Some(0x00201234));
+        assert_eq!(physical_address(tlb.translate(0x00005234)), Some(0x00221234));
+
+        tlb.apply_instruction("tlbwr", "");
+        assert!(tlb.entries[TLB_ENTRIES - 1].is_some());
+    }
+
+    #[test]
+    fn reports_the_line_of_invalid_dumps() {
+        let error: io::Error = Tlb::parse("asid 1\n0 0 00400000 4007\n").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().starts_with("line 2:"));
+        assert!(Tlb::parse("40 0 0 0 0").is_err());
+    }
+}
@@ -0,0 +1,637 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Annotation of instruction traces
+//!
+//! Traces of Ares are understood best, along with those of the other
+//! emulators and debuggers of [`crate::parsers`].
+//!
+//! Both the CPU lines, e.g. `CPU  ffffffffa40005fc  sw ...`, and the RSP
+//! lines with their 12-bit IMEM program counter, e.g. `RSP  0a4  lqv ...`,
+//! are annotated. RSP addresses are looked up at the KSEG1 address reaching
+//! them, see [`crate::rsp`].
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::map::{
+    address_location_to_string,
+    format_virtual_address,
+    is_32bit_compatible,
+    sign_extend,
+    AddressLocation,
+};
+use crate::mapfile::MemoryMap;
+use crate::output::{location_to_csv, location_to_json, OutputFormat, RecordWriter};
+use crate::parsers::{TraceFormat, DETECT_LINES};
+use crate::registers::{decode_register, decoded_register_to_string, find_register, Register};
+use crate::rom::Rom;
+use crate::rsp::{rsp_to_virtual, RSP_IMEM};
+use crate::source::SourceLines;
+use crate::symbols::SymbolTable;
+use crate::tlb::Tlb;
+
+/// Mnemonics of the CPU loads and stores.
+static LOADS_AND_STORES: &[&str] = &[
+    "lb", "lbu", "lh", "lhu", "lw", "lwu", "lwl", "lwr", "ld", "ldl", "ldr", "ll", "lld",
+    "sb", "sh", "sw", "swl", "swr", "sd", "sdl", "sdr", "sc", "scd",
+    "lwc1", "ldc1", "swc1", "sdc1",
+];
+
+/// Mnemonics of the RSP loads and stores, scalar and vector.
+static RSP_LOADS_AND_STORES: &[&str] = &[
+    "lb", "lbu", "lh", "lhu", "lw", "lwu", "sb", "sh", "sw",
+    "lbv", "lsv", "llv", "ldv", "lqv", "lrv", "lpv", "luv", "lhv", "lfv", "lwv", "ltv",
+    "sbv", "ssv", "slv", "sdv", "sqv", "srv", "spv", "suv", "shv", "sfv", "swv", "stv",
+];
+
+/// Settings of the trace annotation.
+#[derive(Debug, Clone, Default)]
+pub struct TraceOptions {
+    /// Memory map the addresses are looked up in, the built-in one by default
+    pub map: MemoryMap,
+    /// TLB used to translate addresses of mapped segments. Entries written by
+    /// the traced instructions are applied to it as the trace goes.
+    pub tlb: Tlb,
+    /// Symbols shown as function+offset next to the annotation
+    pub symbols: SymbolTable,
+    /// Microcode symbols used instead on RSP lines, at the KSEG1 address of
+    /// their RSP address, see [`SymbolTable::load_rsp`]
+    pub rsp_symbols: SymbolTable,
+    /// Debug information used to add a source location column
+    pub source: Option<Rc<SourceLines>>,
+    /// Cartridge ROM whose bytes are shown at its addresses
+    pub rom: Option<Rc<Rom>>,
+    /// How the annotated lines are written
+    pub format: OutputFormat,
+    /// Shape of the instruction lines, detected from the first lines of the
+    /// trace when not given
+    pub trace_format: Option<TraceFormat>,
+    /// User-defined instruction line patterns, tried in order instead of the
+    /// trace format when given, see [`crate::parsers::trace_pattern`]
+    pub trace_patterns: Vec<Regex>,
+}
+
+/// Effective address of a load or store.
+struct Access {
+    address: u64,
+    location: AddressLocation,
+}
+
+/// An instruction line of a trace, e.g.
+/// `CPU  ffffffffa40005fc  sw      t3{$00003303},at+$0{$a4400000}`.
+struct InstructionLine<'a> {
+    prefix: &'a str,
+    /// True for the lines of the RSP, whose addresses are converted to the
+    /// KSEG1 addresses of IMEM and DMEM
+    rsp: bool,
+    address: u64,
+    /// The instruction as printed, mnemonic and operands
+    instruction: &'a str,
+    mnemonic: &'a str,
+    operands: &'a str,
+    /// Effective address of loads and stores
+    effective: Option<u64>,
+}
+
+/// A register access logged by Ares, e.g. `VI I/O: VI_CONTROL <= 00003303`.
+struct RegisterLine {
+    register: &'static Register,
+    value: u32,
+    write: bool,
+}
+
+/// The patterns recognizing the lines of a trace.
+struct TracePatterns {
+    /// True for Ares traces, which also log the RSP
+    ares: bool,
+    instruction: Vec<Regex>,
+    operand: Regex,
+    rsp_instruction: Regex,
+    rsp_operand: Regex,
+    io: Regex,
+}
+
+impl TracePatterns {
+    /// The patterns of a trace format, or the user-defined instruction
+    /// patterns instead when any are given.
+    fn new(format: TraceFormat, custom: &[Regex]) -> TracePatterns {
+        TracePatterns {
+            ares: custom.is_empty() && format == TraceFormat::Ares,
+            instruction: if custom.is_empty() { vec![format.regex()] } else { custom.to_vec() },
+            operand: Regex::new(r"\w+[+-]\$[0-9a-fA-F]+\{\$([0-9a-fA-F]{8}|[0-9a-fA-F]{16})\}").unwrap(),
+            rsp_instruction: Regex::new(r"^(RSP)\s*([a-f0-9]{3,4})\s+(.*)$").unwrap(),
+            rsp_operand: Regex::new(r"\w+[+-]\$[0-9a-fA-F]+\{\$([0-9a-fA-F]{1,4})\}").unwrap(),
+            io: Regex::new(r"^[A-Z]{2,3} I/O: ([A-Z0-9_]+) (<=|=>) ([0-9a-fA-F]{8})\b").unwrap(),
+        }
+    }
+
+    fn instruction<'a>(&self, line: &'a str) -> Option<InstructionLine<'a>> {
+        let Some(caps) = self.instruction.iter().find_map(|regex| regex.captures(line)) else {
+            return self.ares.then(|| self.rsp_instruction(line)).flatten();
+        };
+        let prefix: &str = caps.name("prefix").map_or("CPU", |m| m.as_str());
+        let address: u64 = parse_trace_address(caps.name("pc").map_or("", |m| m.as_str()))?;
+        let instruction: &str = caps.name("rest").map_or("", |m| m.as_str()).trim_end();
+
+        let mut parts = instruction.splitn(2, char::is_whitespace);
+        let mnemonic: &str = parts.next().unwrap_or("");
+        let operands: &str = parts.next().unwrap_or("").trim();
+
+        let mut effective: Option<u64> = caps.name("addr").and_then(|m| parse_trace_address(m.as_str()));
+        if effective.is_none() && LOADS_AND_STORES.contains(&mnemonic.to_lowercase().as_str()) {
+            if let Some(operand) = self.operand.captures(operands) {
+                effective = parse_trace_address(operand.get(1).map_or("", |m| m.as_str()));
+            }
+        }
+
+        Some(InstructionLine {
+            prefix,
+            rsp: false,
+            address,
+            instruction,
+            mnemonic,
+            operands,
+            effective,
+        })
+    }
+
+    /// An RSP line, its program counter being an offset in IMEM and the
+    /// effective addresses of its loads and stores offsets in DMEM.
+    fn rsp_instruction<'a>(&self, line: &'a str) -> Option<InstructionLine<'a>> {
+        let caps = self.rsp_instruction.captures(line)?;
+        let prefix: &str = caps.get(1).map_or("", |m| m.as_str());
+        let pc: u32 = u32::from_str_radix(caps.get(2).map_or("", |m| m.as_str()), 16).unwrap();
+        let instruction: &str = caps.get(3).map_or("", |m| m.as_str());
+
+        let mut parts = instruction.splitn(2, char::is_whitespace);
+        let mnemonic: &str = parts.next().unwrap_or("");
+        let operands: &str = parts.next().unwrap_or("").trim();
+
+        let effective: Option<u64> = RSP_LOADS_AND_STORES.contains(&mnemonic)
+            .then(|| self.rsp_operand.captures(operands))
+            .flatten()
+            .map(|operand| {
+                let address: u32 = u32::from_str_radix(operand.get(1).map_or("", |m| m.as_str()), 16).unwrap();
+                rsp_to_virtual(address & 0xFFF)
+            });
+
+        Some(InstructionLine {
+            prefix,
+            rsp: true,
+            address: rsp_to_virtual(RSP_IMEM | (pc & 0xFFF)),
+            instruction,
+            mnemonic,
+            operands,
+            effective,
+        })
+    }
+
+    fn register(&self, line: &str) -> Option<RegisterLine> {
+        let caps = self.io.captures(line)?;
+        let (_, register) = find_register(&caps[1])?;
+        Some(RegisterLine {
+            register,
+            value: u32::from_str_radix(&caps[3], 16).unwrap(),
+            write: &caps[2] == "<=",
+        })
+    }
+}
+
+/// Parses a hexadecimal address of a trace, sign-extending addresses of up to
+/// 8 digits as in 32-bit mode.
+fn parse_trace_address(digits: &str) -> Option<u64> {
+    let digits: &str = digits.trim_start_matches("0x");
+    let address: u64 = u64::from_str_radix(digits, 16).ok()?;
+    Some(if digits.len() <= 8 { sign_extend(address as u32) } else { address })
+}
+
+/// The lines of a trace and the patterns recognizing them, those of the
+/// user-defined patterns, the trace format given or the one detected from the
+/// first [`DETECT_LINES`] lines.
+fn trace_lines<R: BufRead>(
+    reader: R,
+    options: &TraceOptions,
+) -> io::Result<(TracePatterns, impl Iterator<Item = io::Result<String>>)> {
+    let mut lines = reader.lines();
+    let mut first: Vec<String> = Vec::new();
+    if options.trace_format.is_none() && options.trace_patterns.is_empty() {
+        for line in lines.by_ref().take(DETECT_LINES) {
+            first.push(line?);
+        }
+    }
+    let format: TraceFormat = options.trace_format.unwrap_or_else(|| TraceFormat::detect(&first));
+    let patterns: TracePatterns = TracePatterns::new(format, &options.trace_patterns);
+    Ok((patterns, first.into_iter().map(Ok).chain(lines)))
+}
+
+/// What is wrong with an instruction line: running from or accessing RDRAM
+/// beyond the installed memory.
+fn instruction_warnings(location: &AddressLocation, access: Option<&Access>) -> Vec<&'static str> {
+    let mut warnings: Vec<&'static str> = Vec::new();
+    if location.is_unpopulated() {
+        warnings.push("executes beyond installed RDRAM");
+    }
+    if access.is_some_and(|access| access.location.is_unpopulated()) {
+        warnings.push("accesses beyond installed RDRAM");
+    }
+    warnings
+}
+
+/// Column names of the CSV output of [`rewrite_lines`]: the line number, the
+/// trace prefix, the location columns of the instruction address (see
+/// [`crate::output::LOCATION_CSV_HEADER`]), the instruction, and the
+/// effective address of loads and stores with its annotation, register and
+/// symbol, and the warnings about the line joined with `; `.
+pub const TRACE_CSV_HEADER: &[&str] = &[
+    "line",
+    "prefix",
+    "annotation",
+    "virtual",
+    "physical",
+    "segment",
+    "region",
+    "subregions",
+    "register",
+    "symbol",
+    "source",
+    "canonical",
+    "rom_offset",
+    "rsp",
+    "instruction",
+    "effective_virtual",
+    "effective_annotation",
+    "effective_register",
+    "effective_symbol",
+    "warnings",
+];
+
+/// Read lines from `reader` and annotate the instruction lines among them,
+/// recognized by the user-defined patterns of the options (see
+/// [`crate::parsers::trace_pattern`]), tried in order, when any are given.
+/// Otherwise they are recognized by the pattern of the trace format of the
+/// options, or else of the format detected from the first [`DETECT_LINES`]
+/// lines, see [`crate::parsers`]. Lines that don't match are written to
+/// `writer` as they are. Matching lines are rewritten with the prefix, the
+/// annotation and the instruction address, converted to an integer (u64),
+/// followed by the instruction. Addresses of 32-bit compatibility segments
+/// are shortened to their lower 32 bits.
+///
+/// RSP lines of Ares traces, with a 12-bit program counter instead, are
+/// annotated at the KSEG1 address of IMEM, and the DMEM offsets of their
+/// loads and stores at the KSEG1 address of DMEM. Microcode symbols apply to
+/// them instead of the symbols of the CPU.
+///
+/// The effective address Ares prints in the memory operand of loads and
+/// stores, e.g. `at+$0{$a4400000}`, or the `addr` capture of the pattern, is
+/// annotated at the end of the line with the register it accesses, if any,
+/// and its short-form description.
+///
+/// When symbols are given, a column with the function+offset of the address
+/// follows the address, and effective addresses are followed by their
+/// symbol+offset too. When debug information is given, the source location
+/// of the address is added at the end of the line after an `@`.
+///
+/// Instructions running from or accessing RDRAM beyond the installed memory,
+/// when its size is known, are flagged in brackets at the end of the line.
+///
+/// Register accesses logged by Ares, e.g. `VI I/O: VI_CONTROL <= 00003303`,
+/// are followed by the bitfields of the value read or written.
+///
+/// With the JSON formats, instruction lines become objects of `kind`
+/// `instruction` holding the `line` number, the `prefix`, the `location` of
+/// the address (see [`location_to_json`]), the `instruction` and the
+/// `effective` location of loads and stores, along with the `warnings` about
+/// the line. Register accesses become objects of `kind` `register` holding
+/// the `line` number, the `register` name, the `direction` (`read` or
+/// `write`), the `value` and its decoded `fields`. Other lines are left out.
+/// The CSV format holds the instruction lines only, see [`TRACE_CSV_HEADER`].
+pub fn rewrite_lines<R: BufRead, W: Write>(reader: R, writer: W, options: &TraceOptions) -> io::Result<()> {
+    let mut tlb: Tlb = options.tlb.clone();
+    let source: Option<&SourceLines> = options.source.as_deref();
+    let rom: Option<&Rom> = options.rom.as_deref();
+    let (patterns, lines) = trace_lines(reader, options)?;
+
+    let mut records: RecordWriter<W> = RecordWriter::new(writer, options.format, TRACE_CSV_HEADER)?;
+
+    for (number, line) in lines.enumerate() {
+        let line: String = line?;
+        let number: usize = number + 1;
+        match patterns.instruction(&line) {
+            Some(parsed) => {
+
+                let location: AddressLocation = options.map.lookup(parsed.address, &tlb);
+                let access: Option<Access> = parsed.effective
+                    .map(|address| Access { address, location: options.map.lookup(address, &tlb) });
+
+                // The RSP has neither a TLB nor DWARF information of its own
+                let (symbols, source): (&SymbolTable, Option<&SourceLines>) = if parsed.rsp {
+                    (&options.rsp_symbols, None)
+                } else {
+                    tlb.apply_instruction(parsed.mnemonic, parsed.operands);
+                    (&options.symbols, source)
+                };
+                let warnings: Vec<&str> = instruction_warnings(&location, access.as_ref());
+
+                match options.format {
+                    OutputFormat::Table => {
+                        let mut effective: String = String::new();
+                        if let Some(access) = &access {
+                            let annotation: String = address_location_to_string(&access.location);
+                            effective = match access.location.register {
+                                Some(register) => format!(" -> {} ({})", register.name, annotation),
+                                None => format!(" -> {}", annotation),
+                            };
+                            if let Some(symbol) = symbols.describe(access.address) {
+                                effective = format!("{} {}", effective, symbol);
+                            }
+                        }
+
+                        let mut address: String = if is_32bit_compatible(parsed.address) {
+                            format!("{:#010x}", parsed.address as u32)
+                        } else {
+                            format!("{:#018x}", parsed.address)
+                        };
+                        if !options.symbols.is_empty() || !options.rsp_symbols.is_empty() {
+                            let symbol: String = symbols.describe(parsed.address).unwrap_or_default();
+                            address = format!("{} {:<32}", address, symbol);
+                        }
+
+                        let source: String = source
+                            .and_then(|source| source.describe(parsed.address))
+                            .map_or(String::new(), |location| format!(" @ {}", location));
+                        let warnings: String = warnings.iter().map(|warning| format!(" [{}]", warning)).collect();
+
+                        writeln!(
+                            records.writer,
+                            "{} {:<12} {} {}{}{}{}",
+                            parsed.prefix,
+                            address_location_to_string(&location),
+                            address,
+                            parsed.instruction,
+                            effective,
+                            source,
+                            warnings
+                        )?;
+                    }
+                    OutputFormat::Json | OutputFormat::JsonLines => {
+                        records.json(&json!({
+                            "kind": "instruction",
+                            "line": number,
+                            "prefix": parsed.prefix,
+                            "location": location_to_json(&location, symbols, source, rom),
+                            "instruction": parsed.instruction,
+                            "effective": access.as_ref()
+                                .map(|access| location_to_json(&access.location, symbols, None, rom)),
+                            "warnings": warnings,
+                        }))?;
+                    }
+                    OutputFormat::Csv => {
+                        let mut fields: Vec<String> = vec![number.to_string(), parsed.prefix.to_string()];
+                        fields.extend(location_to_csv(&location, symbols, source));
+                        fields.push(parsed.instruction.to_string());
+                        match &access {
+                            Some(access) => fields.extend([
+                                format_virtual_address(access.address),
+                                address_location_to_string(&access.location),
+                                access.location.register.map_or(String::new(), |register| register.name.to_string()),
+                                symbols.describe(access.address).unwrap_or_default(),
+                            ]),
+                            None => fields.extend(std::iter::repeat(String::new()).take(4)),
+                        }
+                        fields.push(warnings.join("; "));
+                        records.csv(&fields)?;
+                    }
+                }
+            }
+            None => {
+                let access: Option<RegisterLine> = patterns.register(&line);
+                let decoded = access.as_ref()
+                    .map(|access| decode_register(access.register, access.value, access.write))
+                    .unwrap_or_default();
+
+                match options.format {
+                    OutputFormat::Table => {
+                        let decoded: String = decoded_register_to_string(&decoded);
+                        if decoded.is_empty() {
+                            writeln!(records.writer, "{}", line)?;
+                        } else {
+                            writeln!(records.writer, "{}  [{}]", line, decoded)?;
+                        }
+                    }
+                    OutputFormat::Json | OutputFormat::JsonLines => {
+                        if let Some(access) = &access {
+                            let fields: Vec<Value> = decoded.iter()
+                                .map(|(field, field_value)| json!({
+                                    "name": field.name,
+                                    "value": field_value,
+                                    "meaning": field.value_name(*field_value),
+                                }))
+                                .collect();
+                            records.json(&json!({
+                                "kind": "register",
+                                "line": number,
+                                "register": access.register.name,
+                                "direction": if access.write { "write" } else { "read" },
+                                "value": format!("0x{:08X}", access.value),
+                                "fields": fields,
+                            }))?;
+                        }
+                    }
+                    OutputFormat::Csv => {}
+                }
+            }
+        }
+    }
+
+    records.finish()
+}
+
+/// Counts of what a trace executed and accessed, keyed by the short-form
+/// annotation of the addresses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceStats {
+    pub lines: usize,
+    pub instructions: usize,
+    /// Instructions executed per annotation of their address
+    pub executed: BTreeMap<String, usize>,
+    /// Loads per annotation of their effective address
+    pub loads: BTreeMap<String, usize>,
+    /// Stores per annotation of their effective address
+    pub stores: BTreeMap<String, usize>,
+    /// Reads and writes per register, from the loads and stores to register
+    /// addresses. The register accesses logged by Ares repeat those, so they
+    /// are not counted again.
+    pub registers: BTreeMap<&'static str, (usize, usize)>,
+}
+
+/// Gather the statistics of a trace, translating mapped addresses through
+/// the TLB of the options as [`rewrite_lines`] does.
+pub fn trace_stats<R: BufRead>(reader: R, options: &TraceOptions) -> io::Result<TraceStats> {
+    let mut tlb: Tlb = options.tlb.clone();
+    let (patterns, lines) = trace_lines(reader, options)?;
+    let mut stats: TraceStats = TraceStats::default();
+
+    for line in lines {
+        let line: String = line?;
+        stats.lines += 1;
+
+        if let Some(parsed) = patterns.instruction(&line) {
+            stats.instructions += 1;
+            let location: AddressLocation = options.map.lookup(parsed.address, &tlb);
+            *stats.executed.entry(address_location_to_string(&location)).or_default() += 1;
+
+            if let Some(address) = parsed.effective {
+                let location: AddressLocation = options.map.lookup(address, &tlb);
+                let store: bool = parsed.mnemonic.to_lowercase().starts_with('s');
+                let counts = if store { &mut stats.stores } else { &mut stats.loads };
+                *counts.entry(address_location_to_string(&location)).or_default() += 1;
+                if let Some(register) = location.register {
+                    let (reads, writes) = stats.registers.entry(register.name).or_default();
+                    if store { *writes += 1 } else { *reads += 1 }
+                }
+            }
+
+            if !parsed.rsp {
+                tlb.apply_instruction(parsed.mnemonic, parsed.operands);
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Rewrite the lines of the named file to stdout, see [`rewrite_lines`].
+pub fn rewrite_lines_of_file<P: AsRef<Path>>(filename: P, options: &TraceOptions) -> io::Result<()> {
+    let file: File = File::open(filename)?;
+    let reader: io::BufReader<File> = io::BufReader::new(file);
+    let stdout = io::stdout();
+    rewrite_lines(reader, stdout.lock(), options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::trace_pattern;
+    use crate::symbols::Symbol;
+
+    fn rewrite(trace: &str, options: &TraceOptions) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        rewrite_lines(trace.as_bytes(), &mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn annotates_instruction_addresses() {
+        let output: String = rewrite("CPU  ffffffffa40005f4  lui     t3,$0000\n", &TraceOptions::default());
+        assert_eq!(output, "CPU 1G.RSPD      0xa40005f4 lui     t3,$0000\n");
+    }
+
+    #[test]
+    fn passes_other_lines_through() {
+        let output: String = rewrite("Booting\n\nCPU done\n", &TraceOptions::default());
+        assert_eq!(output, "Booting\n\nCPU done\n");
+    }
+
+    #[test]
+    fn annotates_effective_addresses_of_loads_and_stores() {
+        let trace: &str = "CPU  ffffffffa40005f0  sw      t0{$f0f0f000},v0+$3dd0{$a003e300}\n\
+                           CPU  ffffffffa40005fc  sw      t3{$00003303},at+$0{$a4400000}\n";
+        let output: String = rewrite(trace, &TraceOptions::default());
+        let lines: Vec<&str> = output.lines().collect();
+        assert!(lines[0].ends_with(" -> 1R.RDRM"));
+        assert!(lines[1].ends_with(" -> VI_CTRL (1G.InVI)"));
+    }
+
+    #[test]
+    fn annotates_instruction_and_effective_addresses_alike() {
+        let trace: &str = "CPU  ffffffffa4400000  sw      t3{$00003303},at+$4{$a4400004}\n";
+        let output: String = rewrite(trace, &TraceOptions::default());
+        assert_eq!(output, "CPU 1G.InVI      0xa4400000 sw      t3{$00003303},at+$4{$a4400004} -> VI_ORIGIN (1G.InVI)\n");
+
+        let stats: TraceStats = trace_stats(trace.as_bytes(), &TraceOptions::default()).unwrap();
+        assert_eq!(stats.executed.get("1G.InVI"), Some(&1));
+        assert_eq!(stats.stores.get("1G.InVI"), Some(&1));
+    }
+
+    #[test]
+    fn writes_json_lines_records() {
+        let options: TraceOptions = TraceOptions { format: OutputFormat::JsonLines, ..TraceOptions::default() };
+        let output: String = rewrite("CPU  ffffffffa40005fc  sw      t3{$00003303},at+$0{$a4400000}\n", &options);
+        let record: serde_json::Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(record["line"], 1);
+        assert_eq!(record["kind"], "instruction");
+        assert_eq!(record["location"]["annotation"], "1G.RSPD");
+        assert_eq!(record["effective"]["register"]["name"], "VI_CTRL");
+    }
+
+    #[test]
+    fn counts_executed_instructions_and_register_accesses() {
+        let trace: &str = "CPU  ffffffffa40005f4  lui     t3,$0000\n\
+                           CPU  ffffffffa40005fc  sw      t3{$00003303},at+$0{$a4400000}\n\
+                           VI I/O: VI_CONTROL <= 00003303\n\
+                           CPU  ffffffffa4000600  lw      t6{$a0002000},at+$4{$a4400004}\n";
+        let stats: TraceStats = trace_stats(trace.as_bytes(), &TraceOptions::default()).unwrap();
+        assert_eq!(stats.lines, 4);
+        assert_eq!(stats.instructions, 3);
+        assert_eq!(stats.executed.get("1G.RSPD"), Some(&3));
+        assert_eq!(stats.stores.get("1G.InVI"), Some(&1));
+        assert_eq!(stats.loads.get("1G.InVI"), Some(&1));
+        assert_eq!(stats.registers.get("VI_CTRL"), Some(&(0, 1)));
+        assert_eq!(stats.registers.get("VI_ORIGIN"), Some(&(1, 0)));
+    }
+
+    #[test]
+    fn annotates_rsp_lines_with_microcode_symbols() {
+        let trace: &str = "RSP  000  lqv     v01[e0],zero+$10{$010}\n\
+                           RSP  004  lw      t0,zero+$20{$020}\n";
+        let output: String = rewrite(trace, &TraceOptions::default());
+        assert_eq!(output.lines().next(), Some("RSP 1G.RSPI      0xa4001000 lqv     v01[e0],zero+$10{$010} -> 1G.RSPD"));
+
+        let mut rsp_symbols: SymbolTable = SymbolTable::default();
+        rsp_symbols.extend([
+            Symbol { address: rsp_to_virtual(0x1000), size: None, name: "ucode_start".to_string() },
+            Symbol { address: rsp_to_virtual(0x010), size: Some(0x10), name: "dmem_matrix".to_string() },
+        ]);
+        let options: TraceOptions = TraceOptions { rsp_symbols, ..TraceOptions::default() };
+        let lines: Vec<String> = rewrite(trace, &options).lines().map(str::to_string).collect();
+        assert!(lines[0].contains(" ucode_start "));
+        assert!(lines[0].ends_with(" -> 1G.RSPD dmem_matrix"));
+        assert!(lines[1].contains(" ucode_start+0x4 "));
+        assert!(lines[1].ends_with(" -> 1G.RSPD"));
+    }
+
+    #[test]
+    fn reads_the_detected_format() {
+        let trace: &str = "80246000 [3C1D8020] lui $sp, 0x8020\n\
+                           A4400004 [8D090014] lw $t1, 0x14($t0)\n";
+        let expected: &str = "CPU 0R.RDRM      0x80246000 lui $sp, 0x8020\n\
+                              CPU 1G.InVI      0xa4400004 lw $t1, 0x14($t0)\n";
+        assert_eq!(rewrite(trace, &TraceOptions::default()), expected);
+
+        let options: TraceOptions = TraceOptions { trace_format: Some(TraceFormat::Gdb), ..TraceOptions::default() };
+        assert_eq!(rewrite("=> 0x1040 <boot>:\tnop\n", &options), "CPU U.TLBMISS    0x00001040 nop\n");
+    }
+
+    #[test]
+    fn reads_lines_of_user_defined_patterns_in_order() {
+        let trace_patterns: Vec<Regex> = vec![
+            trace_pattern(r"^\[(?P<prefix>[a-z]{3})\] PC=(?P<pc>[0-9A-F]{8}) (?P<rest>.*?)(?: @(?P<addr>[0-9A-F]{8}))?$")
+                .unwrap(),
+            trace_pattern(r"^(?P<pc>[0-9A-F]{8}): (?P<rest>.*)$").unwrap(),
+        ];
+        let options: TraceOptions = TraceOptions { trace_patterns, ..TraceOptions::default() };
+        let trace: &str = "[cpu] PC=80246008 sw t1,0x14(t0) @A4400014\n\
+                           80246010: nop\n\
+                           other line\n";
+        let expected: &str = "cpu 0R.RDRM      0x80246008 sw t1,0x14(t0) -> VI_BURST (1G.InVI)\n\
+                              CPU 0R.RDRM      0x80246010 nop\n\
+                              other line\n";
+        assert_eq!(rewrite(trace, &options), expected);
+    }
+}
@@ -0,0 +1,17 @@
+/*
+ * Source of vi.elf, the DWARF fixture of the source line tests, built with
+ *
+ * gcc -m32 -g -O2 -nostdlib -static -no-pie -fno-pic -fno-asynchronous-unwind-tables \
+ *     -fdebug-prefix-map=$PWD=. -Wl,--build-id=none -Wl,-Ttext=0x80000400 -Wl,-e,vi_init \
+ *     -o vi.elf vi.c
+ */
+
+static inline __attribute__((always_inline)) void vi_write(volatile unsigned *reg, unsigned value)
+{
+    *reg = value;
+}
+
+void vi_init(void)
+{
+    vi_write((volatile unsigned *)0xA4400000, 0x3303);
+}